@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS "log_gaps"
+(
+    "id"              BIGSERIAL   PRIMARY KEY,
+    "datetime"        TIMESTAMPTZ NOT NULL,
+    "kind"            TEXT        NOT NULL,
+    "after_datetime"  TIMESTAMPTZ NOT NULL,
+    "before_datetime" TIMESTAMPTZ NULL,
+    UNIQUE("kind", "after_datetime")
+);
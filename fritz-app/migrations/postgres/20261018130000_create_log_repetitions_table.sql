@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS "log_repetitions"
+(
+    "id"               BIGSERIAL   PRIMARY KEY,
+    "log_id"           BIGINT      NOT NULL REFERENCES "logs" ("id") ON DELETE CASCADE,
+    "datetime"         TIMESTAMPTZ NOT NULL,
+    "repetition_count" BIGINT      NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS "log_repetitions_log_id_idx" ON "log_repetitions" ("log_id");
+
+-- the current state is all we know about existing logs
+INSERT INTO "log_repetitions" ("log_id", "datetime", "repetition_count")
+SELECT "id", "datetime", "repetition_count"
+FROM "logs"
+WHERE "repetition_count" IS NOT NULL;
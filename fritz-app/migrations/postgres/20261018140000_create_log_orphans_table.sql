@@ -0,0 +1,13 @@
+-- Add migration script here
+
+-- logs inserted by a resync are older than the logs appended before them,
+-- so they must not be used to find the most recently appended log
+ALTER TABLE "logs" ADD COLUMN IF NOT EXISTS "resynced" BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS "log_orphans"
+(
+    "id"       BIGSERIAL   PRIMARY KEY,
+    "log_id"   BIGINT      NOT NULL REFERENCES "logs" ("id") ON DELETE CASCADE,
+    "datetime" TIMESTAMPTZ NOT NULL,
+    UNIQUE("log_id")
+);
@@ -0,0 +1,12 @@
+-- Add migration script here
+
+-- every time the logs on the FRITZ!Box have been cleared after the most
+-- recently appended log ("log_id") and everything before it has been archived
+CREATE TABLE IF NOT EXISTS "log_clears"
+(
+    "id"            BIGSERIAL   PRIMARY KEY,
+    "log_id"        BIGINT      NOT NULL REFERENCES "logs" ("id") ON DELETE CASCADE,
+    "datetime"      TIMESTAMPTZ NOT NULL,
+    "archived_logs" BIGINT      NOT NULL,
+    UNIQUE("log_id")
+);
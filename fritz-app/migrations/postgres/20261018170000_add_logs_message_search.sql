@@ -0,0 +1,8 @@
+-- Add migration script here
+
+-- full-text search over the messages, which are german prose
+ALTER TABLE "logs"
+    ADD COLUMN IF NOT EXISTS "message_search" TSVECTOR
+        GENERATED ALWAYS AS (to_tsvector('german', "message")) STORED;
+
+CREATE INDEX IF NOT EXISTS "logs_message_search_idx" ON "logs" USING GIN ("message_search");
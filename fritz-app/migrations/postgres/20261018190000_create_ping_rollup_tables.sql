@@ -0,0 +1,43 @@
+-- Add migration script here
+
+-- pings of a target rolled up into buckets starting at "datetime", see
+-- `db::PingRollup`
+CREATE TABLE IF NOT EXISTS "ping_1m"
+(
+    "id"         BIGSERIAL        PRIMARY KEY,
+    "target"     TEXT             NOT NULL,
+    "datetime"   TIMESTAMPTZ      NOT NULL,
+    "count"      BIGINT           NOT NULL,
+    "loss_count" BIGINT           NOT NULL,
+    "loss_ratio" DOUBLE PRECISION NOT NULL,
+    "min_ms"     BIGINT           NULL,
+    "avg_ms"     DOUBLE PRECISION NULL,
+    "max_ms"     BIGINT           NULL,
+    "p50_ms"     DOUBLE PRECISION NULL,
+    "p95_ms"     DOUBLE PRECISION NULL,
+    "p99_ms"     DOUBLE PRECISION NULL,
+    "jitter_ms"  DOUBLE PRECISION NULL,
+    UNIQUE("target", "datetime")
+);
+
+CREATE INDEX IF NOT EXISTS "ping_1m_datetime_idx" ON "ping_1m" ("datetime");
+
+CREATE TABLE IF NOT EXISTS "ping_1h"
+(
+    "id"         BIGSERIAL        PRIMARY KEY,
+    "target"     TEXT             NOT NULL,
+    "datetime"   TIMESTAMPTZ      NOT NULL,
+    "count"      BIGINT           NOT NULL,
+    "loss_count" BIGINT           NOT NULL,
+    "loss_ratio" DOUBLE PRECISION NOT NULL,
+    "min_ms"     BIGINT           NULL,
+    "avg_ms"     DOUBLE PRECISION NULL,
+    "max_ms"     BIGINT           NULL,
+    "p50_ms"     DOUBLE PRECISION NULL,
+    "p95_ms"     DOUBLE PRECISION NULL,
+    "p99_ms"     DOUBLE PRECISION NULL,
+    "jitter_ms"  DOUBLE PRECISION NULL,
+    UNIQUE("target", "datetime")
+);
+
+CREATE INDEX IF NOT EXISTS "ping_1h_datetime_idx" ON "ping_1h" ("datetime");
@@ -0,0 +1,21 @@
+-- Add migration script here
+
+-- every attempt to fetch and append the logs of the FRITZ!Box, see
+-- `db::PollRun`
+CREATE TABLE IF NOT EXISTS "poll_runs"
+(
+    "id"                BIGSERIAL   PRIMARY KEY,
+    "datetime"          TIMESTAMPTZ NOT NULL,
+    "finished_datetime" TIMESTAMPTZ NOT NULL,
+    "fetched"           BIGINT      NOT NULL,
+    "inserted"          BIGINT      NOT NULL,
+    "updated"           BIGINT      NOT NULL,
+    "gap"               BOOLEAN     NOT NULL,
+    "spooled"           BOOLEAN     NOT NULL,
+    "error"             TEXT        NULL,
+    "error_message"     TEXT        NULL,
+    "version"           TEXT        NOT NULL,
+    "host"              TEXT        NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS "poll_runs_datetime_idx" ON "poll_runs" ("datetime");
@@ -0,0 +1,82 @@
+-- Add migration script here
+
+-- same schema as the postgres migrations, but timestamps are saved as unixtime
+-- with millisecond precision
+CREATE TABLE IF NOT EXISTS "logs"
+(
+    "id"                  INTEGER PRIMARY KEY AUTOINCREMENT,
+    "datetime"            INTEGER NOT NULL,
+    "message"             TEXT    NOT NULL,
+    "message_id"          INTEGER NOT NULL,
+    "category_id"         INTEGER NOT NULL,
+    "repetition_datetime" INTEGER NULL,
+    "repetition_count"    INTEGER NULL,
+    "resynced"            BOOLEAN NOT NULL DEFAULT FALSE,
+    UNIQUE("datetime", "message_id", "category_id")
+);
+
+CREATE TABLE IF NOT EXISTS "requests"
+(
+    "id"            INTEGER PRIMARY KEY AUTOINCREMENT,
+    "datetime"      INTEGER NOT NULL,
+    "name"          TEXT    NOT NULL,
+    "url"           TEXT    NOT NULL,
+    "method"        TEXT    NOT NULL,
+    "duration_ms"   INTEGER NOT NULL,
+    "response_code" INTEGER NULL,
+    "session_id"    TEXT    NULL
+);
+
+CREATE TABLE IF NOT EXISTS "updates"
+(
+    "id"            INTEGER PRIMARY KEY AUTOINCREMENT,
+    "datetime"      INTEGER NOT NULL,
+    "upserted_rows" INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "ping"
+(
+    "id"          INTEGER PRIMARY KEY AUTOINCREMENT,
+    "datetime"    INTEGER NOT NULL,
+    "target"      TEXT    NOT NULL,
+    "duration_ms" INTEGER NULL,
+    "ttl"         INTEGER NULL,
+    "bytes"       INTEGER NULL
+);
+
+CREATE TABLE IF NOT EXISTS "log_gaps"
+(
+    "id"              INTEGER PRIMARY KEY AUTOINCREMENT,
+    "datetime"        INTEGER NOT NULL,
+    "kind"            TEXT    NOT NULL,
+    "after_datetime"  INTEGER NOT NULL,
+    "before_datetime" INTEGER NULL,
+    UNIQUE("kind", "after_datetime")
+);
+
+CREATE TABLE IF NOT EXISTS "log_repetitions"
+(
+    "id"               INTEGER PRIMARY KEY AUTOINCREMENT,
+    "log_id"           INTEGER NOT NULL REFERENCES "logs" ("id") ON DELETE CASCADE,
+    "datetime"         INTEGER NOT NULL,
+    "repetition_count" INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS "log_repetitions_log_id_idx" ON "log_repetitions" ("log_id");
+
+CREATE TABLE IF NOT EXISTS "log_orphans"
+(
+    "id"       INTEGER PRIMARY KEY AUTOINCREMENT,
+    "log_id"   INTEGER NOT NULL REFERENCES "logs" ("id") ON DELETE CASCADE,
+    "datetime" INTEGER NOT NULL,
+    UNIQUE("log_id")
+);
+
+CREATE TABLE IF NOT EXISTS "log_clears"
+(
+    "id"            INTEGER PRIMARY KEY AUTOINCREMENT,
+    "log_id"        INTEGER NOT NULL REFERENCES "logs" ("id") ON DELETE CASCADE,
+    "datetime"      INTEGER NOT NULL,
+    "archived_logs" INTEGER NOT NULL,
+    UNIQUE("log_id")
+);
@@ -0,0 +1,30 @@
+-- Add migration script here
+
+-- full-text search over the messages, kept in sync with "logs" by triggers.
+-- SQLite has no german stemmer, but umlauts and case are folded at least.
+CREATE VIRTUAL TABLE IF NOT EXISTS "logs_search" USING fts5
+(
+    "message",
+    content='logs',
+    content_rowid='id',
+    tokenize='unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS "logs_search_insert" AFTER INSERT ON "logs"
+BEGIN
+    INSERT INTO "logs_search" ("rowid", "message") VALUES (new."id", new."message");
+END;
+
+CREATE TRIGGER IF NOT EXISTS "logs_search_delete" AFTER DELETE ON "logs"
+BEGIN
+    INSERT INTO "logs_search" ("logs_search", "rowid", "message") VALUES ('delete', old."id", old."message");
+END;
+
+CREATE TRIGGER IF NOT EXISTS "logs_search_update" AFTER UPDATE OF "message" ON "logs"
+BEGIN
+    INSERT INTO "logs_search" ("logs_search", "rowid", "message") VALUES ('delete', old."id", old."message");
+    INSERT INTO "logs_search" ("rowid", "message") VALUES (new."id", new."message");
+END;
+
+-- index the logs inserted before this migration
+INSERT INTO "logs_search" ("logs_search") VALUES ('rebuild');
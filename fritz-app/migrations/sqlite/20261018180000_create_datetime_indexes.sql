@@ -0,0 +1,6 @@
+-- Add migration script here
+
+-- expired rows are pruned by "datetime"
+CREATE INDEX IF NOT EXISTS "requests_datetime_idx" ON "requests" ("datetime");
+CREATE INDEX IF NOT EXISTS "updates_datetime_idx" ON "updates" ("datetime");
+CREATE INDEX IF NOT EXISTS "ping_datetime_idx" ON "ping" ("datetime");
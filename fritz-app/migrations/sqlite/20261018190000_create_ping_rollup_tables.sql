@@ -0,0 +1,43 @@
+-- Add migration script here
+
+-- pings of a target rolled up into buckets starting at "datetime", see
+-- `db::PingRollup`
+CREATE TABLE IF NOT EXISTS "ping_1m"
+(
+    "id"         INTEGER PRIMARY KEY AUTOINCREMENT,
+    "target"     TEXT    NOT NULL,
+    "datetime"   INTEGER NOT NULL,
+    "count"      INTEGER NOT NULL,
+    "loss_count" INTEGER NOT NULL,
+    "loss_ratio" REAL    NOT NULL,
+    "min_ms"     INTEGER NULL,
+    "avg_ms"     REAL    NULL,
+    "max_ms"     INTEGER NULL,
+    "p50_ms"     REAL    NULL,
+    "p95_ms"     REAL    NULL,
+    "p99_ms"     REAL    NULL,
+    "jitter_ms"  REAL    NULL,
+    UNIQUE("target", "datetime")
+);
+
+CREATE INDEX IF NOT EXISTS "ping_1m_datetime_idx" ON "ping_1m" ("datetime");
+
+CREATE TABLE IF NOT EXISTS "ping_1h"
+(
+    "id"         INTEGER PRIMARY KEY AUTOINCREMENT,
+    "target"     TEXT    NOT NULL,
+    "datetime"   INTEGER NOT NULL,
+    "count"      INTEGER NOT NULL,
+    "loss_count" INTEGER NOT NULL,
+    "loss_ratio" REAL    NOT NULL,
+    "min_ms"     INTEGER NULL,
+    "avg_ms"     REAL    NULL,
+    "max_ms"     INTEGER NULL,
+    "p50_ms"     REAL    NULL,
+    "p95_ms"     REAL    NULL,
+    "p99_ms"     REAL    NULL,
+    "jitter_ms"  REAL    NULL,
+    UNIQUE("target", "datetime")
+);
+
+CREATE INDEX IF NOT EXISTS "ping_1h_datetime_idx" ON "ping_1h" ("datetime");
@@ -0,0 +1,21 @@
+-- Add migration script here
+
+-- every attempt to fetch and append the logs of the FRITZ!Box, see
+-- `db::PollRun`
+CREATE TABLE IF NOT EXISTS "poll_runs"
+(
+    "id"                INTEGER PRIMARY KEY AUTOINCREMENT,
+    "datetime"          INTEGER NOT NULL,
+    "finished_datetime" INTEGER NOT NULL,
+    "fetched"           INTEGER NOT NULL,
+    "inserted"          INTEGER NOT NULL,
+    "updated"           INTEGER NOT NULL,
+    "gap"               BOOLEAN NOT NULL,
+    "spooled"           BOOLEAN NOT NULL,
+    "error"             TEXT    NULL,
+    "error_message"     TEXT    NULL,
+    "version"           TEXT    NOT NULL,
+    "host"              TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS "poll_runs_datetime_idx" ON "poll_runs" ("datetime");
@@ -0,0 +1,9 @@
+-- Add migration script here
+
+-- why a request failed, how large the response was, how many times in a row
+-- the request has been tried and which address answered, see `db::Request`
+ALTER TABLE "requests" ADD COLUMN "error_kind" TEXT NULL;
+ALTER TABLE "requests" ADD COLUMN "error_message" TEXT NULL;
+ALTER TABLE "requests" ADD COLUMN "response_bytes" INTEGER NULL;
+ALTER TABLE "requests" ADD COLUMN "attempt" INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE "requests" ADD COLUMN "remote_addr" TEXT NULL;
@@ -0,0 +1,6 @@
+-- Add migration script here
+
+-- how a local time of the FRITZ!Box that occurred twice or not at all because
+-- of daylight saving time has been resolved, see `fritz::TimeResolution`
+ALTER TABLE "logs" ADD COLUMN "datetime_resolution" TEXT NULL;
+ALTER TABLE "logs" ADD COLUMN "repetition_datetime_resolution" TEXT NULL;
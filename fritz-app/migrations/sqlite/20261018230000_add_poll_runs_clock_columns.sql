@@ -0,0 +1,6 @@
+-- Add migration script here
+
+-- how far the newest log of the FRITZ!Box was ahead of the request and how
+-- many logs had implausible timestamps, see `fritz::ClockCheck`
+ALTER TABLE "poll_runs" ADD COLUMN "clock_drift_ms" INTEGER NULL;
+ALTER TABLE "poll_runs" ADD COLUMN "implausible" INTEGER NOT NULL DEFAULT 0;
@@ -1,24 +1,37 @@
 //! Exposes a `Client` struct to interact with the API.
 
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Instant;
 
 use anyhow::Context;
-use chrono::{Local, Utc};
+use chrono::{DateTime, Utc};
 use parking_lot::Mutex;
-use reqwest::tls::Version;
-use reqwest::{Method, RequestBuilder};
+use reqwest::Method;
 
-use super::{model, SessionId, SessionInfo};
+use super::transport::{Body, HttpRequest, HttpTransport, RecordTransport, Transport};
+use super::{model, recording, SessionId, SessionInfo, StatusError};
+use crate::poll::Failure;
 use crate::{db, fritz};
 
 fn elapsed_ms(start: &Instant) -> i64 {
     start.elapsed().as_millis().min(i64::MAX as u128) as i64
 }
 
+/// Context of errors while creating a new session, e.g. because the FRITZ!Box
+/// rejected the credentials
+#[derive(Debug)]
+pub struct LoginFailed;
+
+impl std::fmt::Display for LoginFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("login failed")
+    }
+}
+
 pub struct Client {
     /// Use to make REST requests
-    client: reqwest::Client,
+    transport: Box<dyn Transport>,
     /// Example: `192.168.178.1` or `fritz.box`
     domain: String,
     /// This is set once logged in
@@ -27,10 +40,22 @@ pub struct Client {
     username: String,
     /// Password to log in with
     password: String,
-    /// Path to save responses to
-    save_response_path: Option<PathBuf>,
+    /// Saves responses if enabled
+    recorder: Option<Arc<recording::Recorder>>,
     /// Database
     database: Option<db::Database>,
+    /// How many requests of each name failed in a row
+    failures: Mutex<HashMap<String, i64>>,
+    /// Timezone the timestamps of the FRITZ!Box are in
+    timezone: Mutex<fritz::BoxTimezone>,
+    /// Whether to detect the timezone after every login
+    detect_timezone: bool,
+    /// When the last login has been requested, until the timezone has been
+    /// detected from its log
+    logged_in_at: Mutex<Option<DateTime<Utc>>>,
+    /// How the clock of the FRITZ!Box compared to the host when the logs
+    /// have last been fetched
+    clock: Mutex<Option<fritz::ClockCheck>>,
 }
 
 impl Client {
@@ -38,7 +63,7 @@ impl Client {
     ///
     /// Parameters that are `None` will be with their environment variables
     /// counterpart.
-    pub async fn new(
+    pub fn new(
         domain: Option<&str>,
         username: Option<&str>,
         password: Option<&str>,
@@ -72,124 +97,152 @@ impl Client {
         let username = resolve_var("FRITZBOX_USERNAME", username)?;
         let password = resolve_var("FRITZBOX_PASSWORD", password)?;
 
-        let mut builder = reqwest::Client::builder()
-            .https_only(true)
-            .min_tls_version(Version::TLS_1_2);
-
-        match resolve_root_cert("FRITZBOX_ROOT_CERT_PATH", root_cert) {
-            Err(_) => {
-                log::warn!("couldn't load root cert, accepting invalid certs");
-                builder = builder.danger_accept_invalid_certs(true);
-            }
-            Ok(root_cert) => {
-                builder = builder.add_root_certificate(root_cert);
-            }
+        let root_cert = resolve_root_cert("FRITZBOX_ROOT_CERT_PATH", root_cert).ok();
+        let transport = HttpTransport::new(root_cert)?;
+        let transport: Box<dyn Transport> = match dotenv::var("FRITZBOX_RECORD_CASSETTE") {
+            Err(_) => Box::new(transport),
+            Ok(path) => Box::new(RecordTransport::create(transport, path)?),
         };
 
-        let client = builder
-            .build()
-            .context("invalid http client configuration")?;
+        let timezone = fritz::TimezoneSetting::try_from_env()?;
 
-        let save_response_path = Self::save_response_path().await;
+        let recorder = recording::Recorder::try_from_env()
+            .unwrap_or_else(|err| {
+                log::warn!("couldn't set up saving responses: {:?}", err);
+                None
+            })
+            .map(Arc::new);
 
         Ok(Client {
-            client,
+            transport,
             domain,
             session_id: Mutex::new(None),
             username,
             password,
-            save_response_path,
+            recorder,
             database: pool.cloned(),
-        })
+            failures: Mutex::new(HashMap::new()),
+            timezone: Mutex::new(fritz::BoxTimezone::Host),
+            detect_timezone: false,
+            logged_in_at: Mutex::new(None),
+            clock: Mutex::new(None),
+        }
+        .with_timezone(timezone))
     }
 
-    /// Determine path to save responses to from environment variables.
-    async fn save_response_path() -> Option<PathBuf> {
-        let Ok(save_response) = dotenv::var("FRITZBOX_SAVE_RESPONSE") else {
-            return None;
-        };
-        let Ok(save_response) = save_response.parse::<bool>() else {
-            log::warn!("couldn't parse FRITZBOX_SAVE_RESPONSE as bool");
-            return None;
+    /// Send requests with `transport` instead, e.g. to replay a cassette.
+    pub fn with_transport(self, transport: impl Transport + 'static) -> Client {
+        Client {
+            transport: Box::new(transport),
+            ..self
+        }
+    }
+
+    /// Parse the timestamps of the FRITZ!Box in a fixed timezone, or detect
+    /// it after every login.
+    ///
+    /// Until the timezone has been detected, the timezone of the host is used.
+    /// A detected offset is replaced by the timezone of the host if they
+    /// match, see [`fritz::BoxTimezone::prefer`].
+    pub fn with_timezone(self, timezone: fritz::TimezoneSetting) -> Client {
+        let (timezone, detect_timezone) = match timezone {
+            fritz::TimezoneSetting::Fixed(timezone) => (timezone, false),
+            fritz::TimezoneSetting::Detect => (fritz::BoxTimezone::Host, true),
         };
-        if !save_response {
-            return None;
+        Client {
+            timezone: Mutex::new(timezone),
+            detect_timezone,
+            ..self
         }
+    }
+
+    /// Timezone the timestamps of the FRITZ!Box are parsed in.
+    pub fn timezone(&self) -> fritz::BoxTimezone {
+        *self.timezone.lock()
+    }
 
-        let Ok(save_response_path) = dotenv::var("FRITZBOX_SAVE_RESPONSE_PATH") else {
-            log::warn!("missing env var FRITZBOX_SAVE_RESPONSE_PATH");
-            return None;
+    /// How the clock of the FRITZ!Box compared to the host when the logs have
+    /// last been fetched.
+    pub fn clock(&self) -> Option<fritz::ClockCheck> {
+        *self.clock.lock()
+    }
+
+    /// Detect the timezone from the log of the login requested at
+    /// `logged_in_at`, which should be the newest one.
+    fn detect_timezone(&self, response: &model::Response, logged_in_at: DateTime<Utc>) {
+        let Some(newest) = response.data.logs.first() else {
+            log::warn!("couldn't detect the timezone, there are no logs");
+            return;
         };
 
-        let save_response_path = PathBuf::from(save_response_path);
-        match tokio::fs::metadata(&save_response_path).await {
-            Ok(metadata) => {
-                if !metadata.is_dir() {
-                    log::warn!("FRITZBOX_SAVE_RESPONSE_PATH does not point to a folder");
-                    return None;
-                }
-                Some(save_response_path)
-            }
-            Err(_) => {
-                if let Err(err) = tokio::fs::create_dir(&save_response_path).await {
-                    log::warn!(
-                        "couldn't create folder to FRITZBOX_SAVE_RESPONSE_PATH: {:?}",
-                        err
-                    );
-                    None
-                } else {
-                    log::info!("created folder to FRITZBOX_SAVE_RESPONSE_PATH");
-                    Some(save_response_path)
+        match fritz::BoxTimezone::detect(newest, logged_in_at, &self.username) {
+            Ok(Some(detected)) => {
+                let timezone = detected.prefer(fritz::BoxTimezone::Host, logged_in_at);
+                let previous = std::mem::replace(&mut *self.timezone.lock(), timezone);
+                if previous != timezone {
+                    log::info!("detected timezone {} (was {})", timezone, previous);
+                    if timezone == detected {
+                        log::warn!(
+                            "{} isn't turned for daylight saving time until the next login, \
+                             set FRITZBOX_TIMEZONE to the IANA timezone of the FRITZ!Box instead",
+                            timezone
+                        );
+                    }
                 }
             }
+            Ok(None) => log::warn!(
+                "couldn't detect the timezone, the newest log isn't the login, keeping {}",
+                self.timezone()
+            ),
+            Err(err) => log::warn!("couldn't detect the timezone: {:?}", err),
         }
     }
 
-    async fn save_response(&self, name: &str, text: &str) {
-        let Some(mut path) = self.save_response_path.as_ref().cloned() else {
+    /// Redact and save a response if `FRITZBOX_SAVE_RESPONSE` is enabled.
+    ///
+    /// Compressing and writing the response blocks, so it's done on a
+    /// blocking thread. Waiting for it keeps the responses in order.
+    async fn save_response(&self, recorded: impl FnOnce() -> recording::Recorded) {
+        let Some(recorder) = self.recorder.clone() else {
             return;
         };
 
-        let now = Local::now().format("%Y-%m-%d_%H-%M-%S.%3f");
-        path.push(format!("response_{}_{}.txt", now, name));
-
-        if let Err(err) = tokio::fs::write(&path, text).await {
-            log::warn!("couldn't save {}: {:?}", path.to_string_lossy(), err);
+        let recorded = recorded();
+        let name = recorded.name.clone();
+        let saved = tokio::task::spawn_blocking(move || recorder.record(recorded)).await;
+        match saved {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => log::warn!("couldn't save {} response: {:?}", name, err),
+            Err(err) => log::warn!("couldn't save {} response: {}", name, err),
         }
     }
 
-    async fn request_with_inner<F>(
+    async fn request_with_inner(
         &self,
-        name: &str,
-        url: &str,
-        method: Method,
-        func: F,
+        req: &HttpRequest,
         meta: &mut db::Request,
-    ) -> anyhow::Result<String>
-    where
-        F: FnOnce(RequestBuilder) -> RequestBuilder,
-    {
+    ) -> anyhow::Result<String> {
         meta.datetime = Utc::now();
-        meta.name = name.to_string();
-        meta.url = url.to_string();
-        meta.method = method.to_string();
+        meta.name = req.name.clone();
+        meta.url = req.url.clone();
+        meta.method = req.method.to_string();
+        meta.session_id = (*self.session_id.lock()).map(|id| id.to_string());
 
         let now = Instant::now();
-        let mut builder = self.client.request(method.clone(), url);
-        builder = func(builder);
-
-        let resp = builder.send().await.context("send request")?;
-        meta.response_code = Some(resp.status().as_u16().into());
+        let resp = self.transport.send(req).await;
+        meta.duration_ms = elapsed_ms(&now);
+        let resp = resp.context("send request")?;
+        meta.response_code = Some(resp.status.into());
+        meta.remote_addr = resp.remote_addr.clone();
 
-        if let Err(err) = resp.error_for_status_ref() {
-            meta.duration_ms = elapsed_ms(&now);
-            return Err(err).context("response status non 2XX");
+        if !(200..300).contains(&resp.status) {
+            return Err(StatusError {
+                status: resp.status,
+            })
+            .context("response status non 2XX");
         }
 
-        let text = resp.text().await;
-        meta.duration_ms = elapsed_ms(&now);
-        meta.session_id = (*self.session_id.lock()).map(|id| id.to_string());
-        let text = text.context("response code non 2XX")?;
+        meta.response_bytes = Some(resp.body.len().min(i64::MAX as usize) as i64);
 
         log::info!(
             "{} request to {} ({} - {}) took {}ms (session-id: {:?})",
@@ -201,26 +254,45 @@ impl Client {
             meta.session_id,
         );
 
-        self.save_response(name, &text).await;
+        self.save_response(|| req.recorded(&resp)).await;
 
-        Ok(text)
+        Ok(resp.body)
     }
 
-    async fn request_with<F>(
+    async fn request_with(
         &self,
         name: &str,
         url: &str,
         method: Method,
-        func: F,
-    ) -> anyhow::Result<String>
-    where
-        F: FnOnce(RequestBuilder) -> RequestBuilder,
-    {
-        let mut meta = db::Request::default();
+        body: Body,
+    ) -> anyhow::Result<String> {
+        let req = HttpRequest {
+            name: name.to_string(),
+            method,
+            url: url.to_string(),
+            body,
+        };
+        let mut meta = db::Request {
+            attempt: self
+                .failures
+                .lock()
+                .get(name)
+                .map_or(1, |failures| failures + 1),
+            ..db::Request::default()
+        };
+
+        let resp = self.request_with_inner(&req, &mut meta).await;
 
-        let resp = self
-            .request_with_inner(name, url, method, func, &mut meta)
-            .await;
+        match resp.as_ref() {
+            Ok(_) => {
+                self.failures.lock().remove(name);
+            }
+            Err(err) => {
+                meta.error_kind = Some(Failure::of_fetch(err).to_string());
+                meta.error_message = Some(format!("{:#}", err));
+                *self.failures.lock().entry(name.to_string()).or_default() += 1;
+            }
+        }
 
         if let Some(database) = self.database.as_ref() {
             if let Err(err) = database.insert_request(&meta).await {
@@ -239,7 +311,7 @@ impl Client {
 
     pub async fn check_or_renew_session_id(&self) -> anyhow::Result<SessionId> {
         match self.check_session_id().await? {
-            None => self.login().await,
+            None => self.login().await.context(LoginFailed),
             Some(session_id) => Ok(session_id),
         }
     }
@@ -255,9 +327,7 @@ impl Client {
         let form: [(&str, &str); 1] = [("sid", &session_id.to_string())];
 
         let text = self
-            .request_with("check-session-id", &url, Method::POST, |req| {
-                req.form(&form)
-            })
+            .request_with("check-session-id", &url, Method::POST, Body::form(&form))
             .await?;
         let resp_session_id = SessionInfo::from_xml(&text)?.session_id;
 
@@ -275,7 +345,7 @@ impl Client {
         let url = self.make_url("/login_sid.lua?version=2");
 
         let text = self
-            .request_with("login-challenge", &url, Method::GET, |req| req)
+            .request_with("login-challenge", &url, Method::GET, Body::Empty)
             .await?;
 
         SessionInfo::from_xml(&text)
@@ -297,7 +367,7 @@ impl Client {
         let form: [(&str, &str); 2] = [("username", &self.username), ("response", &response)];
 
         let text = self
-            .request_with("login-response", &url, Method::POST, |req| req.form(&form))
+            .request_with("login-response", &url, Method::POST, Body::form(&form))
             .await?;
 
         SessionInfo::from_xml(&text)
@@ -308,6 +378,7 @@ impl Client {
         // get the challenge
         let login_challenge = self.login_challenge().await?;
         // respond with the correct response
+        let logged_in_at = Utc::now();
         let response = self.login_response(&login_challenge).await?;
         // check returned session id
         if !response.session_id.is_valid() {
@@ -318,6 +389,9 @@ impl Client {
         }
 
         *self.session_id.lock() = Some(response.session_id);
+        if self.detect_timezone {
+            *self.logged_in_at.lock() = Some(logged_in_at);
+        }
         Ok(response.session_id)
     }
 
@@ -332,7 +406,7 @@ impl Client {
         let form: [(&str, &str); 2] = [("logout", "1"), ("sid", &session_id.to_string())];
 
         let _ = self
-            .request_with("logout", &url, Method::POST, |req| req.form(&form))
+            .request_with("logout", &url, Method::POST, Body::form(&form))
             .await?;
 
         *self.session_id.lock() = None;
@@ -343,12 +417,10 @@ impl Client {
     pub async fn certificate(&self) -> anyhow::Result<String> {
         let url = self.make_url("/cgi-bin/firmwarecfg");
         let session_id = self.check_or_renew_session_id().await?.to_string();
-        let form = reqwest::multipart::Form::new()
-            .text("sid", session_id)
-            .text("BoxCertExport", "");
+        let form: [(&str, &str); 2] = [("sid", &session_id), ("BoxCertExport", "")];
 
         let text = self
-            .request_with("box-cert", &url, Method::POST, |req| req.multipart(form))
+            .request_with("box-cert", &url, Method::POST, Body::multipart(&form))
             .await?;
 
         Ok(text)
@@ -368,7 +440,7 @@ impl Client {
         ];
 
         let text = self
-            .request_with("clear-logs", &url, Method::POST, |req| req.form(&form))
+            .request_with("clear-logs", &url, Method::POST, Body::form(&form))
             .await?;
 
         serde_json::from_str(&text).context("parse json")
@@ -377,7 +449,10 @@ impl Client {
     /// Fetch logs from the FRITZ!Box.
     ///
     /// API returns logs ordered from **new to old** so the **newest log is at index 0**.
-    pub async fn logs(&self) -> anyhow::Result<Vec<fritz::Log>> {
+    ///
+    /// Implausible timestamps are fixed, see [`fritz::check_clock`]. `tail` is
+    /// the newest archived log, see [`fritz::Log::from_api`].
+    pub async fn logs(&self, tail: Option<&fritz::Log>) -> anyhow::Result<Vec<fritz::Log>> {
         let url = self.make_url("/data.lua");
         let session_id = self.check_or_renew_session_id().await?.to_string();
         let form: [(&str, &str); 6] = [
@@ -389,15 +464,27 @@ impl Client {
             ("xhrId", "all"),
         ];
 
+        let requested = Utc::now();
         let text = self
-            .request_with("logs", &url, Method::POST, |req| req.form(&form))
+            .request_with("logs", &url, Method::POST, Body::form(&form))
             .await?;
 
-        let logs: Vec<model::Log> = serde_json::from_str::<model::Response>(&text)
-            .context("parse response json")?
-            .data
-            .logs;
+        let response = model::Response::from_json(&text)?;
+        let logged_in_at = self.logged_in_at.lock().take();
+        if let Some(logged_in_at) = logged_in_at {
+            self.detect_timezone(&response, logged_in_at);
+        }
 
-        logs.into_iter().map(fritz::Log::try_from).collect()
+        let mut logs = response.into_logs(&self.timezone(), tail)?;
+        let clock = fritz::check_clock(&mut logs, requested);
+        if clock.implausible != 0 {
+            log::warn!(
+                "{} logs have implausible timestamps, the newest log is {:?} ahead",
+                clock.implausible,
+                clock.drift
+            );
+        }
+        *self.clock.lock() = Some(clock);
+        Ok(logs)
     }
 }
@@ -1,7 +1,10 @@
 mod client;
-pub use client::Client;
+pub use client::{Client, LoginFailed};
 
 pub mod challenge;
+pub mod recording;
+pub mod transport;
+pub use transport::StatusError;
 
 mod session;
 pub use session::{SessionId, SessionInfo, User};
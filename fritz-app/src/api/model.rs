@@ -1,5 +1,8 @@
+use anyhow::Context;
 use serde::Deserialize;
 
+use crate::fritz;
+
 /// A single log entry.
 ///
 /// - `[0]`: Date (`31.12.23`)
@@ -23,3 +26,21 @@ pub struct Data {
 pub struct Response {
     pub data: Data,
 }
+
+impl Response {
+    pub fn from_json(json: &str) -> anyhow::Result<Response> {
+        serde_json::from_str(json).context("parse response json")
+    }
+
+    /// Convert the logs into a common format, their timestamps are in `timezone`.
+    ///
+    /// Logs are ordered from **new to old** so the **newest log is at index 0**.
+    /// `tail` is the newest archived log, see [`fritz::Log::from_api`].
+    pub fn into_logs(
+        self,
+        timezone: &fritz::BoxTimezone,
+        tail: Option<&fritz::Log>,
+    ) -> anyhow::Result<Vec<fritz::Log>> {
+        fritz::Log::from_api(self.data.logs, timezone, tail)
+    }
+}
@@ -0,0 +1,660 @@
+//! Responses saved by the `Client` if `FRITZBOX_SAVE_RESPONSE` is enabled.
+//!
+//! Responses are appended to zstd compressed segments named
+//! `responses_<timestamp>Z.jsonl.zst`, where the timestamp is the UTC time at
+//! which the segment has been started. Every response is a [`Recorded`] JSON
+//! line compressed into its own zstd frame, so a segment stays readable up to
+//! the last complete response if the process dies while writing. A new
+//! segment is started once the current one is too large or too old, the
+//! oldest segments are removed once all segments together are too large.
+//!
+//! Session ids, login responses and passwords are redacted before anything is
+//! written, see [`redact`].
+//!
+//! Older versions saved every response to its own plaintext file named
+//! `response_<timestamp>_<request-name>.txt`, where the timestamp is the local
+//! time at which the response has been received. These are still listed.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+
+const DATETIME_FORMAT: &str = "%Y-%m-%d_%H-%M-%S.%3f";
+const DATETIME_LEN: usize = "2023-12-31_23-59-59.999".len();
+
+/// Segments are named in UTC, so their names are unique and sorted even
+/// when the clocks are turned back
+const SEGMENT_DATETIME_FORMAT: &str = "%Y-%m-%d_%H-%M-%S.%3fZ";
+
+const SEGMENT_PREFIX: &str = "responses_";
+const SEGMENT_SUFFIX: &str = ".jsonl.zst";
+
+/// zstd compression level of segments
+const COMPRESSION_LEVEL: i32 = 9;
+
+/// Replaces session ids, so redacted responses can still be parsed
+pub const REDACTED_SID: &str = "ffffffffffffffff";
+/// Replaces any other secret
+pub const REDACTED: &str = "REDACTED";
+
+/// Session id of a FRITZ!Box that isn't logged in, which isn't a secret
+const INVALID_SID: &str = "0000000000000000";
+
+/// Request parameters whose values are secrets
+const SECRET_PARAMS: [&str; 3] = ["sid", "response", "password"];
+
+/// A response together with the request it answered.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Recorded {
+    /// Time at which the response has been received
+    pub datetime: DateTime<Utc>,
+    /// Name of the request, e.g. `logs`
+    pub name: String,
+    pub method: String,
+    pub url: String,
+    /// Form parameters of the request
+    pub params: Vec<(String, String)>,
+    pub status: u16,
+    pub body: String,
+}
+
+impl Recorded {
+    /// Redact the secrets of the request and the response.
+    pub fn redacted(self) -> Recorded {
+        Recorded {
+            url: redact(&self.url),
+            params: self
+                .params
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = redact_param(&key, &value);
+                    (key, value)
+                })
+                .collect(),
+            body: redact(&self.body),
+            ..self
+        }
+    }
+}
+
+fn redact_param(key: &str, value: &str) -> String {
+    match key {
+        "sid" if value == INVALID_SID => value.to_string(),
+        "sid" => REDACTED_SID.to_string(),
+        _ if SECRET_PARAMS.contains(&key) => REDACTED.to_string(),
+        _ => value.to_string(),
+    }
+}
+
+/// Replace the session ids in a URL or a XML or JSON response.
+pub fn redact(text: &str) -> String {
+    let redact_sid = |caps: &lazy_regex::Captures| {
+        let sid = if &caps[2] == INVALID_SID {
+            INVALID_SID
+        } else {
+            REDACTED_SID
+        };
+        format!("{}{}{}", &caps[1], sid, &caps[3])
+    };
+
+    let text =
+        lazy_regex::regex!(r"(<SID>)([0-9a-fA-F]{16})(</SID>)").replace_all(text, redact_sid);
+    let text =
+        lazy_regex::regex!(r#"("sid"\s*:\s*")([0-9a-fA-F]{16})(")"#).replace_all(&text, redact_sid);
+    lazy_regex::regex!(r"([?&]sid=)([0-9a-fA-F]{16})()")
+        .replace_all(&text, redact_sid)
+        .into_owned()
+}
+
+/// When to start a new segment and when to remove old ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RotationOptions {
+    /// Start a new segment once the current one is at least this large
+    pub segment_bytes: u64,
+    /// Start a new segment once the current one is at least this old
+    pub segment_age: Duration,
+    /// Remove the oldest segments once all segments together are larger
+    pub max_bytes: u64,
+}
+
+impl Default for RotationOptions {
+    fn default() -> RotationOptions {
+        RotationOptions {
+            segment_bytes: 16 * 1024 * 1024,
+            segment_age: Duration::from_secs(24 * 60 * 60),
+            max_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+impl RotationOptions {
+    pub fn try_from_env() -> anyhow::Result<RotationOptions> {
+        let mut opts = RotationOptions::default();
+        if let Ok(bytes) = dotenv::var("FRITZBOX_SAVE_RESPONSE_SEGMENT_BYTES") {
+            opts.segment_bytes = bytes
+                .parse()
+                .context("parse FRITZBOX_SAVE_RESPONSE_SEGMENT_BYTES")?;
+        }
+        if let Ok(hours) = dotenv::var("FRITZBOX_SAVE_RESPONSE_SEGMENT_HOURS") {
+            let hours = hours
+                .parse::<u64>()
+                .context("parse FRITZBOX_SAVE_RESPONSE_SEGMENT_HOURS")?;
+            opts.segment_age = Duration::from_secs(hours * 60 * 60);
+        }
+        if let Ok(bytes) = dotenv::var("FRITZBOX_SAVE_RESPONSE_MAX_BYTES") {
+            opts.max_bytes = bytes
+                .parse()
+                .context("parse FRITZBOX_SAVE_RESPONSE_MAX_BYTES")?;
+        }
+        Ok(opts)
+    }
+}
+
+/// Appends responses to compressed segments in a folder.
+pub struct Recorder {
+    dir: PathBuf,
+    opts: RotationOptions,
+    segment: parking_lot::Mutex<Option<Segment>>,
+}
+
+struct Segment {
+    file: File,
+    started: DateTime<Utc>,
+    len: u64,
+}
+
+impl Recorder {
+    /// Record to `dir`, creating it if necessary. The first response starts a
+    /// new segment, segments of earlier runs aren't appended to.
+    pub fn open(dir: impl AsRef<Path>, opts: RotationOptions) -> anyhow::Result<Recorder> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("create response directory {}", dir.display()))?;
+        Ok(Recorder {
+            dir,
+            opts,
+            segment: parking_lot::Mutex::new(None),
+        })
+    }
+
+    /// Record to `FRITZBOX_SAVE_RESPONSE_PATH` if `FRITZBOX_SAVE_RESPONSE` is
+    /// enabled.
+    pub fn try_from_env() -> anyhow::Result<Option<Recorder>> {
+        let Ok(save_response) = dotenv::var("FRITZBOX_SAVE_RESPONSE") else {
+            return Ok(None);
+        };
+        if !save_response
+            .parse::<bool>()
+            .context("parse FRITZBOX_SAVE_RESPONSE")?
+        {
+            return Ok(None);
+        }
+
+        let dir = dotenv::var("FRITZBOX_SAVE_RESPONSE_PATH")
+            .context("load FRITZBOX_SAVE_RESPONSE_PATH")?;
+        Recorder::open(dir, RotationOptions::try_from_env()?).map(Some)
+    }
+
+    /// Redact and append a response to the current segment.
+    ///
+    /// Compressing and writing blocks, so async code should call this on a
+    /// blocking thread, e.g. with [`tokio::task::spawn_blocking`].
+    pub fn record(&self, recorded: Recorded) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(&recorded.redacted()).context("serialize response")?;
+        line.push(b'\n');
+        let frame = zstd::encode_all(line.as_slice(), COMPRESSION_LEVEL).context("compress")?;
+
+        let mut segment = self.segment.lock();
+        let rotate = segment.as_ref().is_none_or(|segment| {
+            segment.len >= self.opts.segment_bytes
+                || (Utc::now() - segment.started)
+                    .to_std()
+                    .is_ok_and(|age| age >= self.opts.segment_age)
+        });
+        if rotate {
+            *segment = Some(self.start_segment()?);
+        }
+        let Some(current) = segment.as_mut() else {
+            unreachable!("a segment has just been started");
+        };
+        current
+            .file
+            .write_all(&frame)
+            .context("append to segment")?;
+        current.len += frame.len() as u64;
+        drop(segment);
+
+        if rotate {
+            self.remove_old_segments()?;
+        }
+        Ok(())
+    }
+
+    fn start_segment(&self) -> anyhow::Result<Segment> {
+        let started = Utc::now();
+        let path = self.dir.join(segment_file_name(started));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("create segment {}", path.display()))?;
+        Ok(Segment {
+            file,
+            started,
+            len: 0,
+        })
+    }
+
+    /// Remove the oldest segments, but never the current one, until all
+    /// segments together fit into [`RotationOptions::max_bytes`].
+    fn remove_old_segments(&self) -> anyhow::Result<()> {
+        let segments = list(&self.dir)?.segments;
+        let mut total = 0;
+        let mut sizes = Vec::with_capacity(segments.len());
+        for segment in &segments {
+            let len = std::fs::metadata(&segment.path)
+                .with_context(|| format!("read metadata of {}", segment.path.display()))?
+                .len();
+            total += len;
+            sizes.push(len);
+        }
+
+        let removable = segments.len().saturating_sub(1);
+        for (segment, len) in segments.iter().zip(sizes).take(removable) {
+            if total <= self.opts.max_bytes {
+                break;
+            }
+            std::fs::remove_file(&segment.path)
+                .with_context(|| format!("remove {}", segment.path.display()))?;
+            log::info!("removed old response segment {}", segment.path.display());
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+/// Create the file name of a segment started at `datetime`.
+pub fn segment_file_name(datetime: DateTime<Utc>) -> String {
+    format!(
+        "{}{}{}",
+        SEGMENT_PREFIX,
+        datetime.format(SEGMENT_DATETIME_FORMAT),
+        SEGMENT_SUFFIX
+    )
+}
+
+/// Parse a file name created by [`segment_file_name`].
+pub fn parse_segment_file_name(file_name: &str) -> anyhow::Result<DateTime<Utc>> {
+    let datetime = file_name
+        .strip_prefix(SEGMENT_PREFIX)
+        .and_then(|rest| rest.strip_suffix(SEGMENT_SUFFIX))
+        .context("missing responses_ prefix or .jsonl.zst suffix")?;
+    Ok(
+        NaiveDateTime::parse_from_str(datetime, SEGMENT_DATETIME_FORMAT)
+            .context("parse timestamp")?
+            .and_utc(),
+    )
+}
+
+/// Reads the responses of a segment, in the order they've been recorded.
+///
+/// A response that has only been written partially because the process died
+/// ends the segment.
+pub struct SegmentReader {
+    path: PathBuf,
+    reader: BufReader<zstd::Decoder<'static, BufReader<File>>>,
+    read: usize,
+    done: bool,
+}
+
+impl SegmentReader {
+    pub fn open(path: &Path) -> anyhow::Result<SegmentReader> {
+        let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+        Ok(SegmentReader {
+            path: path.to_path_buf(),
+            reader: BufReader::new(zstd::Decoder::new(file).context("create decoder")?),
+            read: 0,
+            done: false,
+        })
+    }
+}
+
+impl Iterator for SegmentReader {
+    type Item = anyhow::Result<Recorded>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut line = Vec::new();
+        match self.reader.read_until(b'\n', &mut line) {
+            Ok(_) if line.ends_with(b"\n") => {
+                self.read += 1;
+                Some(serde_json::from_slice(&line).with_context(|| {
+                    format!("parse response {} of {}", self.read, self.path.display())
+                }))
+            }
+            Ok(0) => {
+                self.done = true;
+                None
+            }
+            Ok(_) => {
+                self.done = true;
+                log::warn!("skipping the torn end of {}", self.path.display());
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                log::warn!("skipping the torn end of {}: {}", self.path.display(), err);
+                None
+            }
+        }
+    }
+}
+
+/// Parse the local timestamp of a response saved by an older version, which
+/// is ambiguous while the clocks are turned back.
+fn parse_datetime(datetime: &str) -> anyhow::Result<DateTime<Local>> {
+    NaiveDateTime::parse_from_str(datetime, DATETIME_FORMAT)
+        .context("parse timestamp")?
+        .and_local_timezone(Local)
+        .earliest()
+        .context("timestamp into local timezone")
+}
+
+/// A response saved to its own plaintext file by an older version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseFile {
+    pub path: PathBuf,
+    /// Time at which the response has been received
+    pub datetime: DateTime<Local>,
+    /// Name of the request, e.g. `logs`
+    pub name: String,
+}
+
+/// A segment of compressed responses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentFile {
+    pub path: PathBuf,
+    /// Time at which the segment has been started
+    pub datetime: DateTime<Utc>,
+}
+
+/// Create the file name for a response received at `datetime`.
+pub fn file_name(datetime: DateTime<Local>, name: &str) -> String {
+    format!("response_{}_{}.txt", datetime.format(DATETIME_FORMAT), name)
+}
+
+/// Parse a file name created by [`file_name`].
+pub fn parse_file_name(file_name: &str) -> anyhow::Result<(DateTime<Local>, &str)> {
+    let rest = file_name
+        .strip_prefix("response_")
+        .and_then(|rest| rest.strip_suffix(".txt"))
+        .context("missing response_ prefix or .txt suffix")?;
+
+    let datetime = rest.get(..DATETIME_LEN).context("file name too short")?;
+    let name = rest
+        .get(DATETIME_LEN..)
+        .and_then(|name| name.strip_prefix('_'))
+        .context("missing request name")?;
+
+    Ok((parse_datetime(datetime)?, name))
+}
+
+/// Content of a folder containing saved responses.
+#[derive(Debug, Default)]
+pub struct Listing {
+    /// Saved responses sorted from **old to new**
+    pub files: Vec<ResponseFile>,
+    /// Segments sorted from **old to new**
+    pub segments: Vec<SegmentFile>,
+    /// Files that don't follow the naming scheme
+    pub invalid: Vec<(PathBuf, anyhow::Error)>,
+}
+
+/// List all saved responses in `dir`.
+pub fn list(dir: &Path) -> anyhow::Result<Listing> {
+    let mut files = Vec::new();
+    let mut segments = Vec::new();
+    let mut invalid = Vec::new();
+
+    for entry in std::fs::read_dir(dir).context("read response dir")? {
+        let entry = entry.context("read response dir entry")?;
+        if !entry.file_type().context("get file type")?.is_file() {
+            continue;
+        }
+
+        let path = entry.path();
+        let file_name = entry.file_name();
+        let file_name = file_name.to_str().context("file name is not utf-8");
+        if let Ok(file_name) = file_name.as_ref() {
+            if file_name.starts_with(SEGMENT_PREFIX) {
+                match parse_segment_file_name(file_name) {
+                    Ok(datetime) => segments.push(SegmentFile { path, datetime }),
+                    Err(err) => invalid.push((path, err)),
+                }
+                continue;
+            }
+        }
+
+        let parsed = file_name.and_then(|file_name| {
+            parse_file_name(file_name).map(|(datetime, name)| (datetime, name.to_string()))
+        });
+
+        match parsed {
+            Ok((datetime, name)) => files.push(ResponseFile {
+                path,
+                datetime,
+                name,
+            }),
+            Err(err) => invalid.push((path, err)),
+        }
+    }
+
+    files.sort_by(|lhs, rhs| {
+        lhs.datetime
+            .cmp(&rhs.datetime)
+            .then(lhs.path.cmp(&rhs.path))
+    });
+    segments.sort_by(|lhs, rhs| {
+        lhs.datetime
+            .cmp(&rhs.datetime)
+            .then(lhs.path.cmp(&rhs.path))
+    });
+    Ok(Listing {
+        files,
+        segments,
+        invalid,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::time::Duration;
+
+    use chrono::{Local, TimeZone, Utc};
+
+    use super::{
+        file_name, list, parse_file_name, parse_segment_file_name, redact, segment_file_name,
+        Recorded, Recorder, RotationOptions, SegmentReader, REDACTED, REDACTED_SID,
+    };
+
+    fn recorded(name: &str, body: &str) -> Recorded {
+        Recorded {
+            datetime: Utc.with_ymd_and_hms(2023, 12, 31, 23, 59, 59).unwrap(),
+            name: name.to_string(),
+            method: "POST".to_string(),
+            url: "https://192.168.178.1/data.lua".to_string(),
+            params: vec![
+                ("page".to_string(), "log".to_string()),
+                ("sid".to_string(), "0de8afc227e5abeb".to_string()),
+            ],
+            status: 200,
+            body: body.to_string(),
+        }
+    }
+
+    /// A fresh directory for a test, removed by the test once it's done.
+    fn test_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "fritz-app-recording-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn round_trip() {
+        let datetime = Local
+            .with_ymd_and_hms(2023, 12, 31, 23, 59, 59)
+            .single()
+            .unwrap();
+
+        let file_name = file_name(datetime, "logs");
+        assert_eq!(file_name, "response_2023-12-31_23-59-59.000_logs.txt");
+
+        let (parsed, name) = parse_file_name(&file_name).unwrap();
+        assert_eq!(parsed, datetime);
+        assert_eq!(name, "logs");
+    }
+
+    #[test]
+    fn segment_round_trip() {
+        let datetime = Utc.with_ymd_and_hms(2023, 10, 29, 0, 30, 0).unwrap();
+
+        let file_name = segment_file_name(datetime);
+        assert_eq!(file_name, "responses_2023-10-29_00-30-00.000Z.jsonl.zst");
+        assert_eq!(parse_segment_file_name(&file_name).unwrap(), datetime);
+        assert!(parse_segment_file_name("responses_2023-10-29_00-30-00.000.jsonl.zst").is_err());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse_file_name("logs.txt").is_err());
+        assert!(parse_file_name("response_2023-12-31_logs.txt").is_err());
+        assert!(parse_file_name("response_2023-12-31_23-59-59.000.txt").is_err());
+        assert!(parse_file_name("response_2023-13-31_23-59-59.000_logs.txt").is_err());
+    }
+
+    #[test]
+    fn redact_secrets() {
+        assert_eq!(
+            redact("<SessionInfo><SID>0de8afc227e5abeb</SID></SessionInfo>"),
+            format!("<SessionInfo><SID>{}</SID></SessionInfo>", REDACTED_SID)
+        );
+        assert_eq!(
+            redact(r#"{"sid": "0de8afc227e5abeb","data":{}}"#),
+            format!(r#"{{"sid": "{}","data":{{}}}}"#, REDACTED_SID)
+        );
+        assert_eq!(
+            redact("https://fritz.box/data.lua?sid=0de8afc227e5abeb&page=log"),
+            format!("https://fritz.box/data.lua?sid={}&page=log", REDACTED_SID)
+        );
+
+        // a FRITZ!Box that isn't logged in answers with an invalid session id
+        let logged_out = "<SID>0000000000000000</SID>";
+        assert_eq!(redact(logged_out), logged_out);
+
+        let login = Recorded {
+            params: vec![
+                ("username".to_string(), "fritz3713".to_string()),
+                ("response".to_string(), "60000$d4949767".to_string()),
+            ],
+            ..recorded("login-response", "")
+        }
+        .redacted();
+        assert_eq!(
+            login.params,
+            [
+                ("username".to_string(), "fritz3713".to_string()),
+                ("response".to_string(), REDACTED.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn record_and_read() {
+        let dir = test_dir("record-and-read");
+        let recorder = Recorder::open(&dir, RotationOptions::default()).unwrap();
+        recorder
+            .record(recorded("logs", r#"{"sid":"0de8afc227e5abeb"}"#))
+            .unwrap();
+        recorder.record(recorded("logout", "")).unwrap();
+
+        let segments = list(&dir).unwrap().segments;
+        assert_eq!(segments.len(), 1);
+
+        // the end of a response that has been written partially is skipped
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&segments[0].path)
+            .unwrap();
+        let frame = zstd::encode_all(&b"{\"datetime\":"[..], 3).unwrap();
+        file.write_all(&frame[..frame.len() / 2]).unwrap();
+
+        let read = SegmentReader::open(&segments[0].path)
+            .unwrap()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            read,
+            [
+                recorded("logs", &format!(r#"{{"sid":"{}"}}"#, REDACTED_SID)).redacted(),
+                recorded("logout", "").redacted(),
+            ]
+        );
+        assert_eq!(read[0].params[1].1, REDACTED_SID);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotate() {
+        let dir = test_dir("rotate");
+        let body = "a".repeat(1000);
+        let frame_len = zstd::encode_all(
+            [
+                serde_json::to_vec(&recorded("logs", &body).redacted()).unwrap(),
+                b"\n".to_vec(),
+            ]
+            .concat()
+            .as_slice(),
+            super::COMPRESSION_LEVEL,
+        )
+        .unwrap()
+        .len() as u64;
+
+        // every segment holds two responses, only three segments are kept
+        let recorder = Recorder::open(
+            &dir,
+            RotationOptions {
+                segment_bytes: frame_len * 2,
+                segment_age: Duration::from_secs(60 * 60),
+                max_bytes: frame_len * 6,
+            },
+        )
+        .unwrap();
+        for _ in 0..10 {
+            recorder.record(recorded("logs", &body)).unwrap();
+            // segments are named by the millisecond they've been started at
+            std::thread::sleep(Duration::from_millis(2));
+        }
+
+        let segments = list(&dir).unwrap().segments;
+        let responses = segments
+            .iter()
+            .map(|segment| SegmentReader::open(&segment.path).unwrap().count())
+            .collect::<Vec<_>>();
+        assert_eq!(responses, [2, 2, 2]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -45,7 +45,7 @@ impl SessionInfo {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct SessionId {
     /// Actual SessionId
     pub id: [u8; 8],
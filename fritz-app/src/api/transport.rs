@@ -0,0 +1,285 @@
+//! How the `Client` talks to the FRITZ!Box.
+//!
+//! [`HttpTransport`] sends requests over HTTPS. [`RecordTransport`] wraps
+//! another transport and captures every request and response into a cassette,
+//! which [`ReplayTransport`] serves back in the same order without any network,
+//! so the client can be tested against captured FRITZ!Box traffic.
+//!
+//! A cassette contains one [`Recorded`] JSON object per line, redacted like
+//! saved responses, so it can be committed.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chrono::Utc;
+use reqwest::tls::Version;
+use reqwest::Method;
+
+use super::recording::Recorded;
+
+/// Body of a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Body {
+    Empty,
+    /// `application/x-www-form-urlencoded` parameters
+    Form(Vec<(String, String)>),
+    /// `multipart/form-data` text parts
+    Multipart(Vec<(String, String)>),
+}
+
+impl Body {
+    pub fn form(params: &[(&str, &str)]) -> Body {
+        Body::Form(to_owned(params))
+    }
+
+    pub fn multipart(params: &[(&str, &str)]) -> Body {
+        Body::Multipart(to_owned(params))
+    }
+
+    /// Parameters of the body, in order.
+    pub fn params(&self) -> &[(String, String)] {
+        match self {
+            Body::Empty => &[],
+            Body::Form(params) | Body::Multipart(params) => params,
+        }
+    }
+}
+
+fn to_owned(params: &[(&str, &str)]) -> Vec<(String, String)> {
+    params
+        .iter()
+        .map(|&(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// A request to the FRITZ!Box
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    /// Name of the request, e.g. `logs`
+    pub name: String,
+    pub method: Method,
+    pub url: String,
+    pub body: Body,
+}
+
+impl HttpRequest {
+    /// The request together with `response`, as saved to disk.
+    pub fn recorded(&self, response: &HttpResponse) -> Recorded {
+        Recorded {
+            datetime: Utc::now(),
+            name: self.name.clone(),
+            method: self.method.to_string(),
+            url: self.url.clone(),
+            params: self.body.params().to_vec(),
+            status: response.status,
+            body: response.body.clone(),
+        }
+    }
+}
+
+/// A response of the FRITZ!Box, whatever its status
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    /// Address that answered, if the response has been received over the network
+    pub remote_addr: Option<String>,
+    pub body: String,
+}
+
+/// The FRITZ!Box answered with a status other than 2XX
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusError {
+    pub status: u16,
+}
+
+impl std::fmt::Display for StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HTTP status {}", self.status)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+#[async_trait::async_trait]
+pub trait Transport: Send + Sync {
+    /// Send `req` and receive the response, whatever its status.
+    async fn send(&self, req: &HttpRequest) -> anyhow::Result<HttpResponse>;
+}
+
+/// Sends requests over HTTPS
+pub struct HttpTransport {
+    client: reqwest::Client,
+}
+
+impl HttpTransport {
+    /// Trust `root_cert` if given, otherwise accept any certificate.
+    pub fn new(root_cert: Option<reqwest::Certificate>) -> anyhow::Result<HttpTransport> {
+        let mut builder = reqwest::Client::builder()
+            .https_only(true)
+            .min_tls_version(Version::TLS_1_2);
+
+        match root_cert {
+            None => {
+                log::warn!("couldn't load root cert, accepting invalid certs");
+                builder = builder.danger_accept_invalid_certs(true);
+            }
+            Some(root_cert) => {
+                builder = builder.add_root_certificate(root_cert);
+            }
+        };
+
+        let client = builder
+            .build()
+            .context("invalid http client configuration")?;
+        Ok(HttpTransport { client })
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for HttpTransport {
+    async fn send(&self, req: &HttpRequest) -> anyhow::Result<HttpResponse> {
+        let builder = self.client.request(req.method.clone(), &req.url);
+        let builder = match &req.body {
+            Body::Empty => builder,
+            Body::Form(params) => builder.form(params),
+            Body::Multipart(params) => {
+                let form = params
+                    .iter()
+                    .fold(reqwest::multipart::Form::new(), |form, (key, value)| {
+                        form.text(key.clone(), value.clone())
+                    });
+                builder.multipart(form)
+            }
+        };
+
+        let resp = builder.send().await?;
+        let status = resp.status().as_u16();
+        let remote_addr = resp.remote_addr().map(|addr| addr.to_string());
+        let body = resp.text().await.context("read response body")?;
+        Ok(HttpResponse {
+            status,
+            remote_addr,
+            body,
+        })
+    }
+}
+
+/// Captures every request and response of another transport into a cassette
+pub struct RecordTransport {
+    inner: Box<dyn Transport>,
+    path: PathBuf,
+    cassette: parking_lot::Mutex<File>,
+}
+
+impl RecordTransport {
+    /// Record to the cassette at `path`, replacing it if it exists.
+    pub fn create(
+        inner: impl Transport + 'static,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<RecordTransport> {
+        let path = path.as_ref().to_path_buf();
+        let cassette =
+            File::create(&path).with_context(|| format!("create cassette {}", path.display()))?;
+        Ok(RecordTransport {
+            inner: Box::new(inner),
+            path,
+            cassette: parking_lot::Mutex::new(cassette),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for RecordTransport {
+    async fn send(&self, req: &HttpRequest) -> anyhow::Result<HttpResponse> {
+        let resp = self.inner.send(req).await?;
+
+        let mut line =
+            serde_json::to_vec(&req.recorded(&resp).redacted()).context("serialize interaction")?;
+        line.push(b'\n');
+        self.cassette
+            .lock()
+            .write_all(&line)
+            .with_context(|| format!("append to cassette {}", self.path.display()))?;
+
+        Ok(resp)
+    }
+}
+
+/// Serves the responses of a cassette in the order they've been recorded
+pub struct ReplayTransport {
+    interactions: parking_lot::Mutex<VecDeque<Recorded>>,
+}
+
+impl ReplayTransport {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<ReplayTransport> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("open cassette {}", path.display()))?;
+
+        let mut interactions = Vec::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.with_context(|| format!("read cassette {}", path.display()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            interactions.push(serde_json::from_str(&line).with_context(|| {
+                format!("parse interaction {} of {}", index + 1, path.display())
+            })?);
+        }
+        Ok(ReplayTransport::new(interactions))
+    }
+
+    pub fn new(interactions: impl IntoIterator<Item = Recorded>) -> ReplayTransport {
+        ReplayTransport {
+            interactions: parking_lot::Mutex::new(interactions.into_iter().collect()),
+        }
+    }
+
+    /// Number of interactions that haven't been replayed yet
+    pub fn remaining(&self) -> usize {
+        self.interactions.lock().len()
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for ReplayTransport {
+    async fn send(&self, req: &HttpRequest) -> anyhow::Result<HttpResponse> {
+        let Some(expected) = self.interactions.lock().pop_front() else {
+            anyhow::bail!("cassette has no interaction left for {} request", req.name);
+        };
+
+        // the cassette is redacted, so secrets only have to be sent, not match
+        let actual = req
+            .recorded(&HttpResponse {
+                status: expected.status,
+                remote_addr: None,
+                body: String::new(),
+            })
+            .redacted();
+        let matches = actual.name == expected.name
+            && actual.method == expected.method
+            && actual.url == expected.url
+            && actual.params == expected.params;
+        if !matches {
+            anyhow::bail!(
+                "unexpected {} {} {} request with {:?}, the cassette expects {} {} {} with {:?}",
+                actual.name,
+                actual.method,
+                actual.url,
+                actual.params,
+                expected.name,
+                expected.method,
+                expected.url,
+                expected.params,
+            );
+        }
+
+        Ok(HttpResponse {
+            status: expected.status,
+            remote_addr: None,
+            body: expected.body,
+        })
+    }
+}
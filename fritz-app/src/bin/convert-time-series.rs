@@ -0,0 +1,38 @@
+use anyhow::Context;
+use fritz_app::db::TimeSeriesMode;
+use structopt::StructOpt;
+
+/// Convert the time-series tables of a Postgres database into TimescaleDB
+/// hypertables or tables partitioned by month. Converted tables are skipped.
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// `timescale`, `partitioned` or `auto`, which uses TimescaleDB if the
+    /// extension is available
+    #[structopt(long = "mode", default_value = "auto")]
+    mode: TimeSeriesMode,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    fritz_app::log::init().context("initialize logger")?;
+    let opt = Opt::from_args();
+
+    match dotenv::dotenv() {
+        Ok(path) => log::info!("loaded .env from {}", path.to_str().expect("utf-8")),
+        Err(err) => log::warn!("couldn't load .env file: {:?}", err),
+    };
+
+    let db_url = std::env::var("DATABASE_URL").context("load DATABASE_URL")?;
+    let db = fritz_app::db::Database::open(&db_url)
+        .await
+        .context("open database")?;
+
+    let mode = db
+        .convert_time_series(opt.mode)
+        .await
+        .context("convert time-series tables")?;
+    log::info!("converted time-series tables ({})", mode);
+
+    db.close().await;
+    Ok(())
+}
@@ -56,6 +56,8 @@ impl TryFrom<Log> for db::Log {
                 None => None,
             },
             repetition_count: log.repetition_count,
+            datetime_resolution: None,
+            repetition_datetime_resolution: None,
         })
     }
 }
@@ -72,6 +74,11 @@ impl TryFrom<Request> for db::Request {
             duration_ms: request.duration_ms,
             response_code: request.response_code,
             session_id: request.session_id,
+            error_kind: None,
+            error_message: None,
+            response_bytes: None,
+            attempt: 1,
+            remote_addr: None,
         })
     }
 }
@@ -136,10 +143,10 @@ where
 fn main() -> anyhow::Result<()> {
     let opt = Opt::from_args();
 
-    if !std::fs::metadata(&opt.input_dir).map_or(false, |m| m.is_dir()) {
+    if !std::fs::metadata(&opt.input_dir).is_ok_and(|m| m.is_dir()) {
         anyhow::bail!("Input dir is not a directory");
     }
-    if !std::fs::metadata(&opt.output_dir).map_or(false, |m| m.is_dir()) {
+    if !std::fs::metadata(&opt.output_dir).is_ok_and(|m| m.is_dir()) {
         anyhow::bail!("Output dir is not a directory");
     }
 
@@ -1,82 +1,285 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
-use chrono::Utc;
+use structopt::StructOpt;
 use tokio::time::MissedTickBehavior;
 
+/// Fetch logs from the FRITZ!Box and save them in the database.
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Don't write to the database or clear the FRITZ!Box, only log what
+    /// would be written
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
     fritz_app::log::init().context("initialize logger")?;
+    let opt = Opt::from_args();
 
     match dotenv::dotenv() {
         Ok(path) => log::info!("loaded .env from {}", path.to_str().expect("utf-8")),
         Err(err) => log::warn!("couldn't load .env file: {:?}", err),
     };
 
-    let mut interval = {
+    let settings = Settings::try_from_env()?;
+
+    let db = if opt.dry_run {
+        open_dry_run().await?
+    } else {
+        let db_url = std::env::var("DATABASE_URL").context("load DATABASE_URL")?;
+        let db = fritz_app::db::Database::open(&db_url)
+            .await
+            .context("open database")?;
+
+        // optionally spool writes to local disk while the database is unavailable
+        match fritz_app::db::Spool::try_from_env().context("open spool")? {
+            None => db,
+            Some(spool) => db.with_spool(spool),
+        }
+    };
+
+    // only one instance polls and pings, a standby takes over once the leader is gone
+    let leader_opts =
+        fritz_app::leader::LeaderOptions::try_from_env().context("load leader options")?;
+    loop {
+        let mut leadership = fritz_app::leader::acquire(&db, &leader_opts).await;
+        tokio::select! {
+            err = fritz_app::leader::lost(&db, &mut leadership, &leader_opts) => {
+                log::warn!("lost leadership, standing by: {:?}", err);
+            }
+            result = lead(&opt, &settings, &db) => return result,
+        }
+    }
+}
+
+struct Settings {
+    refresh_pause: Duration,
+    resync_pause: Option<Duration>,
+    clear_logs: bool,
+}
+
+impl Settings {
+    fn try_from_env() -> anyhow::Result<Settings> {
         let pause_seconds = std::env::var("FRITZBOX_REFRESH_PAUSE_SECONDS")
             .context("load FRITZBOX_REFRESH_PAUSE_SECONDS")?
             .parse::<u64>()
             .context("parse FRITZBOX_REFRESH_PAUSE_SECONDS")?;
 
-        let mut interval = tokio::time::interval(Duration::from_secs(pause_seconds));
-        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
-        interval
-    };
+        // optionally compare all logs on the FRITZ!Box against the database every now and then
+        let resync_pause = match std::env::var("FRITZBOX_RESYNC_PAUSE_SECONDS") {
+            Err(_) => None,
+            Ok(pause_seconds) => Some(Duration::from_secs(
+                pause_seconds
+                    .parse::<u64>()
+                    .context("parse FRITZBOX_RESYNC_PAUSE_SECONDS")?,
+            )),
+        };
 
-    let db_url = std::env::var("DATABASE_URL").context("load DATABASE_URL")?;
-    let db = fritz_app::db::Database::open(&db_url)
-        .await
-        .context("open database")?;
+        // optionally clear the logs on the FRITZ!Box once they've been archived
+        let clear_logs = match std::env::var("FRITZBOX_CLEAR_LOGS") {
+            Err(_) => false,
+            Ok(clear_logs) => clear_logs
+                .parse::<bool>()
+                .context("parse FRITZBOX_CLEAR_LOGS")?,
+        };
 
-    let _ping_loop_handle = tokio::spawn(fritz_app::ping::ping_loop(
+        Ok(Settings {
+            refresh_pause: Duration::from_secs(pause_seconds),
+            resync_pause,
+            clear_logs,
+        })
+    }
+}
+
+/// Poll, ping, prune and replay spooled writes while this instance is the
+/// leader.
+///
+/// Dropping the returned future stops everything, background loops included.
+async fn lead(opt: &Opt, settings: &Settings, db: &fritz_app::db::Database) -> anyhow::Result<()> {
+    let mut interval = tokio::time::interval(settings.refresh_pause);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut last_resync: Option<Instant> = None;
+
+    // aborted once dropped
+    let mut background = tokio::task::JoinSet::new();
+    background.spawn(fritz_app::ping::ping_loop(
         fritz_app::ping::PingLoopOptions::try_from_env(db.clone())
             .context("load ping loop options")?,
     ));
+    background.spawn(fritz_app::ping::rollup::rollup_loop(db.clone()));
+
+    // only the leader writes, so only the leader replays spooled writes
+    if db.has_spool() {
+        background.spawn(fritz_app::db::replay_loop(db.clone()));
+    }
+
+    // optionally prune expired rows from high-volume tables
+    if let Some(opts) = fritz_app::retention::PruneLoopOptions::try_from_env(db.clone())
+        .context("load retention policies")?
+    {
+        background.spawn(fritz_app::retention::prune_loop(opts));
+    }
 
-    let client = fritz_app::api::Client::new(None, None, None, None, Some(&db)).await?;
+    let client = fritz_app::api::Client::new(None, None, None, None, Some(db))?;
     let _ = client.login().await.context("initial login attempt")?;
 
+    let host = fritz_app::poll::host();
     loop {
-        // fetch all logs from the FRITZ!Box
-        //
-        // if the logs couldn't be fetched, try again because
-        // the reason could be that the FRITZ!Box is restarting
-        // or the reason is something else ¯\_(ツ)_/¯
-        let logs = loop {
-            // wait for next tick
-            interval.tick().await;
-
-            match client.logs().await {
-                Ok(mut logs) => {
-                    logs.reverse();
-                    break logs;
-                }
-                Err(err) => {
-                    log::warn!("couldn't fetch logs: {:?}", err);
-                    continue;
-                }
-            }
-        };
+        interval.tick().await;
 
-        // append all new logs to the database
-        let upserted = db
-            .append_new_logs(&logs)
-            .await
-            .context("insert logs")?
-            .len();
-
-        if let Err(err) = db
-            .insert_update(&fritz_app::db::Update {
-                id: None,
-                datetime: Utc::now(),
-                upserted_rows: upserted.min(i64::MAX as usize) as i64,
-            })
-            .await
-        {
-            log::warn!("couldn't insert update metadata into db: {:?}", err);
+        let run = poll(opt, settings, db, &client, &host, &mut last_resync).await;
+        if let Err(err) = db.insert_poll_run(&run).await {
+            log::warn!("couldn't record poll run: {:?}", err);
+        }
+    }
+}
+
+/// Fetch all logs from the FRITZ!Box and append the new ones to the database.
+///
+/// If that fails, the next poll tries again because the reason could be that
+/// the FRITZ!Box is restarting or the reason is something else ¯\_(ツ)_/¯
+async fn poll(
+    opt: &Opt,
+    settings: &Settings,
+    db: &fritz_app::db::Database,
+    client: &fritz_app::api::Client,
+    host: &str,
+    last_resync: &mut Option<Instant>,
+) -> fritz_app::db::PollRun {
+    let mut run = fritz_app::db::PollRun::start(host);
+
+    // without the tail, logs after a wrapped buffer can't be resolved in the
+    // hour the clock is turned back, but they can still be archived
+    let tail = db.select_tail_log().await.unwrap_or_else(|err| {
+        log::warn!("couldn't select the newest archived log: {:?}", err);
+        None
+    });
+    let logs = match client.logs(tail.as_ref()).await {
+        Ok(mut logs) => {
+            logs.reverse();
+            logs
+        }
+        Err(err) => {
+            log::warn!("couldn't fetch logs: {:?}", err);
+            return run.fail(fritz_app::poll::Failure::of_fetch(&err), &err);
+        }
+    };
+    run.fetched = logs.len().min(i64::MAX as usize) as i64;
+    if let Some(clock) = client.clock() {
+        run.clock_drift_ms = clock.drift.map(|drift| drift.num_milliseconds());
+        run.implausible = clock.implausible.min(i64::MAX as usize) as i64;
+    }
+
+    // compare the logs the database already knows about
+    let resync_due = match (settings.resync_pause, *last_resync) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(pause), Some(last)) => last.elapsed() >= pause,
+    };
+    if resync_due && opt.dry_run {
+        *last_resync = Some(Instant::now());
+        log::info!("skipping resync, a dry run only knows about the most recent log");
+    } else if resync_due {
+        *last_resync = Some(Instant::now());
+        match db.resync(&logs, false).await {
+            Ok(resync) if resync.is_empty() => log::info!("resync found no differences"),
+            Ok(resync) => log::warn!("resync fixed {}", resync),
+            Err(err) => log::warn!("couldn't resync logs: {:?}", err),
         }
+    }
+
+    // append all new logs to the database
+    //
+    // if that fails, try again with the next batch of logs
+    let appended = match db.append(&logs).await {
+        Ok(appended) => appended,
+        Err(err) => {
+            log::warn!("couldn't insert logs: {:?}", err);
+            return run.fail(fritz_app::poll::Failure::Database, &err);
+        }
+    };
+
+    log::info!("upserted {} logs", appended.upserted.len());
+
+    if settings.clear_logs && !logs.is_empty() && opt.dry_run {
+        log::info!("would clear {} logs on the FRITZ!Box", logs.len());
+    } else if settings.clear_logs && !logs.is_empty() {
+        if let Err(err) = archive_and_clear(client, db).await {
+            log::warn!("couldn't clear logs: {:?}", err);
+        }
+    }
+
+    run.succeed(&appended)
+}
+
+/// Open an in-memory database that logs what would be written.
+///
+/// If `DATABASE_URL` is set, the in-memory database starts out with the most
+/// recent log from it, so only logs that would actually be written are logged.
+async fn open_dry_run() -> anyhow::Result<fritz_app::db::Database> {
+    let Ok(db_url) = std::env::var("DATABASE_URL") else {
+        log::info!("dry run without DATABASE_URL, starting with an empty database");
+        return Ok(fritz_app::db::Database::open_dry_run(&[]));
+    };
+
+    let db = fritz_app::db::Database::open(&db_url)
+        .await
+        .context("open database")?;
+    let tail = db
+        .select_tail_log()
+        .await
+        .context("select most recent log")?;
+    db.close().await;
+
+    Ok(fritz_app::db::Database::open_dry_run(tail.as_slice()))
+}
+
+/// Clear the logs on the FRITZ!Box, but only if every log on it is in the
+/// database, and confirm the clear with another fetch.
+///
+/// Logs logged between the last fetch and clearing them are lost, so the logs
+/// are fetched and appended again right before clearing them.
+async fn archive_and_clear(
+    client: &fritz_app::api::Client,
+    db: &fritz_app::db::Database,
+) -> anyhow::Result<()> {
+    let tail = db.select_tail_log().await?;
+    let mut logs = client
+        .logs(tail.as_ref())
+        .await
+        .context("fetch logs before clearing")?;
+    logs.reverse();
+    let upserted = db.append_new_logs(&logs).await?;
+    log::info!("upserted {} logs before clearing", upserted.len());
 
-        log::info!("upserted {} logs", upserted);
+    let missing = db.missing_logs(&logs).await?;
+    if !missing.is_empty() {
+        anyhow::bail!("{} logs aren't in the database yet", missing.len());
     }
+
+    client.clear_logs().await.context("clear logs")?;
+
+    let tail = db.select_tail_log().await?;
+    let mut remaining = client
+        .logs(tail.as_ref())
+        .await
+        .context("fetch logs after clearing")?;
+    remaining.reverse();
+    let not_cleared = remaining
+        .iter()
+        .filter(|log| logs.iter().any(|archived| archived.is_same_entry(log)))
+        .count();
+    if not_cleared != 0 {
+        anyhow::bail!("{} archived logs are still on the FRITZ!Box", not_cleared);
+    }
+
+    db.insert_clear(logs.len()).await?;
+    log::info!("cleared {} archived logs on the FRITZ!Box", logs.len());
+
+    // the FRITZ!Box might have logged something since clearing
+    let upserted = db.append_new_logs(&remaining).await?;
+    log::info!("upserted {} logs", upserted.len());
+    Ok(())
 }
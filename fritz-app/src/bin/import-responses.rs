@@ -0,0 +1,163 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use fritz_app::api;
+use fritz_app::fritz::{check_clock, BoxTimezone, TimezoneSetting};
+use structopt::StructOpt;
+
+/// Replay responses saved with `FRITZBOX_SAVE_RESPONSE` into the database.
+///
+/// Only responses to `logs` requests are imported, from old to new, both from
+/// compressed segments and from the plaintext files of older versions.
+/// Importing the same files twice doesn't change the database.
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Folder containing the saved responses (`FRITZBOX_SAVE_RESPONSE_PATH`)
+    #[structopt(parse(from_os_str), long = "input-dir")]
+    input_dir: PathBuf,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    fritz_app::log::init().context("initialize logger")?;
+    let opt = Opt::from_args();
+
+    match dotenv::dotenv() {
+        Ok(path) => log::info!("loaded .env from {}", path.to_str().expect("utf-8")),
+        Err(err) => log::warn!("couldn't load .env file: {:?}", err),
+    };
+
+    if !std::fs::metadata(&opt.input_dir).is_ok_and(|m| m.is_dir()) {
+        anyhow::bail!("Input dir is not a directory");
+    }
+
+    // saved responses don't tell which timezone the FRITZ!Box was in
+    let timezone = match TimezoneSetting::try_from_env()? {
+        TimezoneSetting::Fixed(timezone) => timezone,
+        TimezoneSetting::Detect => {
+            anyhow::bail!("can't detect the timezone from saved responses, set FRITZBOX_TIMEZONE")
+        }
+    };
+    log::info!("parsing timestamps in {}", timezone);
+
+    let db_url = std::env::var("DATABASE_URL").context("load DATABASE_URL")?;
+    let db = fritz_app::db::Database::open(&db_url)
+        .await
+        .context("open database")?;
+
+    let api::recording::Listing {
+        files,
+        segments,
+        invalid: mut failed,
+    } = api::recording::list(&opt.input_dir).context("list saved responses")?;
+    let files = files
+        .into_iter()
+        .filter(|file| file.name == "logs")
+        .collect::<Vec<_>>();
+
+    log::info!(
+        "importing {} saved responses and {} segments",
+        files.len(),
+        segments.len()
+    );
+
+    let mut files = files.into_iter().peekable();
+    let mut upserted = 0;
+    for segment in segments {
+        let reader = match api::recording::SegmentReader::open(&segment.path) {
+            Ok(reader) => reader,
+            Err(err) => {
+                failed.push((segment.path, err));
+                continue;
+            }
+        };
+
+        for (index, recorded) in reader.enumerate() {
+            let recorded = match recorded {
+                Ok(recorded) => recorded,
+                Err(err) => {
+                    failed.push((segment.path.clone(), err));
+                    break;
+                }
+            };
+
+            // responses saved to their own file before this one
+            while let Some(file) = files.next_if(|file| file.datetime <= recorded.datetime) {
+                let text = tokio::fs::read_to_string(&file.path)
+                    .await
+                    .context("read saved response");
+                let source = file.path.to_string_lossy().into_owned();
+                let requested = file.datetime.to_utc();
+                match import(&db, &timezone, &source, requested, text).await {
+                    Ok(logs) => upserted += logs,
+                    Err(err) => failed.push((file.path, err)),
+                }
+            }
+
+            if recorded.name != "logs" {
+                continue;
+            }
+            let source = format!("response {} of {}", index + 1, segment.path.display());
+            let requested = recorded.datetime;
+            match import(&db, &timezone, &source, requested, Ok(recorded.body)).await {
+                Ok(logs) => upserted += logs,
+                Err(err) => failed.push((segment.path.clone(), err.context(source))),
+            }
+        }
+    }
+
+    for file in files {
+        let text = tokio::fs::read_to_string(&file.path)
+            .await
+            .context("read saved response");
+        let source = file.path.to_string_lossy().into_owned();
+        let requested = file.datetime.to_utc();
+        match import(&db, &timezone, &source, requested, text).await {
+            Ok(logs) => upserted += logs,
+            Err(err) => failed.push((file.path, err)),
+        }
+    }
+
+    for (path, err) in failed.iter() {
+        log::warn!("couldn't import {}: {:#}", path.to_string_lossy(), err);
+    }
+    log::info!(
+        "upserted {} logs, couldn't import {} responses",
+        upserted,
+        failed.len()
+    );
+
+    db.close().await;
+    Ok(())
+}
+
+/// Append the logs of a saved `logs` response received at `requested`.
+///
+/// Implausible timestamps are fixed like when polling, see [`check_clock`].
+///
+/// Returns the number of upserted logs.
+async fn import(
+    db: &fritz_app::db::Database,
+    timezone: &BoxTimezone,
+    source: &str,
+    requested: DateTime<Utc>,
+    text: anyhow::Result<String>,
+) -> anyhow::Result<usize> {
+    let tail = db.select_tail_log().await?;
+    let mut logs = api::Response::from_json(&text?)?.into_logs(timezone, tail.as_ref())?;
+    let clock = check_clock(&mut logs, requested);
+    if clock.implausible != 0 {
+        log::warn!(
+            "{} logs of {} have implausible timestamps, the newest log is {:?} ahead",
+            clock.implausible,
+            source,
+            clock.drift
+        );
+    }
+    logs.reverse();
+
+    let upserted = db.append_new_logs(&logs).await?.len();
+    log::info!("upserted {} logs from {}", upserted, source);
+    Ok(upserted)
+}
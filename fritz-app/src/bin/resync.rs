@@ -0,0 +1,53 @@
+use anyhow::Context;
+use structopt::StructOpt;
+
+/// Compare all logs on the FRITZ!Box against the database, insert missing
+/// logs, fix diverged repetitions and flag logs that are only in the database.
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Only report the differences, don't write anything
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    fritz_app::log::init().context("initialize logger")?;
+    let opt = Opt::from_args();
+
+    match dotenv::dotenv() {
+        Ok(path) => log::info!("loaded .env from {}", path.to_str().expect("utf-8")),
+        Err(err) => log::warn!("couldn't load .env file: {:?}", err),
+    };
+
+    let db_url = std::env::var("DATABASE_URL").context("load DATABASE_URL")?;
+    let db = fritz_app::db::Database::open(&db_url)
+        .await
+        .context("open database")?;
+
+    // a dry run shouldn't even record request metadata
+    let client = fritz_app::api::Client::new(
+        None,
+        None,
+        None,
+        None,
+        if opt.dry_run { None } else { Some(&db) },
+    )?;
+
+    let tail = db.select_tail_log().await.context("select tail log")?;
+    let mut logs = client.logs(tail.as_ref()).await.context("fetch logs")?;
+    logs.reverse();
+
+    let resync = db.resync(&logs, opt.dry_run).await.context("resync logs")?;
+    if resync.is_empty() {
+        log::info!("no differences found");
+    } else if opt.dry_run {
+        log::info!("would fix {}", resync);
+    } else {
+        log::info!("fixed {}", resync);
+    }
+
+    client.logout().await.context("logout")?;
+    db.close().await;
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use std::ops::Bound;
+
+use anyhow::Context;
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use structopt::StructOpt;
+
+/// Full-text search the messages of the logs in the database, best matches first.
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Words to search for, e.g. `NAS` or `"WLAN-Gerät" -abgemeldet` (Postgres)
+    #[structopt(required = true)]
+    text: Vec<String>,
+    /// Only logs logged at or after this date (`2024-06-28`) or time (RFC 3339)
+    #[structopt(long = "since", parse(try_from_str = parse_datetime))]
+    since: Option<DateTime<Utc>>,
+    /// Only logs logged before this date (`2024-06-28`) or time (RFC 3339)
+    #[structopt(long = "until", parse(try_from_str = parse_datetime))]
+    until: Option<DateTime<Utc>>,
+    /// Show at most this many logs
+    #[structopt(long = "limit", default_value = "20")]
+    limit: usize,
+}
+
+/// Parse a RFC 3339 timestamp, or a date which is the start of that day in
+/// the local timezone.
+fn parse_datetime(s: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+        return Ok(datetime.into());
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").context("parse date or time")?;
+    date.and_hms_opt(0, 0, 0)
+        .and_then(|datetime| datetime.and_local_timezone(Local).earliest())
+        .map(Into::into)
+        .context("start of day in local time")
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    fritz_app::log::init().context("initialize logger")?;
+    let opt = Opt::from_args();
+
+    match dotenv::dotenv() {
+        Ok(path) => log::info!("loaded .env from {}", path.to_str().expect("utf-8")),
+        Err(err) => log::warn!("couldn't load .env file: {:?}", err),
+    };
+
+    let db_url = std::env::var("DATABASE_URL").context("load DATABASE_URL")?;
+    let db = fritz_app::db::Database::open(&db_url)
+        .await
+        .context("open database")?;
+
+    let text = opt.text.join(" ");
+    let range = (
+        opt.since.map_or(Bound::Unbounded, Bound::Included),
+        opt.until.map_or(Bound::Unbounded, Bound::Excluded),
+    );
+    let hits = db
+        .search_logs(&text, range, opt.limit)
+        .await
+        .context("search logs")?;
+
+    log::info!("found {} logs matching {:?}", hits.len(), text);
+    for hit in hits {
+        println!(
+            "{} [{:>4}, {:>2}] {}",
+            hit.log.log.datetime.format("%Y-%m-%d %H:%M:%S"),
+            hit.log.log.message_id,
+            hit.log.log.category_id,
+            hit.snippet
+        );
+    }
+
+    db.close().await;
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Context;
+use fritz_app::simulator::{Config, Simulator};
+use structopt::StructOpt;
+
+/// Emulate the FRITZ!Box web API over HTTPS, e.g. to run the daemon against it.
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Address to listen on
+    #[structopt(long = "listen", default_value = "127.0.0.1:8443")]
+    listen: SocketAddr,
+    /// Username to log in with
+    #[structopt(long = "username", default_value = "fritz3713")]
+    username: String,
+    /// Password to log in with
+    #[structopt(long = "password", default_value = "password")]
+    password: String,
+    /// How many logs the FRITZ!Box holds before dropping the oldest
+    #[structopt(long = "capacity", default_value = "400")]
+    capacity: usize,
+    /// IANA timezone the FRITZ!Box logs in
+    #[structopt(long = "timezone", default_value = "Europe/Berlin")]
+    timezone: chrono_tz::Tz,
+    /// Write the generated certificate to this file, to use as `FRITZBOX_ROOT_CERT_PATH`
+    #[structopt(long = "cert-out")]
+    cert_out: Option<PathBuf>,
+    /// Log a message every this many seconds
+    #[structopt(long = "interval")]
+    interval: Option<u64>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    fritz_app::log::init().context("initialize logger")?;
+    let opt = Opt::from_args();
+
+    let simulator = Simulator::new(Config {
+        username: opt.username,
+        password: opt.password,
+        capacity: opt.capacity,
+        timezone: opt.timezone,
+        ..Config::default()
+    })
+    .context("create simulator")?;
+
+    if let Some(path) = opt.cert_out.as_ref() {
+        std::fs::write(path, simulator.cert_pem())
+            .with_context(|| format!("write certificate to {}", path.display()))?;
+        log::info!("wrote certificate to {}", path.display());
+    }
+
+    let server = simulator.serve(opt.listen).await.context("serve")?;
+    log::info!("simulating the FRITZ!Box on https://{}", server.domain());
+
+    if let Some(interval) = opt.interval {
+        let simulator = simulator.clone();
+        tokio::spawn(async move {
+            // cycle through a few message ids so some of them are repetitions
+            for message_id in (1..=5).cycle() {
+                tokio::time::sleep(Duration::from_secs(interval)).await;
+                let message = format!("Simulierte Meldung {}", message_id / 2);
+                simulator.push_log(simulator.now(), &message, message_id / 2, 1);
+            }
+        });
+    }
+
+    tokio::signal::ctrl_c().await.context("wait for ctrl-c")?;
+    log::info!("received ctrl-c, stopping");
+
+    drop(server);
+    Ok(())
+}
@@ -1,114 +1,126 @@
-use anyhow::Context;
-use sqlx::PgPool;
+use std::collections::HashMap;
+use std::ops::RangeBounds;
+use std::sync::Arc;
 
-use super::model::{Request, Update};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use futures_util::TryStreamExt;
+
+use super::event::Event;
+use super::log_query::{LogQuery, SearchHit, StoredLog};
+use super::memory::MemoryStorage;
+use super::model::{
+    Appended, Gap, LogRepetition, PingRollup, PollRun, Request, Resolution, Table, Update,
+};
+use super::postgres::{PostgresStorage, TimeSeriesMode};
+use super::request_query::{RequestQuery, RequestStats};
+use super::spool::{self, Entry, Spool};
+use super::sqlite::SqliteStorage;
+use super::storage::{Leadership, Storage, Transaction};
 use crate::{db, fritz};
 
+/// How many rows [`Database::prune`] deletes at once, so a large backlog of
+/// expired rows doesn't lock a table for long.
+const PRUNE_BATCH_SIZE: usize = 10_000;
+
 #[derive(Clone)]
 pub struct Database {
-    pool: PgPool,
+    storage: Arc<dyn Storage>,
+    spool: Option<Arc<Spool>>,
+    /// Oldest ping replayed from the spool that hasn't been rolled up per
+    /// resolution, see [`Database::take_late_ping`]
+    late_pings: Arc<parking_lot::Mutex<HashMap<Resolution, DateTime<Utc>>>>,
 }
 
 impl Database {
-    #[allow(clippy::unused_async)]
-    pub async fn open_in_memory() -> anyhow::Result<Database> {
-        unimplemented!("open_in_memory is not supported for postgres")
+    fn new(storage: Arc<dyn Storage>) -> Database {
+        Database {
+            storage,
+            spool: None,
+            late_pings: Arc::default(),
+        }
+    }
+
+    /// Open a fresh database that only lives in memory, without any SQL engine.
+    pub fn open_in_memory() -> Database {
+        Database::new(Arc::new(MemoryStorage::new()))
     }
+
+    /// Open a fresh database that only lives in memory, starts out with the
+    /// given logs and logs everything that's written to it.
+    ///
+    /// Logs must be sorted from **old to new** so the oldest log is at index 0.
+    pub fn open_dry_run(logs: &[fritz::Log]) -> Database {
+        Database::new(Arc::new(MemoryStorage::dry_run(logs)))
+    }
+
+    /// Open a fresh SQLite database that only lives in memory.
+    pub async fn open_sqlite_in_memory() -> anyhow::Result<Database> {
+        let storage = SqliteStorage::open_in_memory().await?;
+        Ok(Database::new(Arc::new(storage)))
+    }
+
+    /// Open the database at `url` and run the migrations.
+    ///
+    /// The storage backend is chosen by the scheme of the url, `postgres://`
+    /// (or `postgresql://`) for Postgres and `sqlite://` for SQLite.
     pub async fn open(url: &str) -> anyhow::Result<Database> {
-        let pool = PgPool::connect(url).await.context("connect to sqlite")?;
-        Self::migrate(&pool).await?;
+        let storage: Arc<dyn Storage> = match url.split_once(':').map(|(scheme, _)| scheme) {
+            Some("postgres" | "postgresql") => Arc::new(PostgresStorage::open(url).await?),
+            Some("sqlite") => Arc::new(SqliteStorage::open(url).await?),
+            _ => anyhow::bail!("unsupported database url, expected postgres:// or sqlite://"),
+        };
+
+        Ok(Database::new(storage))
+    }
 
-        Ok(Database { pool })
+    /// Spool logs, pings and request metadata to local disk instead of
+    /// failing while the database is unavailable, see [`Spool`].
+    ///
+    /// Spooled writes are replayed in order by [`db::replay_loop`], until
+    /// then every write is spooled as well.
+    pub fn with_spool(mut self, spool: Spool) -> Database {
+        self.spool = Some(Arc::new(spool));
+        self
+    }
+
+    /// Whether writes are spooled while the database is unavailable.
+    pub const fn has_spool(&self) -> bool {
+        self.spool.is_some()
     }
 
     pub async fn close(self) {
-        self.pool.close().await;
+        self.storage.close().await;
     }
 
-    async fn migrate(pool: &PgPool) -> anyhow::Result<()> {
-        sqlx::migrate!("./migrations/")
-            .run(pool)
-            .await
-            .context("migrate database")
+    async fn begin(&self) -> anyhow::Result<Box<dyn Transaction>> {
+        self.storage.begin().await
     }
 
     pub async fn clear_logs(&self) -> anyhow::Result<()> {
-        sqlx::query!(r#"DELETE FROM "logs""#)
-            .execute(&self.pool)
-            .await
-            .context("clear logs")
-            .map(|_| ())
+        self.storage.clear_logs().await
     }
 
     /// Appends a log to the database without checking for consistency
     pub async fn insert_log(&self, log: &fritz::Log) -> anyhow::Result<()> {
-        let log = super::Log::from(log.clone());
-
-        sqlx::query!(
-            r#"
-        INSERT INTO "logs"
-        (
-            "datetime",
-            "message",
-            "message_id",
-            "category_id",
-            "repetition_datetime",
-            "repetition_count"
-        )
-        VALUES ($1, $2, $3, $4, $5, $6)
-            "#,
-            /* 1 */ log.datetime,
-            /* 2 */ log.message,
-            /* 3 */ log.message_id,
-            /* 4 */ log.category_id,
-            /* 5 */ log.repetition_datetime,
-            /* 6 */ log.repetition_count
-        )
-        .execute(&self.pool)
-        .await
-        .context("insert log")
-        .map(|_| ())
+        self.insert_logs(std::slice::from_ref(log)).await
     }
 
     /// Append logs to the database without checking for consistency
     pub async fn insert_logs(&self, logs: &[fritz::Log]) -> anyhow::Result<()> {
-        for log in logs {
-            self.insert_log(log).await?;
-        }
-        Ok(())
+        let mut tx = self.begin().await?;
+        tx.insert_logs(logs, false).await?;
+        tx.publish(&inserted_events(logs, false)).await?;
+        tx.commit().await
     }
 
     pub async fn count_logs(&self) -> anyhow::Result<usize> {
-        let count = sqlx::query!(
-            r#"
-        SELECT count(*) as "count"
-        FROM "logs"
-            "#
-        )
-        .fetch_one(&self.pool)
-        .await
-        .context("fetch row count")?
-        .count
-        .context("missing row count")?;
-
-        usize::try_from(count).context("negative row count")
+        self.storage.count_logs().await
     }
 
     pub async fn is_empty(&self) -> anyhow::Result<bool> {
-        // https://dba.stackexchange.com/a/223286
-        let count = sqlx::query!(
-            r#"
-        SELECT count(*) as count
-        FROM (SELECT 0 from logs LIMIT 1)
-            "#
-        )
-        .fetch_one(&self.pool)
-        .await
-        .context("check database empty")?
-        .count
-        .context("missing row count")?;
-
-        Ok(count == 0)
+        self.storage.is_empty().await
     }
 
     /// Select the `limit` latest logs offset by `offset`.
@@ -117,34 +129,16 @@ impl Database {
         offset: usize,
         limit: usize,
     ) -> anyhow::Result<Vec<fritz::Log>> {
-        let offset = i64::try_from(offset).context("cast offset as i64")?;
-        let limit = i64::try_from(limit).context("cast limit as i64")?;
-        sqlx::query_as!(
-            db::Log,
-            r#"
-        SELECT "id",
-               "datetime",
-               "message",
-               "message_id",
-               "category_id",
-               "repetition_datetime",
-               "repetition_count"
-        FROM "logs"
-        ORDER BY "id" DESC
-        LIMIT $1
-        OFFSET $2
-            "#,
-            /* 1 */ limit,
-            /* 2 */ offset,
-        )
-        .fetch_all(&self.pool)
-        .await
-        .context("fetch logs")?
-        .into_iter()
-        .map(|log| log.try_into())
-        .collect::<Result<Vec<_>, _>>()
+        self.begin().await?.select_latest_logs(offset, limit).await
+    }
+
+    /// Select the most recently appended log, ignoring logs inserted by a resync.
+    pub async fn select_tail_log(&self) -> anyhow::Result<Option<fritz::Log>> {
+        self.begin().await?.select_tail_log().await
     }
 
+    /// Select the latest inserted log, which might be an older log inserted by
+    /// a resync, see [`Database::select_tail_log`].
     pub async fn select_latest_log(&self) -> anyhow::Result<Option<fritz::Log>> {
         Ok(self
             .select_latest_logs(0, 1)
@@ -155,215 +149,587 @@ impl Database {
     }
 
     pub async fn update_log(&self, old: &fritz::Log, new: &fritz::Log) -> anyhow::Result<()> {
-        let old_log = super::Log::from(old.clone());
-        let new_log = super::Log::from(new.clone());
-
-        let rows_affected = sqlx::query!(
-            r#"
-        UPDATE "logs"
-        SET "datetime"            = $1,
-            "message"             = $2,
-            "message_id"          = $3,
-            "category_id"         = $4,
-            "repetition_datetime" = $5,
-            "repetition_count"    = $6
-        WHERE "datetime"    = $7 AND
-              "message_id"  = $8 AND
-              "category_id" = $9
-            "#,
-            /* 1 */ new_log.datetime,
-            /* 2 */ new_log.message,
-            /* 3 */ new_log.message_id,
-            /* 4 */ new_log.category_id,
-            /* 5 */ new_log.repetition_datetime,
-            /* 6 */ new_log.repetition_count,
-            /* 7 */ old_log.datetime,
-            /* 8 */ old_log.message_id,
-            /* 9 */ old_log.category_id,
-        )
-        .execute(&self.pool)
-        .await
-        .context("update log")?
-        .rows_affected();
-
-        if rows_affected != 1 {
-            log::error!(
-                "invalid number of rows affected (got {}, expected 1)",
-                rows_affected
-            );
-        }
+        let mut tx = self.begin().await?;
+        check_updated(tx.update_log(old, new).await?);
+        tx.publish(&[Event::LogUpdated {
+            old: old.clone(),
+            new: new.clone(),
+        }])
+        .await?;
+        tx.commit().await
+    }
 
-        Ok(())
+    /// Select the logs matching `query`.
+    ///
+    /// Use [`Database::stream_logs`] for large results.
+    pub async fn query_logs(&self, query: &LogQuery) -> anyhow::Result<Vec<StoredLog>> {
+        self.stream_logs(query).try_collect().await
     }
 
-    /// Appends the given logs to the database.
+    /// Select the logs matching `query` without loading all of them at once.
+    pub fn stream_logs(&self, query: &LogQuery) -> BoxStream<'_, anyhow::Result<StoredLog>> {
+        self.storage.stream_logs(query.clone())
+    }
+
+    /// Full-text search the messages of the logs that have last been logged
+    /// within `range`, returning at most `limit` logs with the best matches first.
+    ///
+    /// Postgres stems german words, so `Anmeldungen` also finds `Anmeldung`,
+    /// and understands quoted phrases, `or` and `-` to exclude words. The other
+    /// backends find logs containing every word of `text`, ignoring case.
+    pub async fn search_logs(
+        &self,
+        text: &str,
+        range: impl RangeBounds<DateTime<Utc>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<SearchHit>> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        self.storage.search_logs(text, range, limit).await
+    }
+
+    /// Select the repetition history of the log with the given id, from old to new.
+    pub async fn select_log_repetitions(&self, log_id: i64) -> anyhow::Result<Vec<LogRepetition>> {
+        self.storage.select_log_repetitions(log_id).await
+    }
+
+    /// Appends the given logs to the database and records the update, all
+    /// within a single transaction.
     ///
     /// Logs must be sorted from **old to new** so the oldest log is at index 0.
     ///
-    /// Returns a slice over the inserted or updated elements.
-    pub async fn append_new_logs<'a>(
-        &self,
-        logs: &'a [fritz::Log],
-    ) -> anyhow::Result<&'a [fritz::Log]> {
-        // Database: [3,2,1]
-        //
-        // [4,5]   -> [5,4,3,2,1]: All logs are new
-        // [1,2]   ->     [3,2,1]: All logs are old
-        // [2,3,4] ->   [4,3,2,1]: Some logs are new
-
-        // for some reason, the FRITZ!Box premium software updates the repetition of a existing
-        // log entry after inserting a new log entry which shouldn't happen... because of that
-        // we need to compare the earliest timestamp of the earlier log entry with the latest
-        // timestamp of the following entry.
-        if !logs
-            .windows(2)
-            .all(|w| w[0].earliest_timestamp_utc() <= w[1].latest_timestamp_utc())
-        {
-            log::warn!("called append_new_logs with unsorted logs: {:#?}", logs);
-            return Err(anyhow::anyhow!("logs must be sorted from old to new"));
+    /// Returns the inserted or updated logs, none if they've been spooled.
+    pub async fn append_new_logs(&self, logs: &[fritz::Log]) -> anyhow::Result<Vec<fritz::Log>> {
+        Ok(self.append(logs).await?.upserted)
+    }
+
+    /// Like [`Database::append_new_logs`], but returns everything appending
+    /// the logs changed.
+    pub async fn append(&self, logs: &[fritz::Log]) -> anyhow::Result<Appended> {
+        let entry = || Entry::Logs {
+            logs: logs.to_vec(),
+        };
+        let spooled = Appended {
+            spooled: true,
+            ..Appended::default()
+        };
+        if self.spool_if_pending(entry)? {
+            return Ok(spooled);
         }
+        let result = self.append_logs(logs).await;
+        Ok(self.spool_if_unavailable(result, entry)?.unwrap_or(spooled))
+    }
+
+    async fn append_logs(&self, logs: &[fritz::Log]) -> anyhow::Result<Appended> {
+        let mut tx = self.begin().await?;
 
         // fetch the most recent log in the database to compare against
-        let Some(newest_db_log) = self.select_latest_log().await? else {
-            // the database is empty, all logs must be new
-            self.insert_logs(logs).await?;
-            return Ok(logs);
+        let newest_db_log = tx.select_tail_log().await?;
+        let mut ops = fritz::reconcile(newest_db_log.as_ref(), logs)?;
+
+        // if the logs on the FRITZ!Box have been cleared on purpose after the
+        // most recent log has been archived, nothing is missing
+        let has_gap = ops.iter().any(|op| matches!(op, fritz::Op::Gap { .. }));
+        if has_gap && tx.select_tail_cleared().await? {
+            ops.retain(|op| {
+                !matches!(
+                    op,
+                    fritz::Op::Gap {
+                        kind: fritz::GapKind::Wrapped | fritz::GapKind::Cleared,
+                        ..
+                    }
+                )
+            });
+        }
+
+        let mut events = Vec::new();
+        let upserted = apply_ops(&mut *tx, &ops, &mut events).await?;
+
+        tx.insert_update(&Update {
+            id: None,
+            datetime: Utc::now(),
+            upserted_rows: upserted.len().min(i64::MAX as usize) as i64,
+        })
+        .await?;
+
+        tx.publish(&events).await?;
+        tx.commit().await?;
+
+        let count = |is_op: fn(&fritz::Op) -> bool| ops.iter().filter(|op| is_op(op)).count();
+        Ok(Appended {
+            upserted,
+            inserted: count(|op| matches!(op, fritz::Op::Insert(_))),
+            updated: count(|op| matches!(op, fritz::Op::UpdateRepetition { .. })),
+            gap: count(|op| matches!(op, fritz::Op::Gap { .. })) != 0,
+            spooled: false,
+        })
+    }
+
+    /// Apply the operations returned by [`fritz::reconcile`] in order, within
+    /// a single transaction.
+    ///
+    /// Returns the inserted or updated logs.
+    pub async fn apply_ops(&self, ops: &[fritz::Op]) -> anyhow::Result<Vec<fritz::Log>> {
+        let mut tx = self.begin().await?;
+        let mut events = Vec::new();
+        let upserted = apply_ops(&mut *tx, ops, &mut events).await?;
+        tx.publish(&events).await?;
+        tx.commit().await?;
+        Ok(upserted)
+    }
+
+    /// Compare the logs fetched from the FRITZ!Box against the database,
+    /// insert missing logs, fix diverged repetitions and flag logs that are
+    /// only in the database, all within a single transaction.
+    ///
+    /// Logs newer than the most recent log in the database are ignored, use
+    /// [`Database::append_new_logs`] for them. If `dry_run` is set, nothing is
+    /// written.
+    ///
+    /// Logs must be sorted from **old to new** so the oldest log is at index 0.
+    pub async fn resync(
+        &self,
+        logs: &[fritz::Log],
+        dry_run: bool,
+    ) -> anyhow::Result<fritz::Resync> {
+        let mut tx = self.begin().await?;
+
+        let tail = tx.select_tail_log().await?;
+        let db_logs = match logs.first() {
+            Some(oldest) => {
+                tx.select_logs_since(oldest.earliest_datetime().into())
+                    .await?
+            }
+            None => Vec::new(),
         };
+        let resync = fritz::resync(tail.as_ref(), &db_logs, logs);
 
-        // check if _all_ new logs are actually old
-        //
-        // if the newest log in the argument is older than the latest
-        // log in the database, all logs in the argument must be old.
-        if logs.last().map_or(false, |log| {
-            log.latest_timestamp_utc() < newest_db_log.latest_timestamp_utc()
-        }) {
-            return Ok(&[]);
+        if dry_run || resync.is_empty() {
+            return Ok(resync);
         }
 
-        // check if _all_ new logs are new
-        //
-        // if the oldest log in the argument is newer than the latest
-        // log in the database, all logs in the argument must be new.
-        if logs.first().map_or(false, |log| {
-            log.earliest_timestamp_utc() > newest_db_log.latest_timestamp_utc()
-        }) {
-            self.insert_logs(logs).await?;
-            return Ok(logs);
+        tx.insert_logs(&resync.missing, true)
+            .await
+            .context("insert missing logs")?;
+        for (db_log, box_log) in resync.diverged.iter() {
+            let rows_affected = tx
+                .update_log(db_log, box_log)
+                .await
+                .context("update diverged log")?;
+            check_updated(rows_affected);
+        }
+        let now = Utc::now();
+        for log in resync.orphaned.iter() {
+            tx.insert_orphan(log, now).await?;
         }
 
-        // this index is at most `logs.len() - 1` (obviously)
-        let most_recent_index = logs
+        let mut events = inserted_events(&resync.missing, true);
+        events.extend(
+            resync
+                .diverged
+                .iter()
+                .map(|(db_log, box_log)| Event::LogUpdated {
+                    old: db_log.clone(),
+                    new: box_log.clone(),
+                }),
+        );
+        tx.publish(&events).await?;
+        tx.commit().await?;
+        Ok(resync)
+    }
+
+    /// Return the logs fetched from the FRITZ!Box that aren't in the database
+    /// with the same repetition, see [`fritz::Log::is_same_repetition`].
+    ///
+    /// Logs must be sorted from **old to new** so the oldest log is at index 0.
+    pub async fn missing_logs(&self, logs: &[fritz::Log]) -> anyhow::Result<Vec<fritz::Log>> {
+        let Some(oldest) = logs.first() else {
+            return Ok(Vec::new());
+        };
+        let db_logs = self
+            .begin()
+            .await?
+            .select_logs_since(oldest.earliest_datetime().into())
+            .await?;
+
+        Ok(logs
             .iter()
-            .position(|log| {
-                log.earliest_timestamp_utc() == newest_db_log.earliest_timestamp_utc()
-                    && log.message_id == newest_db_log.message_id
-                    && log.category_id == newest_db_log.category_id
-            })
-            .with_context(|| {
-                format!(
-                    "couldn't find most recent db log in logs argument \
-                        (newest_db_log: {:#?}, new_logs: {:#?})",
-                    newest_db_log, logs
-                )
-            })?;
+            .filter(|log| !db_logs.iter().any(|db_log| db_log.is_same_repetition(log)))
+            .cloned()
+            .collect())
+    }
 
-        let candidates = logs.split_at(most_recent_index).1;
-        let first_candidate = candidates.first().expect("at least one candidate");
-        let update_most_recent = first_candidate.repetition != newest_db_log.repetition;
+    /// Record that the logs on the FRITZ!Box have been cleared after the
+    /// most recent log in the database and `archived_logs` logs before it
+    /// have been archived.
+    ///
+    /// Afterwards, [`Database::append_new_logs`] doesn't record a gap after the
+    /// most recent log.
+    pub async fn insert_clear(&self, archived_logs: usize) -> anyhow::Result<()> {
+        let archived_logs = i64::try_from(archived_logs).context("cast archived logs as i64")?;
+        let mut tx = self.begin().await?;
+        tx.insert_clear(Utc::now(), archived_logs).await?;
+        tx.commit().await
+    }
 
-        // if the repetition changed, update it in the database
-        if update_most_recent {
-            self.update_log(&newest_db_log, first_candidate)
-                .await
-                .context("update most recent db log")?;
+    /// Insert a gap unless the same gap has already been recorded.
+    pub async fn insert_gap(&self, gap: &Gap) -> anyhow::Result<()> {
+        let mut tx = self.begin().await?;
+        tx.insert_gap(gap).await?;
+        tx.commit().await
+    }
+
+    /// Delete all rows from `table` that have been recorded before `before`,
+    /// in batches of [`PRUNE_BATCH_SIZE`] rows.
+    ///
+    /// Returns the number of deleted rows.
+    pub async fn prune(&self, table: Table, before: DateTime<Utc>) -> anyhow::Result<u64> {
+        let mut pruned = 0;
+        loop {
+            let batch = self.storage.prune(table, before, PRUNE_BATCH_SIZE).await?;
+            pruned += batch;
+            if batch < PRUNE_BATCH_SIZE as u64 {
+                return Ok(pruned);
+            }
+            // let pings and updates through between batches
+            tokio::task::yield_now().await;
         }
+    }
 
-        // add all new logs to the database
-        self.insert_logs(&candidates[1..])
-            .await
-            .context("insert new logs")?;
+    /// Convert the time-series tables (`ping`, `requests`, `ping_1m` and
+    /// `ping_1h`) into TimescaleDB hypertables or tables partitioned by month.
+    /// Tables that have already been converted are skipped.
+    ///
+    /// Returns the mode that has been used, never [`TimeSeriesMode::Auto`].
+    pub async fn convert_time_series(
+        &self,
+        mode: TimeSeriesMode,
+    ) -> anyhow::Result<TimeSeriesMode> {
+        self.storage.convert_time_series(mode).await
+    }
 
-        // if we updated the most recent log, include it in the list
-        Ok(if update_most_recent {
-            candidates
-        } else {
-            &candidates[1..]
-        })
+    /// Try to become the only instance writing to the database, `None` if
+    /// another instance holds the lock with the given key.
+    ///
+    /// Only Postgres is shared between processes, other backends always
+    /// grant leadership.
+    pub async fn try_acquire_leadership(
+        &self,
+        key: i64,
+    ) -> anyhow::Result<Option<Box<dyn Leadership>>> {
+        self.storage.try_acquire_leadership(key).await
     }
 
     pub async fn insert_request(&self, req: &Request) -> anyhow::Result<()> {
-        sqlx::query!(
-            r#"
-        INSERT INTO "requests"
-        (
-            "datetime",
-            "name",
-            "url",
-            "method",
-            "duration_ms",
-            "response_code",
-            "session_id"
-        )
-        VALUES ($1, $2, $3, $4, $5, $6, $7)
-            "#,
-            /* 1 */ req.datetime,
-            /* 2 */ req.name,
-            /* 3 */ req.url,
-            /* 4 */ req.method,
-            /* 5 */ req.duration_ms,
-            /* 6 */ req.response_code,
-            /* 7 */ req.session_id,
-        )
-        .execute(&self.pool)
-        .await
-        .context("insert request")?;
+        let entry = || Entry::Request(req.clone());
+        if self.spool_if_pending(entry)? {
+            return Ok(());
+        }
+        let result = self.storage.insert_request(req).await;
+        self.spool_if_unavailable(result, entry).map(|_| ())
+    }
 
-        Ok(())
+    /// Select the metadata of the requests to the FRITZ!Box matching `query`,
+    /// from old to new.
+    pub async fn query_requests(&self, query: &RequestQuery) -> anyhow::Result<Vec<Request>> {
+        self.storage.select_requests(query).await
     }
 
-    pub async fn insert_update(&self, update: &Update) -> anyhow::Result<()> {
-        sqlx::query!(
-            r#"
-        INSERT INTO "updates"
-        (
-            "datetime",
-            "upserted_rows"
-        )
-        VALUES ($1, $2)
-            "#,
-            /* 1 */ update.datetime,
-            /* 2 */ update.upserted_rows,
-        )
-        .execute(&self.pool)
-        .await
-        .context("insert update")?;
+    /// Summarize the requests matching `query` per name, e.g. how often the
+    /// FRITZ!Box timed out and how long it took to answer.
+    pub async fn request_stats(&self, query: &RequestQuery) -> anyhow::Result<Vec<RequestStats>> {
+        Ok(RequestStats::of(&self.query_requests(query).await?))
+    }
 
-        Ok(())
+    pub async fn insert_poll_run(&self, run: &PollRun) -> anyhow::Result<()> {
+        let entry = || Entry::PollRun(run.clone());
+        if self.spool_if_pending(entry)? {
+            return Ok(());
+        }
+        let result = self.storage.insert_poll_run(run).await;
+        self.spool_if_unavailable(result, entry).map(|_| ())
+    }
+
+    /// Select the poll runs started at or after `since` and before `until`,
+    /// from old to new.
+    pub async fn select_poll_runs(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<PollRun>> {
+        self.storage.select_poll_runs(since, until).await
+    }
+
+    pub async fn insert_update(&self, update: &Update) -> anyhow::Result<()> {
+        let mut tx = self.begin().await?;
+        tx.insert_update(update).await?;
+        tx.commit().await
     }
 
     pub async fn insert_ping(&self, ping: &db::Ping) -> anyhow::Result<()> {
-        sqlx::query!(
-            r#"
-        INSERT INTO "ping"
-        (
-            "datetime",
-            "target",
-            "duration_ms",
-            "ttl",
-            "bytes"
-        )
-        VALUES ($1, $2, $3, $4, $5)
-            "#,
-            /* 1 */ ping.datetime,
-            /* 2 */ ping.target,
-            /* 3 */ ping.duration_ms,
-            /* 4 */ ping.ttl,
-            /* 5 */ ping.bytes,
-        )
-        .execute(&self.pool)
-        .await
-        .context("insert ping")?;
+        let entry = || Entry::Ping(ping.clone());
+        if self.spool_if_pending(entry)? {
+            return Ok(());
+        }
+        let result = self.storage.insert_ping(ping).await;
+        self.spool_if_unavailable(result, entry).map(|_| ())
+    }
 
+    /// Select the pings of all targets recorded at or after `since` and before
+    /// `until`, from old to new.
+    pub async fn select_pings(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<db::Ping>> {
+        self.storage.select_pings(since, until).await
+    }
+
+    /// Select when the first ping at or after `since` has been recorded.
+    pub async fn select_next_ping_datetime(
+        &self,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.storage.select_next_ping_datetime(since).await
+    }
+
+    /// Select the start of the most recent bucket of `resolution` that has
+    /// been rolled up.
+    pub async fn select_latest_ping_rollup_datetime(
+        &self,
+        resolution: Resolution,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.storage
+            .select_latest_ping_rollup_datetime(resolution)
+            .await
+    }
+
+    /// Take the oldest ping that has been replayed from the spool since the
+    /// last call for `resolution`.
+    ///
+    /// Its bucket might have been rolled up before the ping was replayed, so
+    /// it has to be rolled up again.
+    pub fn take_late_ping(&self, resolution: Resolution) -> Option<DateTime<Utc>> {
+        self.late_pings.lock().remove(&resolution)
+    }
+
+    /// Roll up the bucket of `resolution` containing `datetime` again, e.g.
+    /// because the ping taken by [`Database::take_late_ping`] couldn't be
+    /// rolled up after all.
+    pub fn mark_late_ping(&self, resolution: Resolution, datetime: DateTime<Utc>) {
+        self.late_pings
+            .lock()
+            .entry(resolution)
+            .and_modify(|late| *late = (*late).min(datetime))
+            .or_insert(datetime);
+    }
+
+    /// Insert rollups, replacing rollups of the same target and bucket.
+    pub async fn upsert_ping_rollups(
+        &self,
+        resolution: Resolution,
+        rollups: &[PingRollup],
+    ) -> anyhow::Result<()> {
+        self.storage
+            .upsert_ping_rollups(resolution, rollups)
+            .await?;
+        self.publish(&Event::outages(resolution, rollups)).await;
         Ok(())
     }
+
+    /// Receive the events of the changes committed from now on, e.g. new
+    /// logs, gaps and outages.
+    ///
+    /// With Postgres, this includes the changes of every instance writing to
+    /// the database, events sent while the connection is lost are missed.
+    /// Other backends only include the changes of this process.
+    pub async fn subscribe(&self) -> anyhow::Result<BoxStream<'static, anyhow::Result<Event>>> {
+        self.storage.subscribe().await
+    }
+
+    /// Send events of changes committed outside of a transaction to the
+    /// subscribers. The changes have been committed already, so failing to
+    /// send them is only logged.
+    async fn publish(&self, events: &[Event]) {
+        if events.is_empty() {
+            return;
+        }
+        if let Err(err) = self.storage.publish(events).await {
+            log::warn!("couldn't publish {} events: {:?}", events.len(), err);
+        }
+    }
+
+    /// Select the rollups of `resolution` whose bucket starts within `range`,
+    /// optionally only of one target, from old to new.
+    pub async fn select_ping_rollups(
+        &self,
+        resolution: Resolution,
+        target: Option<&str>,
+        range: impl RangeBounds<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<PingRollup>> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        self.storage
+            .select_ping_rollups(resolution, target, range)
+            .await
+    }
+
+    /// Replay the writes that have been spooled while the database was
+    /// unavailable, in order.
+    ///
+    /// Returns the number of replayed writes.
+    pub async fn replay_spool(&self) -> anyhow::Result<usize> {
+        let Some(spool) = self.spool.as_ref() else {
+            return Ok(0);
+        };
+
+        let mut replayed = 0;
+        while let Some((entry, next)) = spool.peek()? {
+            let result = match &entry {
+                Entry::Logs { logs } => self.append_logs(logs).await.map(|_| ()),
+                Entry::Ping(ping) => self.storage.insert_ping(ping).await.map(|()| {
+                    for resolution in Resolution::ALL {
+                        self.mark_late_ping(resolution, ping.datetime);
+                    }
+                }),
+                Entry::Request(req) => self.storage.insert_request(req).await,
+                Entry::PollRun(run) => self.storage.insert_poll_run(run).await,
+            };
+            match result {
+                Ok(()) => replayed += 1,
+                Err(err) if spool::is_unavailable(&err) => return Err(err),
+                Err(err) => {
+                    log::error!("database rejected spooled {}: {:?}", entry, err);
+                    spool.reject(&entry, &err)?;
+                }
+            }
+            spool.advance(next)?;
+        }
+        Ok(replayed)
+    }
+
+    /// Spool a write instead of writing it while earlier writes are still
+    /// spooled, so they're replayed in order.
+    ///
+    /// Returns whether the write has been spooled.
+    fn spool_if_pending(&self, entry: impl FnOnce() -> Entry) -> anyhow::Result<bool> {
+        match self.spool.as_ref() {
+            Some(spool) if spool.is_pending() => {
+                spool.push(&entry())?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Spool a write if `result` failed because the database is unavailable.
+    ///
+    /// Returns `None` if the write has been spooled.
+    fn spool_if_unavailable<T>(
+        &self,
+        result: anyhow::Result<T>,
+        entry: impl FnOnce() -> Entry,
+    ) -> anyhow::Result<Option<T>> {
+        match (result, self.spool.as_ref()) {
+            (Err(err), Some(spool)) if spool::is_unavailable(&err) => {
+                let entry = entry();
+                spool
+                    .push(&entry)
+                    .with_context(|| format!("spool write that failed with {:#}", err))?;
+                log::warn!("database is unavailable, spooled {}: {:#}", entry, err);
+                Ok(None)
+            }
+            (result, _) => result.map(Some),
+        }
+    }
+}
+
+fn check_updated(rows_affected: i64) {
+    if rows_affected != 1 {
+        log::error!(
+            "invalid number of rows affected (got {}, expected 1)",
+            rows_affected
+        );
+    }
+}
+
+/// Apply the operations returned by [`fritz::reconcile`] in order.
+///
+/// Consecutive inserts are batched into a single statement.
+async fn apply_ops(
+    tx: &mut dyn Transaction,
+    ops: &[fritz::Op],
+    events: &mut Vec<Event>,
+) -> anyhow::Result<Vec<fritz::Log>> {
+    let mut upserted = Vec::with_capacity(ops.len());
+    let mut inserts = Vec::new();
+
+    for op in ops {
+        if let fritz::Op::Insert(log) = op {
+            inserts.push(log.clone());
+            continue;
+        }
+
+        if !inserts.is_empty() {
+            tx.insert_logs(&inserts, false)
+                .await
+                .context("insert new logs")?;
+            events.extend(inserted_events(&inserts, false));
+            upserted.append(&mut inserts);
+        }
+
+        match op {
+            fritz::Op::Insert(_) => unreachable!("inserts are batched"),
+            fritz::Op::UpdateRepetition { old, new } => {
+                let rows_affected = tx
+                    .update_log(old, new)
+                    .await
+                    .context("update most recent db log")?;
+                check_updated(rows_affected);
+                events.push(Event::LogUpdated {
+                    old: old.clone(),
+                    new: new.clone(),
+                });
+                upserted.push(new.clone());
+            }
+            fritz::Op::Gap {
+                kind,
+                after,
+                before,
+            } => {
+                log::warn!(
+                    "logs might be missing between {} and {:?} ({})",
+                    after,
+                    before,
+                    kind
+                );
+                let gap = Gap {
+                    id: None,
+                    datetime: Utc::now(),
+                    kind: kind.to_string(),
+                    after_datetime: (*after).into(),
+                    before_datetime: before.map(Into::into),
+                };
+                tx.insert_gap(&gap).await?;
+                events.push(Event::Gap(gap));
+            }
+        }
+    }
+
+    if !inserts.is_empty() {
+        tx.insert_logs(&inserts, false)
+            .await
+            .context("insert new logs")?;
+        events.extend(inserted_events(&inserts, false));
+        upserted.append(&mut inserts);
+    }
+
+    Ok(upserted)
+}
+
+fn inserted_events(logs: &[fritz::Log], resynced: bool) -> Vec<Event> {
+    logs.iter()
+        .map(|log| Event::LogInserted {
+            log: log.clone(),
+            resynced,
+        })
+        .collect()
 }
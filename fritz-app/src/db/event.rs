@@ -0,0 +1,95 @@
+//! Change feed of what has been written to the database, so other services
+//! can react to new logs, gaps and outages without polling.
+//!
+//! Postgres sends every event as a JSON `NOTIFY` payload on [`EVENT_CHANNEL`]
+//! to every listener, e.g. `LISTEN fritz_events;` in `psql`, within the
+//! transaction that wrote the change, so it's delivered once that commits.
+//! Other backends only notify subscribers within the same process.
+
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use tokio::sync::broadcast;
+
+use super::model::{Gap, PingRollup, Resolution};
+use crate::fritz;
+
+/// Postgres channel events are sent on
+pub const EVENT_CHANNEL: &str = "fritz_events";
+
+/// How many events a subscriber of a backend without `NOTIFY` can fall
+/// behind before it misses events
+const LOCAL_CAPACITY: usize = 1024;
+
+/// A change that has been committed, serialized as JSON with the variant in
+/// `"event"`, e.g. `{"event":"log_inserted","log":{...},"resynced":false}`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A log has been appended, or inserted by a resync if `resynced` is set
+    LogInserted { log: fritz::Log, resynced: bool },
+    /// A log has been updated, e.g. because it has been repeated
+    LogUpdated { old: fritz::Log, new: fritz::Log },
+    /// Logs might be missing
+    Gap(Gap),
+    /// No ping to `target` has been answered within the minute starting at
+    /// `datetime`, even though `count` pings have been sent
+    Outage {
+        target: String,
+        datetime: DateTime<Utc>,
+        count: i64,
+    },
+}
+
+impl Event {
+    /// Outages within `rollups` of `resolution`.
+    pub(super) fn outages(resolution: Resolution, rollups: &[PingRollup]) -> Vec<Event> {
+        if resolution != Resolution::Minute {
+            return Vec::new();
+        }
+        rollups
+            .iter()
+            .filter(|rollup| rollup.count > 0 && rollup.loss_count == rollup.count)
+            .map(|rollup| Event::Outage {
+                target: rollup.target.clone(),
+                datetime: rollup.datetime,
+                count: rollup.count,
+            })
+            .collect()
+    }
+}
+
+/// Events of a backend that can't notify other processes
+#[derive(Clone)]
+pub(super) struct LocalEvents {
+    sender: broadcast::Sender<Event>,
+}
+
+impl LocalEvents {
+    pub fn new() -> LocalEvents {
+        LocalEvents {
+            sender: broadcast::channel(LOCAL_CAPACITY).0,
+        }
+    }
+
+    pub fn publish(&self, events: &[Event]) {
+        for event in events {
+            // nobody might be subscribed
+            let _ = self.sender.send(event.clone());
+        }
+    }
+
+    pub fn subscribe(&self) -> BoxStream<'static, anyhow::Result<Event>> {
+        let mut receiver = self.sender.subscribe();
+        Box::pin(async_stream::stream! {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => yield Ok(event),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        yield Err(anyhow::anyhow!("missed {} events", missed));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,142 @@
+//! Typed filters for selecting logs, see [`super::Database::query_logs`] and
+//! [`super::Database::search_logs`].
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+
+use crate::fritz;
+
+/// A log together with its id in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredLog {
+    pub id: i64,
+    pub log: fritz::Log,
+}
+
+/// How to match the message of a log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageFilter {
+    /// The message contains the string, case-sensitive
+    Contains(String),
+    /// The message matches the regular expression. The syntax is the one of
+    /// the backend, POSIX for Postgres and the `regex` crate otherwise.
+    Regex(String),
+}
+
+/// Order of the selected logs by id, which is the order they've been inserted in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// Which logs to select.
+///
+/// Every filter that is set must match. Logs are ordered by id, so pages can
+/// be fetched by passing the id of the last log of the previous page to
+/// [`LogQuery::after`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogQuery {
+    pub(crate) since: Option<DateTime<Utc>>,
+    pub(crate) until: Option<DateTime<Utc>>,
+    pub(crate) message_ids: Option<Vec<i64>>,
+    pub(crate) category_id: Option<i64>,
+    pub(crate) message: Option<MessageFilter>,
+    pub(crate) repeated_only: bool,
+    pub(crate) order: Order,
+    pub(crate) after: Option<i64>,
+    pub(crate) limit: Option<usize>,
+}
+
+impl LogQuery {
+    pub fn new() -> LogQuery {
+        LogQuery::default()
+    }
+
+    /// Only logs that have last been logged at or after `datetime`.
+    pub fn since(mut self, datetime: impl Into<DateTime<Utc>>) -> LogQuery {
+        self.since = Some(datetime.into());
+        self
+    }
+
+    /// Only logs that have last been logged before `datetime`.
+    pub fn until(mut self, datetime: impl Into<DateTime<Utc>>) -> LogQuery {
+        self.until = Some(datetime.into());
+        self
+    }
+
+    /// Only logs with one of the given message ids.
+    pub fn message_ids(mut self, message_ids: impl IntoIterator<Item = i64>) -> LogQuery {
+        self.message_ids = Some(message_ids.into_iter().collect());
+        self
+    }
+
+    /// Only logs of the given category.
+    pub const fn category_id(mut self, category_id: i64) -> LogQuery {
+        self.category_id = Some(category_id);
+        self
+    }
+
+    /// Only logs whose message contains `needle`.
+    pub fn message_contains(mut self, needle: impl Into<String>) -> LogQuery {
+        self.message = Some(MessageFilter::Contains(needle.into()));
+        self
+    }
+
+    /// Only logs whose message matches the regular expression `pattern`.
+    pub fn message_matches(mut self, pattern: impl Into<String>) -> LogQuery {
+        self.message = Some(MessageFilter::Regex(pattern.into()));
+        self
+    }
+
+    /// Only logs that have been logged more than once.
+    pub const fn repeated_only(mut self) -> LogQuery {
+        self.repeated_only = true;
+        self
+    }
+
+    pub const fn order(mut self, order: Order) -> LogQuery {
+        self.order = order;
+        self
+    }
+
+    /// Only logs after the log with the given id, in the selected [`Order`].
+    pub const fn after(mut self, id: i64) -> LogQuery {
+        self.after = Some(id);
+        self
+    }
+
+    /// Select at most `limit` logs.
+    pub const fn limit(mut self, limit: usize) -> LogQuery {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+/// Marks the start of a matched word in [`SearchHit::snippet`].
+pub const HIGHLIGHT_START: &str = "**";
+/// Marks the end of a matched word in [`SearchHit::snippet`].
+pub const HIGHLIGHT_END: &str = "**";
+
+/// A log matching a full-text search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub log: StoredLog,
+    /// How well the log matches, higher is better. Only comparable between
+    /// hits of the same search.
+    pub rank: f64,
+    /// The message with the matched words between [`HIGHLIGHT_START`] and
+    /// [`HIGHLIGHT_END`], shortened around the matches if it's long.
+    pub snippet: String,
+}
+
+impl TryFrom<super::Log> for StoredLog {
+    type Error = anyhow::Error;
+    fn try_from(log: super::Log) -> anyhow::Result<Self> {
+        Ok(StoredLog {
+            id: log.id.context("log without id")?,
+            log: log.try_into()?,
+        })
+    }
+}
@@ -0,0 +1,759 @@
+//! In-process storage backend for tests and dry runs.
+//!
+//! Mirrors the constraints of the SQL backends, but nothing survives the
+//! process.
+
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use super::event::{Event, LocalEvents};
+use super::log_query::{
+    LogQuery, MessageFilter, Order, SearchHit, StoredLog, HIGHLIGHT_END, HIGHLIGHT_START,
+};
+use super::model::{
+    Gap, Log, LogRepetition, Ping, PingRollup, PollRun, Request, Resolution, Table, Update,
+};
+use super::postgres::TimeSeriesMode;
+use super::request_query::RequestQuery;
+use super::storage::{Leadership, SoleInstance, Storage, Transaction};
+use crate::fritz;
+
+#[derive(Debug, Clone)]
+struct LogRow {
+    log: Log,
+    resynced: bool,
+}
+
+/// All tables, ids are never reused.
+#[derive(Debug, Clone, Default)]
+struct State {
+    last_id: i64,
+    logs: Vec<LogRow>,
+    log_repetitions: Vec<LogRepetition>,
+    /// `(log_id, datetime)`
+    log_orphans: Vec<(i64, DateTime<Utc>)>,
+    /// `(log_id, datetime, archived_logs)`
+    log_clears: Vec<(i64, DateTime<Utc>, i64)>,
+    log_gaps: Vec<Gap>,
+    requests: Vec<Request>,
+    updates: Vec<Update>,
+    pings: Vec<Ping>,
+    ping_1m: Vec<PingRollup>,
+    ping_1h: Vec<PingRollup>,
+    poll_runs: Vec<PollRun>,
+}
+
+impl State {
+    const fn next_id(&mut self) -> i64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    const fn ping_rollups(&mut self, resolution: Resolution) -> &mut Vec<PingRollup> {
+        match resolution {
+            Resolution::Minute => &mut self.ping_1m,
+            Resolution::Hour => &mut self.ping_1h,
+        }
+    }
+
+    fn tail(&self) -> Option<&LogRow> {
+        self.logs.iter().rev().find(|row| !row.resynced)
+    }
+
+    /// Find the log the way the `UNIQUE("datetime", "message_id", "category_id")`
+    /// constraint does.
+    fn position(&self, log: &Log) -> Option<usize> {
+        self.logs.iter().position(|row| {
+            row.log.datetime == log.datetime
+                && row.log.message_id == log.message_id
+                && row.log.category_id == log.category_id
+        })
+    }
+
+    fn select_logs(&self, query: &LogQuery) -> anyhow::Result<Vec<StoredLog>> {
+        let regex = match query.message.as_ref() {
+            Some(MessageFilter::Regex(pattern)) => {
+                Some(lazy_regex::Regex::new(pattern).context("parse message regex")?)
+            }
+            _ => None,
+        };
+        let matches = |row: &&LogRow| {
+            let log = &row.log;
+            !matches!(query.since, Some(since) if log.datetime < since)
+                && !matches!(query.until, Some(until) if log.datetime >= until)
+                && !matches!(query.message_ids.as_ref(), Some(ids) if !ids.contains(&log.message_id))
+                && !matches!(query.category_id, Some(id) if log.category_id != id)
+                && match query.message.as_ref() {
+                    None => true,
+                    Some(MessageFilter::Contains(needle)) => log.message.contains(needle.as_str()),
+                    Some(MessageFilter::Regex(_)) => regex
+                        .as_ref()
+                        .is_some_and(|regex| regex.is_match(&log.message)),
+                }
+                && (!query.repeated_only || log.repetition_count.is_some())
+                && match (query.after, log.id, query.order) {
+                    (None, ..) => true,
+                    (Some(after), Some(id), Order::Ascending) => id > after,
+                    (Some(after), Some(id), Order::Descending) => id < after,
+                    (Some(_), None, _) => false,
+                }
+        };
+
+        let rows: Box<dyn Iterator<Item = &LogRow>> = match query.order {
+            Order::Ascending => Box::new(self.logs.iter()),
+            Order::Descending => Box::new(self.logs.iter().rev()),
+        };
+        rows.filter(matches)
+            .take(query.limit.unwrap_or(usize::MAX))
+            .map(|row| StoredLog::try_from(row.log.clone()))
+            .collect()
+    }
+
+    /// Match the words of `text` case-insensitively, without the stemming of
+    /// the SQL backends. Ranked by how often the words occur.
+    fn search_logs(
+        &self,
+        text: &str,
+        range: (Bound<DateTime<Utc>>, Bound<DateTime<Utc>>),
+        limit: usize,
+    ) -> anyhow::Result<Vec<SearchHit>> {
+        let words = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                lazy_regex::RegexBuilder::new(word)
+                    .case_insensitive(true)
+                    .build()
+                    .context("build search regex")
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+        let highlight = lazy_regex::RegexBuilder::new(
+            &words
+                .iter()
+                .map(lazy_regex::Regex::as_str)
+                .collect::<Vec<_>>()
+                .join("|"),
+        )
+        .case_insensitive(true)
+        .build()
+        .context("build highlight regex")?;
+
+        let mut hits = Vec::new();
+        for row in self.logs.iter().rev() {
+            let log = &row.log;
+            if !range.contains(&log.datetime)
+                || !words.iter().all(|word| word.is_match(&log.message))
+            {
+                continue;
+            }
+            let occurrences = highlight.find_iter(&log.message).count();
+            hits.push(SearchHit {
+                log: StoredLog::try_from(log.clone())?,
+                #[allow(clippy::cast_precision_loss)]
+                rank: occurrences as f64,
+                snippet: highlight
+                    .replace_all(
+                        &log.message,
+                        format!("{}$0{}", HIGHLIGHT_START, HIGHLIGHT_END).as_str(),
+                    )
+                    .into_owned(),
+            });
+        }
+
+        // stable, so equal ranks stay newest first
+        hits.sort_by(|a, b| b.rank.total_cmp(&a.rank));
+        hits.truncate(limit);
+        Ok(hits)
+    }
+
+    /// Delete at most `limit` rows from `table` that have been recorded
+    /// before `before`, oldest first, cascading like the SQL backends.
+    fn prune(&mut self, table: Table, before: DateTime<Utc>, limit: usize) -> u64 {
+        match table {
+            Table::Logs => {
+                let tail_id = self.tail().and_then(|row| row.log.id);
+                let mut pruned = Vec::new();
+                prune_rows(&mut self.logs, limit, |row| {
+                    let expired = row.log.datetime < before && row.log.id != tail_id;
+                    if expired {
+                        pruned.extend(row.log.id);
+                    }
+                    expired
+                });
+                self.log_repetitions
+                    .retain(|rep| !pruned.contains(&rep.log_id));
+                self.log_orphans.retain(|(id, _)| !pruned.contains(id));
+                self.log_clears.retain(|(id, ..)| !pruned.contains(id));
+                pruned.len() as u64
+            }
+            Table::Requests => prune_rows(&mut self.requests, limit, |req| req.datetime < before),
+            Table::Updates => {
+                prune_rows(&mut self.updates, limit, |update| update.datetime < before)
+            }
+            Table::Ping => prune_rows(&mut self.pings, limit, |ping| ping.datetime < before),
+            Table::Ping1m => {
+                prune_rows(&mut self.ping_1m, limit, |rollup| rollup.datetime < before)
+            }
+            Table::Ping1h => {
+                prune_rows(&mut self.ping_1h, limit, |rollup| rollup.datetime < before)
+            }
+            Table::PollRuns => prune_rows(&mut self.poll_runs, limit, |run| run.datetime < before),
+        }
+    }
+
+    fn insert_repetition(&mut self, log: &Log) {
+        let (Some(log_id), Some(repetition_count)) = (log.id, log.repetition_count) else {
+            return;
+        };
+        let id = self.next_id();
+        self.log_repetitions.push(LogRepetition {
+            id: Some(id),
+            log_id,
+            datetime: log.datetime,
+            repetition_count,
+        });
+    }
+}
+
+/// Remove at most `limit` rows that are `expired`, rows are oldest first.
+fn prune_rows<T>(rows: &mut Vec<T>, limit: usize, mut expired: impl FnMut(&T) -> bool) -> u64 {
+    let mut pruned = 0;
+    rows.retain(|row| {
+        if pruned < limit && expired(row) {
+            pruned += 1;
+            false
+        } else {
+            true
+        }
+    });
+    pruned as u64
+}
+
+pub struct MemoryStorage {
+    state: Arc<Mutex<State>>,
+    /// Log everything that's written, but only keep the logs.
+    ///
+    /// Every transaction clones the state, so a long dry run would otherwise
+    /// get slower with every ping and request.
+    dry_run: bool,
+    events: LocalEvents,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage {
+            state: Arc::default(),
+            dry_run: false,
+            events: LocalEvents::new(),
+        }
+    }
+
+    /// Log everything that's written, starting with the given logs.
+    ///
+    /// Pings, requests and poll runs are only logged, so they can't be
+    /// selected afterwards.
+    pub fn dry_run(logs: &[fritz::Log]) -> MemoryStorage {
+        let mut state = State::default();
+        for log in logs {
+            let mut log = Log::from(log.clone());
+            log.id = Some(state.next_id());
+            state.logs.push(LogRow {
+                log,
+                resynced: false,
+            });
+        }
+
+        MemoryStorage {
+            state: Arc::new(Mutex::new(state)),
+            dry_run: true,
+            events: LocalEvents::new(),
+        }
+    }
+
+    /// Lock the state, other transactions wait until it's unlocked again.
+    async fn lock(&self) -> OwnedMutexGuard<State> {
+        Arc::clone(&self.state).lock_owned().await
+    }
+
+    fn print(&self, write: std::fmt::Arguments<'_>) {
+        if self.dry_run {
+            log::info!("would {}", write);
+        }
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for MemoryStorage {
+    async fn begin(&self) -> anyhow::Result<Box<dyn Transaction>> {
+        let guard = self.lock().await;
+        Ok(Box::new(MemoryTransaction {
+            state: guard.clone(),
+            guard,
+            writes: self.dry_run.then(Vec::new),
+            events: self.events.clone(),
+            published: Vec::new(),
+        }))
+    }
+
+    async fn close(&self) {}
+
+    async fn clear_logs(&self) -> anyhow::Result<()> {
+        let mut state = self.lock().await;
+        state.logs.clear();
+        state.log_repetitions.clear();
+        state.log_orphans.clear();
+        state.log_clears.clear();
+        drop(state);
+        self.print(format_args!("clear logs"));
+        Ok(())
+    }
+
+    async fn count_logs(&self) -> anyhow::Result<usize> {
+        Ok(self.lock().await.logs.len())
+    }
+
+    async fn is_empty(&self) -> anyhow::Result<bool> {
+        Ok(self.lock().await.logs.is_empty())
+    }
+
+    fn stream_logs(&self, query: LogQuery) -> BoxStream<'_, anyhow::Result<StoredLog>> {
+        Box::pin(async_stream::try_stream! {
+            let logs = self.lock().await.select_logs(&query)?;
+            for log in logs {
+                yield log;
+            }
+        })
+    }
+
+    async fn search_logs(
+        &self,
+        text: &str,
+        range: (Bound<DateTime<Utc>>, Bound<DateTime<Utc>>),
+        limit: usize,
+    ) -> anyhow::Result<Vec<SearchHit>> {
+        self.lock().await.search_logs(text, range, limit)
+    }
+
+    async fn select_log_repetitions(&self, log_id: i64) -> anyhow::Result<Vec<LogRepetition>> {
+        Ok(self
+            .lock()
+            .await
+            .log_repetitions
+            .iter()
+            .filter(|rep| rep.log_id == log_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn try_acquire_leadership(
+        &self,
+        _key: i64,
+    ) -> anyhow::Result<Option<Box<dyn Leadership>>> {
+        // only this process can access the memory
+        Ok(Some(Box::new(SoleInstance)))
+    }
+
+    async fn publish(&self, events: &[Event]) -> anyhow::Result<()> {
+        self.events.publish(events);
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> anyhow::Result<BoxStream<'static, anyhow::Result<Event>>> {
+        Ok(self.events.subscribe())
+    }
+
+    async fn insert_request(&self, req: &Request) -> anyhow::Result<()> {
+        if !self.dry_run {
+            let mut state = self.lock().await;
+            let id = state.next_id();
+            state.requests.push(Request {
+                id: Some(id),
+                ..req.clone()
+            });
+        }
+        self.print(format_args!(
+            "insert request {} {} ({} ms)",
+            req.method, req.url, req.duration_ms
+        ));
+        Ok(())
+    }
+
+    async fn select_requests(&self, query: &RequestQuery) -> anyhow::Result<Vec<Request>> {
+        let mut requests = self
+            .lock()
+            .await
+            .requests
+            .iter()
+            .filter(|req| query.matches(req))
+            .cloned()
+            .collect::<Vec<_>>();
+        requests.sort_by_key(|req| (req.datetime, req.id));
+        requests.truncate(query.limit.unwrap_or(usize::MAX));
+        Ok(requests)
+    }
+
+    async fn insert_poll_run(&self, run: &PollRun) -> anyhow::Result<()> {
+        if !self.dry_run {
+            let mut state = self.lock().await;
+            let id = state.next_id();
+            state.poll_runs.push(PollRun {
+                id: Some(id),
+                ..run.clone()
+            });
+        }
+        self.print(format_args!(
+            "insert poll run (fetched {}, inserted {}, updated {}, error {:?})",
+            run.fetched, run.inserted, run.updated, run.error
+        ));
+        Ok(())
+    }
+
+    async fn select_poll_runs(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<PollRun>> {
+        let mut runs = self
+            .lock()
+            .await
+            .poll_runs
+            .iter()
+            .filter(|run| (since..until).contains(&run.datetime))
+            .cloned()
+            .collect::<Vec<_>>();
+        runs.sort_by_key(|run| (run.datetime, run.id));
+        Ok(runs)
+    }
+
+    async fn prune(
+        &self,
+        table: Table,
+        before: DateTime<Utc>,
+        limit: usize,
+    ) -> anyhow::Result<u64> {
+        let pruned = self.lock().await.prune(table, before, limit);
+        if pruned != 0 {
+            self.print(format_args!("prune {} rows from {}", pruned, table));
+        }
+        Ok(pruned)
+    }
+
+    async fn convert_time_series(&self, _mode: TimeSeriesMode) -> anyhow::Result<TimeSeriesMode> {
+        anyhow::bail!("converting time-series tables is only supported by Postgres")
+    }
+
+    async fn insert_ping(&self, ping: &Ping) -> anyhow::Result<()> {
+        if !self.dry_run {
+            let mut state = self.lock().await;
+            let id = state.next_id();
+            state.pings.push(Ping {
+                id: Some(id),
+                ..ping.clone()
+            });
+        }
+        self.print(format_args!(
+            "insert ping {} ({:?} ms)",
+            ping.target, ping.duration_ms
+        ));
+        Ok(())
+    }
+
+    async fn select_pings(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Ping>> {
+        let mut pings = self
+            .lock()
+            .await
+            .pings
+            .iter()
+            .filter(|ping| (since..until).contains(&ping.datetime))
+            .cloned()
+            .collect::<Vec<_>>();
+        pings.sort_by_key(|ping| (ping.datetime, ping.id));
+        Ok(pings)
+    }
+
+    async fn select_next_ping_datetime(
+        &self,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        Ok(self
+            .lock()
+            .await
+            .pings
+            .iter()
+            .map(|ping| ping.datetime)
+            .filter(|datetime| *datetime >= since)
+            .min())
+    }
+
+    async fn select_latest_ping_rollup_datetime(
+        &self,
+        resolution: Resolution,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        Ok(self
+            .lock()
+            .await
+            .ping_rollups(resolution)
+            .iter()
+            .map(|rollup| rollup.datetime)
+            .max())
+    }
+
+    async fn upsert_ping_rollups(
+        &self,
+        resolution: Resolution,
+        rollups: &[PingRollup],
+    ) -> anyhow::Result<()> {
+        let mut state = self.lock().await;
+        for rollup in rollups {
+            let existing = state.ping_rollups(resolution).iter().position(|other| {
+                other.target == rollup.target && other.datetime == rollup.datetime
+            });
+            match existing {
+                Some(index) => {
+                    let row = &mut state.ping_rollups(resolution)[index];
+                    *row = PingRollup {
+                        id: row.id,
+                        ..rollup.clone()
+                    };
+                }
+                None => {
+                    let id = state.next_id();
+                    state.ping_rollups(resolution).push(PingRollup {
+                        id: Some(id),
+                        ..rollup.clone()
+                    });
+                }
+            }
+        }
+        drop(state);
+        if !rollups.is_empty() {
+            self.print(format_args!(
+                "upsert {} ping rollups into {}",
+                rollups.len(),
+                resolution.table()
+            ));
+        }
+        Ok(())
+    }
+
+    async fn select_ping_rollups(
+        &self,
+        resolution: Resolution,
+        target: Option<&str>,
+        range: (Bound<DateTime<Utc>>, Bound<DateTime<Utc>>),
+    ) -> anyhow::Result<Vec<PingRollup>> {
+        let mut rollups = self
+            .lock()
+            .await
+            .ping_rollups(resolution)
+            .iter()
+            .filter(|rollup| target.is_none() || target == Some(rollup.target.as_str()))
+            .filter(|rollup| range.contains(&rollup.datetime))
+            .cloned()
+            .collect::<Vec<_>>();
+        rollups.sort_by(|a, b| (a.datetime, &a.target).cmp(&(b.datetime, &b.target)));
+        Ok(rollups)
+    }
+}
+
+struct MemoryTransaction {
+    guard: OwnedMutexGuard<State>,
+    /// Copy of the state that replaces it on commit
+    state: State,
+    /// Writes to log on commit, if it's a dry run
+    writes: Option<Vec<String>>,
+    events: LocalEvents,
+    /// Events to publish on commit
+    published: Vec<Event>,
+}
+
+impl MemoryTransaction {
+    fn record(&mut self, write: impl FnOnce() -> String) {
+        if let Some(writes) = self.writes.as_mut() {
+            writes.push(write());
+        }
+    }
+
+    fn select_logs(&self, rows: impl Iterator<Item = LogRow>) -> anyhow::Result<Vec<fritz::Log>> {
+        rows.map(|row| row.log.try_into()).collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl Transaction for MemoryTransaction {
+    async fn commit(self: Box<Self>) -> anyhow::Result<()> {
+        let MemoryTransaction {
+            mut guard,
+            state,
+            writes,
+            events,
+            published,
+        } = *self;
+        *guard = state;
+        drop(guard);
+
+        for write in writes.into_iter().flatten() {
+            log::info!("would {}", write);
+        }
+        events.publish(&published);
+        Ok(())
+    }
+
+    async fn publish(&mut self, events: &[Event]) -> anyhow::Result<()> {
+        self.published.extend_from_slice(events);
+        Ok(())
+    }
+
+    async fn select_latest_logs(
+        &mut self,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<Vec<fritz::Log>> {
+        let rows = self.state.logs.iter().rev().skip(offset).take(limit);
+        self.select_logs(rows.cloned())
+    }
+
+    async fn select_tail_log(&mut self) -> anyhow::Result<Option<fritz::Log>> {
+        self.state
+            .tail()
+            .map(|row| row.log.clone().try_into())
+            .transpose()
+    }
+
+    async fn select_tail_cleared(&mut self) -> anyhow::Result<bool> {
+        let Some(tail_id) = self.state.tail().and_then(|row| row.log.id) else {
+            return Ok(false);
+        };
+        Ok(self.state.log_clears.iter().any(|(id, ..)| *id == tail_id))
+    }
+
+    async fn select_logs_since(
+        &mut self,
+        datetime: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<fritz::Log>> {
+        let rows = self
+            .state
+            .logs
+            .iter()
+            .filter(|row| row.log.datetime >= datetime);
+        self.select_logs(rows.cloned())
+    }
+
+    async fn insert_logs(&mut self, logs: &[fritz::Log], resynced: bool) -> anyhow::Result<()> {
+        for fritz_log in logs {
+            let mut log = Log::from(fritz_log.clone());
+            if self.state.position(&log).is_some() {
+                anyhow::bail!("insert logs: duplicate log {}", fritz_log);
+            }
+
+            log.id = Some(self.state.next_id());
+            self.state.insert_repetition(&log);
+            self.state.logs.push(LogRow { log, resynced });
+            self.record(|| format!("insert log {}", fritz_log));
+        }
+        Ok(())
+    }
+
+    async fn update_log(&mut self, old: &fritz::Log, new: &fritz::Log) -> anyhow::Result<i64> {
+        let Some(index) = self.state.position(&Log::from(old.clone())) else {
+            return Ok(0);
+        };
+
+        let row = &mut self.state.logs[index];
+        row.log = Log {
+            id: row.log.id,
+            ..Log::from(new.clone())
+        };
+        let log = row.log.clone();
+        self.state.insert_repetition(&log);
+        self.record(|| format!("update log {} to {}", old, new));
+        Ok(1)
+    }
+
+    async fn insert_orphan(
+        &mut self,
+        log: &fritz::Log,
+        datetime: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let Some(index) = self.state.position(&Log::from(log.clone())) else {
+            return Ok(());
+        };
+        let log_id = self.state.logs[index].log.id.context("log without id")?;
+        if self.state.log_orphans.iter().any(|(id, _)| *id == log_id) {
+            return Ok(());
+        }
+
+        self.state.log_orphans.push((log_id, datetime));
+        self.record(|| format!("flag orphaned log {}", log));
+        Ok(())
+    }
+
+    async fn insert_clear(
+        &mut self,
+        datetime: DateTime<Utc>,
+        archived_logs: i64,
+    ) -> anyhow::Result<()> {
+        let tail_id = self.state.tail().and_then(|row| row.log.id);
+        let Some(log_id) =
+            tail_id.filter(|tail_id| !self.state.log_clears.iter().any(|(id, ..)| id == tail_id))
+        else {
+            log::warn!(
+                "recorded no clear, the database is empty or the clear was already recorded"
+            );
+            return Ok(());
+        };
+
+        self.state
+            .log_clears
+            .push((log_id, datetime, archived_logs));
+        self.record(|| format!("record clear of {} archived logs", archived_logs));
+        Ok(())
+    }
+
+    async fn insert_gap(&mut self, gap: &Gap) -> anyhow::Result<()> {
+        if self
+            .state
+            .log_gaps
+            .iter()
+            .any(|other| other.kind == gap.kind && other.after_datetime == gap.after_datetime)
+        {
+            return Ok(());
+        }
+
+        let id = self.state.next_id();
+        self.state.log_gaps.push(Gap {
+            id: Some(id),
+            ..gap.clone()
+        });
+        self.record(|| format!("insert gap after {} ({})", gap.after_datetime, gap.kind));
+        Ok(())
+    }
+
+    async fn insert_update(&mut self, update: &Update) -> anyhow::Result<()> {
+        let id = self.state.next_id();
+        self.state.updates.push(Update {
+            id: Some(id),
+            ..update.clone()
+        });
+        self.record(|| format!("insert update of {} rows", update.upserted_rows));
+        Ok(())
+    }
+}
@@ -4,6 +4,28 @@ pub use connection::*;
 mod model;
 pub use model::*;
 
+mod event;
+pub use event::{Event, EVENT_CHANNEL};
+
+mod log_query;
+pub use log_query::{
+    LogQuery, MessageFilter, Order, SearchHit, StoredLog, HIGHLIGHT_END, HIGHLIGHT_START,
+};
+
+mod request_query;
+pub use request_query::{RequestQuery, RequestStats};
+
+mod memory;
+mod postgres;
+pub use postgres::TimeSeriesMode;
+mod sqlite;
+
+mod spool;
+pub use spool::{replay_loop, Spool};
+
+mod storage;
+pub use storage::{Leadership, Storage, Transaction};
+
 pub mod util {
     use anyhow::Context;
     use chrono::{DateTime, Local, TimeZone, Utc};
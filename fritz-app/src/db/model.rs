@@ -1,7 +1,7 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Timelike, Utc};
 
 /// A log row from the Fritz!BOX logs
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
 pub struct Log {
     pub id: Option<i64>,
     pub datetime: DateTime<Utc>,
@@ -10,10 +10,26 @@ pub struct Log {
     pub category_id: i64,
     pub repetition_datetime: Option<DateTime<Utc>>,
     pub repetition_count: Option<i64>,
+    /// See [`crate::fritz::TimeResolution`]
+    pub datetime_resolution: Option<String>,
+    /// See [`crate::fritz::TimeResolution`]
+    pub repetition_datetime_resolution: Option<String>,
+}
+
+/// The state of a repeated log at some point in time
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LogRepetition {
+    pub id: Option<i64>,
+    pub log_id: i64,
+    /// Timestamp at which the log has been logged for the `repetition_count`-th time
+    pub datetime: DateTime<Utc>,
+    pub repetition_count: i64,
 }
 
 /// Information about a request to the FRITZ!Box
-#[derive(Debug, Clone, Default, serde::Serialize)]
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::FromRow,
+)]
 pub struct Request {
     pub id: Option<i64>,
     pub datetime: DateTime<Utc>,
@@ -23,6 +39,27 @@ pub struct Request {
     pub duration_ms: i64,
     pub response_code: Option<i64>,
     pub session_id: Option<String>,
+    /// Why the request failed, see [`crate::poll::Failure`]
+    pub error_kind: Option<String>,
+    pub error_message: Option<String>,
+    /// Size of the decoded response body
+    pub response_bytes: Option<i64>,
+    /// How many times in a row a request of this name has been sent, 1 unless
+    /// the previous ones failed
+    #[serde(default = "first_attempt")]
+    pub attempt: i64,
+    /// Address of the FRITZ!Box that answered, e.g. `192.168.178.1:443`
+    pub remote_addr: Option<String>,
+}
+
+const fn first_attempt() -> i64 {
+    1
+}
+
+impl Request {
+    pub const fn is_failed(&self) -> bool {
+        self.error_kind.is_some()
+    }
 }
 
 /// Information about updates
@@ -33,8 +70,105 @@ pub struct Update {
     pub upserted_rows: i64,
 }
 
+/// An attempt to fetch the logs from the FRITZ!Box and append them to the
+/// database, recorded whether it succeeded or not
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PollRun {
+    pub id: Option<i64>,
+    /// Timestamp at which the run started
+    pub datetime: DateTime<Utc>,
+    pub finished_datetime: DateTime<Utc>,
+    /// Number of logs fetched from the FRITZ!Box
+    pub fetched: i64,
+    pub inserted: i64,
+    /// Number of logs updated because they've been repeated
+    pub updated: i64,
+    /// Whether logs might be missing, see [`Gap`]
+    pub gap: bool,
+    /// Whether the logs have been spooled because the database was unavailable
+    pub spooled: bool,
+    /// How far the newest log was ahead of the request, see
+    /// [`crate::fritz::ClockCheck`]
+    #[serde(default)]
+    pub clock_drift_ms: Option<i64>,
+    /// Number of logs with implausible timestamps
+    #[serde(default)]
+    pub implausible: i64,
+    /// Why the run failed, see [`crate::poll::Failure`]
+    pub error: Option<String>,
+    pub error_message: Option<String>,
+    /// Version of the app that polled
+    pub version: String,
+    /// Host the app polled from
+    pub host: String,
+}
+
+impl PollRun {
+    /// Start a poll run from `host`, the counts are filled in once it has
+    /// finished.
+    pub fn start(host: &str) -> PollRun {
+        let now = Utc::now();
+        PollRun {
+            id: None,
+            datetime: now,
+            finished_datetime: now,
+            fetched: 0,
+            inserted: 0,
+            updated: 0,
+            gap: false,
+            spooled: false,
+            clock_drift_ms: None,
+            implausible: 0,
+            error: None,
+            error_message: None,
+            version: crate::poll::VERSION.to_string(),
+            host: host.to_string(),
+        }
+    }
+
+    /// Finish the run once `appended` has been appended to the database.
+    pub fn succeed(self, appended: &Appended) -> PollRun {
+        PollRun {
+            finished_datetime: Utc::now(),
+            inserted: to_count(appended.inserted),
+            updated: to_count(appended.updated),
+            gap: appended.gap,
+            spooled: appended.spooled,
+            ..self
+        }
+    }
+
+    /// Finish the run because of `err`.
+    pub fn fail(self, failure: crate::poll::Failure, err: &anyhow::Error) -> PollRun {
+        PollRun {
+            finished_datetime: Utc::now(),
+            error: Some(failure.to_string()),
+            error_message: Some(format!("{:#}", err)),
+            ..self
+        }
+    }
+}
+
+fn to_count(count: usize) -> i64 {
+    count.min(i64::MAX as usize) as i64
+}
+
+/// What appending the logs fetched from the FRITZ!Box changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Appended {
+    /// Inserted or updated logs
+    pub upserted: Vec<crate::fritz::Log>,
+    pub inserted: usize,
+    pub updated: usize,
+    /// Whether logs might be missing, see [`Gap`]
+    pub gap: bool,
+    /// Whether the logs have been spooled because the database was unavailable,
+    /// nothing has been appended yet then
+    pub spooled: bool,
+}
+
 /// Information about pings
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Ping {
     pub id: Option<i64>,
     pub datetime: DateTime<Utc>,
@@ -43,3 +177,135 @@ pub struct Ping {
     pub ttl: Option<i64>,
     pub bytes: Option<i64>,
 }
+
+/// Logs that might be missing because the most recent log in the database
+/// wasn't in the logs fetched from the FRITZ!Box anymore
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Gap {
+    pub id: Option<i64>,
+    /// Timestamp at which the gap has been detected
+    pub datetime: DateTime<Utc>,
+    /// See [`crate::fritz::GapKind`]
+    pub kind: String,
+    pub after_datetime: DateTime<Utc>,
+    pub before_datetime: Option<DateTime<Utc>>,
+}
+
+/// Size of the buckets pings are rolled up into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    Minute,
+    Hour,
+}
+
+impl Resolution {
+    pub const ALL: [Resolution; 2] = [Resolution::Minute, Resolution::Hour];
+
+    /// Table the rollups of this resolution are saved in
+    pub const fn table(self) -> Table {
+        match self {
+            Resolution::Minute => Table::Ping1m,
+            Resolution::Hour => Table::Ping1h,
+        }
+    }
+
+    pub const fn seconds(self) -> i64 {
+        match self {
+            Resolution::Minute => 60,
+            Resolution::Hour => 60 * 60,
+        }
+    }
+
+    pub const fn duration(self) -> chrono::Duration {
+        chrono::Duration::seconds(self.seconds())
+    }
+
+    /// Start of the bucket `datetime` falls into
+    pub fn bucket(self, datetime: DateTime<Utc>) -> DateTime<Utc> {
+        let offset = datetime.timestamp().rem_euclid(self.seconds());
+        (datetime - chrono::Duration::seconds(offset))
+            .with_nanosecond(0)
+            .unwrap_or(datetime)
+    }
+}
+
+/// All pings of a target within a bucket of some [`Resolution`]
+///
+/// Latencies only consider answered pings, so they're `None` if every ping
+/// of the bucket has been lost.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, sqlx::FromRow)]
+pub struct PingRollup {
+    pub id: Option<i64>,
+    pub target: String,
+    /// Start of the bucket
+    pub datetime: DateTime<Utc>,
+    pub count: i64,
+    /// Pings that timed out
+    pub loss_count: i64,
+    pub loss_ratio: f64,
+    pub min_ms: Option<i64>,
+    pub avg_ms: Option<f64>,
+    pub max_ms: Option<i64>,
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    /// Mean difference between the latencies of consecutive answered pings
+    pub jitter_ms: Option<f64>,
+}
+
+/// A table rows can be pruned from once they've expired
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Table {
+    /// Pruning never removes the most recently appended log, so the next
+    /// update still knows where to continue
+    Logs,
+    Requests,
+    Updates,
+    Ping,
+    /// Pings rolled up into 1-minute buckets
+    Ping1m,
+    /// Pings rolled up into 1-hour buckets
+    Ping1h,
+    PollRuns,
+}
+
+impl Table {
+    pub const ALL: [Table; 7] = [
+        Table::Logs,
+        Table::Requests,
+        Table::Updates,
+        Table::Ping,
+        Table::Ping1m,
+        Table::Ping1h,
+        Table::PollRuns,
+    ];
+
+    /// Name of the table in the database
+    pub const fn name(self) -> &'static str {
+        match self {
+            Table::Logs => "logs",
+            Table::Requests => "requests",
+            Table::Updates => "updates",
+            Table::Ping => "ping",
+            Table::Ping1m => "ping_1m",
+            Table::Ping1h => "ping_1h",
+            Table::PollRuns => "poll_runs",
+        }
+    }
+}
+
+impl std::fmt::Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for Table {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Table::ALL
+            .into_iter()
+            .find(|table| table.name() == s)
+            .ok_or_else(|| anyhow::anyhow!("unknown table {:?}", s))
+    }
+}
@@ -0,0 +1,546 @@
+//! Postgres storage backend.
+
+use std::ops::Bound;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use futures_util::{StreamExt, TryStreamExt};
+use sqlx::postgres::PgListener;
+use sqlx::{Connection, PgExecutor, PgPool, Postgres};
+
+use super::event::{Event, EVENT_CHANNEL};
+use super::log_query::{LogQuery, SearchHit, StoredLog};
+use super::model::{
+    Gap, Log, LogRepetition, Ping, PingRollup, PollRun, Request, Resolution, Table, Update,
+};
+use super::request_query::RequestQuery;
+use super::storage::{Leadership, Storage, Transaction};
+use crate::fritz;
+
+mod query;
+mod time_series;
+pub use time_series::TimeSeriesMode;
+
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub async fn open(url: &str) -> anyhow::Result<PostgresStorage> {
+        let pool = PgPool::connect(url).await.context("connect to postgres")?;
+        sqlx::migrate!("./migrations/postgres/")
+            .run(&pool)
+            .await
+            .context("migrate database")?;
+        if let Err(err) = time_series::create_partitions_ahead(&pool).await {
+            log::warn!("couldn't create partitions ahead of time: {:?}", err);
+        }
+
+        Ok(PostgresStorage { pool })
+    }
+}
+
+/// Send `events` on [`EVENT_CHANNEL`].
+///
+/// Within a transaction, they're only delivered once it has been committed.
+async fn notify<'c>(executor: impl PgExecutor<'c>, events: &[Event]) -> anyhow::Result<()> {
+    let payloads = events
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()
+        .context("serialize events")?;
+    sqlx::query(r#"SELECT pg_notify($1, "payload") FROM unnest($2::TEXT[]) AS "payload""#)
+        .bind(EVENT_CHANNEL)
+        .bind(payloads)
+        .execute(executor)
+        .await
+        .context("notify events")
+        .map(|_| ())
+}
+
+/// An advisory lock held by a connection of its own, it's released once the
+/// connection is closed or the server notices that it's gone.
+struct PostgresLeadership {
+    conn: sqlx::PgConnection,
+}
+
+#[async_trait::async_trait]
+impl Leadership for PostgresLeadership {
+    async fn check(&mut self) -> anyhow::Result<()> {
+        self.conn
+            .ping()
+            .await
+            .context("lost connection holding the advisory lock")
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for PostgresStorage {
+    async fn begin(&self) -> anyhow::Result<Box<dyn Transaction>> {
+        let tx = self.pool.begin().await.context("begin transaction")?;
+        Ok(Box::new(PostgresTransaction { tx }))
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    async fn clear_logs(&self) -> anyhow::Result<()> {
+        sqlx::query!(r#"DELETE FROM "logs""#)
+            .execute(&self.pool)
+            .await
+            .context("clear logs")
+            .map(|_| ())
+    }
+
+    async fn count_logs(&self) -> anyhow::Result<usize> {
+        let count = sqlx::query!(
+            r#"
+        SELECT count(*) as "count"
+        FROM "logs"
+            "#
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("fetch row count")?
+        .count
+        .context("missing row count")?;
+
+        usize::try_from(count).context("negative row count")
+    }
+
+    async fn is_empty(&self) -> anyhow::Result<bool> {
+        // https://dba.stackexchange.com/a/223286
+        let count = sqlx::query!(
+            r#"
+        SELECT count(*) as count
+        FROM (SELECT 0 from logs LIMIT 1) AS "first"
+            "#
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("check database empty")?
+        .count
+        .context("missing row count")?;
+
+        Ok(count == 0)
+    }
+
+    fn stream_logs(&self, query: LogQuery) -> BoxStream<'_, anyhow::Result<StoredLog>> {
+        Box::pin(async_stream::try_stream! {
+            let mut builder = query::select_logs(&query)?;
+            let mut rows = builder.build_query_as::<Log>().fetch(&self.pool);
+            while let Some(row) = rows.try_next().await.context("fetch logs")? {
+                yield StoredLog::try_from(row)?;
+            }
+        })
+    }
+
+    async fn search_logs(
+        &self,
+        text: &str,
+        range: (Bound<DateTime<Utc>>, Bound<DateTime<Utc>>),
+        limit: usize,
+    ) -> anyhow::Result<Vec<SearchHit>> {
+        query::search_logs(text, range, limit)?
+            .build_query_as::<query::SearchRow>()
+            .fetch_all(&self.pool)
+            .await
+            .context("search logs")?
+            .into_iter()
+            .map(|row| {
+                Ok(SearchHit {
+                    log: row.log.try_into()?,
+                    rank: f64::from(row.rank),
+                    snippet: row.snippet,
+                })
+            })
+            .collect()
+    }
+
+    async fn select_log_repetitions(&self, log_id: i64) -> anyhow::Result<Vec<LogRepetition>> {
+        sqlx::query_as!(
+            LogRepetition,
+            r#"
+        SELECT "id",
+               "log_id",
+               "datetime",
+               "repetition_count"
+        FROM "log_repetitions"
+        WHERE "log_id" = $1
+        ORDER BY "id" ASC
+            "#,
+            /* 1 */ log_id,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("fetch log repetitions")
+    }
+
+    async fn try_acquire_leadership(
+        &self,
+        key: i64,
+    ) -> anyhow::Result<Option<Box<dyn Leadership>>> {
+        // a session lock lives as long as its connection, which must not be
+        // returned into the pool while it's held
+        let mut conn = self
+            .pool
+            .acquire()
+            .await
+            .context("acquire connection")?
+            .detach();
+        let acquired =
+            sqlx::query_scalar!(r#"SELECT pg_try_advisory_lock($1) AS "acquired!""#, key)
+                .fetch_one(&mut conn)
+                .await
+                .context("try to acquire advisory lock")?;
+
+        if acquired {
+            Ok(Some(Box::new(PostgresLeadership { conn })))
+        } else {
+            conn.close().await.context("close connection")?;
+            Ok(None)
+        }
+    }
+
+    async fn publish(&self, events: &[Event]) -> anyhow::Result<()> {
+        notify(&self.pool, events).await
+    }
+
+    async fn subscribe(&self) -> anyhow::Result<BoxStream<'static, anyhow::Result<Event>>> {
+        let mut listener = PgListener::connect_with(&self.pool)
+            .await
+            .context("connect listener")?;
+        listener
+            .listen(EVENT_CHANNEL)
+            .await
+            .with_context(|| format!("listen on {}", EVENT_CHANNEL))?;
+
+        // the listener reconnects on its own, events sent in the meantime are lost
+        Ok(Box::pin(listener.into_stream().map(|notification| {
+            let notification = notification.context("receive event")?;
+            serde_json::from_str(notification.payload()).context("parse event")
+        })))
+    }
+
+    async fn insert_request(&self, req: &Request) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+        INSERT INTO "requests"
+        (
+            "datetime",
+            "name",
+            "url",
+            "method",
+            "duration_ms",
+            "response_code",
+            "session_id",
+            "error_kind",
+            "error_message",
+            "response_bytes",
+            "attempt",
+            "remote_addr"
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            "#,
+            /* 1 */ req.datetime,
+            /* 2 */ req.name,
+            /* 3 */ req.url,
+            /* 4 */ req.method,
+            /* 5 */ req.duration_ms,
+            /* 6 */ req.response_code,
+            /* 7 */ req.session_id,
+            /* 8 */ req.error_kind,
+            /* 9 */ req.error_message,
+            /* 10 */ req.response_bytes,
+            /* 11 */ req.attempt,
+            /* 12 */ req.remote_addr,
+        )
+        .execute(&self.pool)
+        .await
+        .context("insert request")?;
+
+        Ok(())
+    }
+
+    async fn select_requests(&self, query: &RequestQuery) -> anyhow::Result<Vec<Request>> {
+        query::select_requests(query)?
+            .build_query_as::<Request>()
+            .fetch_all(&self.pool)
+            .await
+            .context("fetch requests")
+    }
+
+    async fn insert_poll_run(&self, run: &PollRun) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+        INSERT INTO "poll_runs"
+        (
+            "datetime",
+            "finished_datetime",
+            "fetched",
+            "inserted",
+            "updated",
+            "gap",
+            "spooled",
+            "clock_drift_ms",
+            "implausible",
+            "error",
+            "error_message",
+            "version",
+            "host"
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+            "#,
+            /* 1 */ run.datetime,
+            /* 2 */ run.finished_datetime,
+            /* 3 */ run.fetched,
+            /* 4 */ run.inserted,
+            /* 5 */ run.updated,
+            /* 6 */ run.gap,
+            /* 7 */ run.spooled,
+            /* 8 */ run.clock_drift_ms,
+            /* 9 */ run.implausible,
+            /* 10 */ run.error,
+            /* 11 */ run.error_message,
+            /* 12 */ run.version,
+            /* 13 */ run.host,
+        )
+        .execute(&self.pool)
+        .await
+        .context("insert poll run")?;
+
+        Ok(())
+    }
+
+    async fn select_poll_runs(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<PollRun>> {
+        sqlx::query_as!(
+            PollRun,
+            r#"
+        SELECT "id",
+               "datetime",
+               "finished_datetime",
+               "fetched",
+               "inserted",
+               "updated",
+               "gap",
+               "spooled",
+               "clock_drift_ms",
+               "implausible",
+               "error",
+               "error_message",
+               "version",
+               "host"
+        FROM "poll_runs"
+        WHERE "datetime" >= $1 AND "datetime" < $2
+        ORDER BY "datetime" ASC, "id" ASC
+            "#,
+            /* 1 */ since,
+            /* 2 */ until,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("fetch poll runs")
+    }
+
+    async fn prune(
+        &self,
+        table: Table,
+        before: DateTime<Utc>,
+        limit: usize,
+    ) -> anyhow::Result<u64> {
+        query::prune(&self.pool, table, before, limit).await
+    }
+
+    async fn convert_time_series(&self, mode: TimeSeriesMode) -> anyhow::Result<TimeSeriesMode> {
+        time_series::convert(&self.pool, mode).await
+    }
+
+    async fn insert_ping(&self, ping: &Ping) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+        INSERT INTO "ping"
+        (
+            "datetime",
+            "target",
+            "duration_ms",
+            "ttl",
+            "bytes"
+        )
+        VALUES ($1, $2, $3, $4, $5)
+            "#,
+            /* 1 */ ping.datetime,
+            /* 2 */ ping.target,
+            /* 3 */ ping.duration_ms,
+            /* 4 */ ping.ttl,
+            /* 5 */ ping.bytes,
+        )
+        .execute(&self.pool)
+        .await
+        .context("insert ping")?;
+
+        Ok(())
+    }
+
+    async fn select_pings(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Ping>> {
+        sqlx::query_as!(
+            Ping,
+            r#"
+        SELECT "id",
+               "datetime",
+               "target",
+               "duration_ms",
+               "ttl",
+               "bytes"
+        FROM "ping"
+        WHERE "datetime" >= $1 AND "datetime" < $2
+        ORDER BY "datetime" ASC, "id" ASC
+            "#,
+            /* 1 */ since,
+            /* 2 */ until,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("fetch pings")
+    }
+
+    async fn select_next_ping_datetime(
+        &self,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        sqlx::query_scalar!(
+            r#"
+        SELECT min("datetime")
+        FROM "ping"
+        WHERE "datetime" >= $1
+            "#,
+            /* 1 */ since,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("fetch next ping")
+    }
+
+    async fn select_latest_ping_rollup_datetime(
+        &self,
+        resolution: Resolution,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        sqlx::query_scalar(&format!(
+            r#"SELECT max("datetime") FROM "{}""#,
+            resolution.table().name()
+        ))
+        .fetch_one(&self.pool)
+        .await
+        .context("fetch latest ping rollup")
+    }
+
+    async fn upsert_ping_rollups(
+        &self,
+        resolution: Resolution,
+        rollups: &[PingRollup],
+    ) -> anyhow::Result<()> {
+        // stay well below the limit of bind parameters per statement
+        let mut tx = self.pool.begin().await.context("begin transaction")?;
+        for chunk in rollups.chunks(1000) {
+            query::upsert_ping_rollups(resolution, chunk)
+                .build()
+                .execute(&mut *tx)
+                .await
+                .context("upsert ping rollups")?;
+        }
+        tx.commit().await.context("commit transaction")
+    }
+
+    async fn select_ping_rollups(
+        &self,
+        resolution: Resolution,
+        target: Option<&str>,
+        range: (Bound<DateTime<Utc>>, Bound<DateTime<Utc>>),
+    ) -> anyhow::Result<Vec<PingRollup>> {
+        query::select_ping_rollups(resolution, target, range)
+            .build_query_as::<PingRollup>()
+            .fetch_all(&self.pool)
+            .await
+            .context("fetch ping rollups")
+    }
+}
+
+struct PostgresTransaction {
+    tx: sqlx::Transaction<'static, Postgres>,
+}
+
+#[async_trait::async_trait]
+impl Transaction for PostgresTransaction {
+    async fn commit(self: Box<Self>) -> anyhow::Result<()> {
+        self.tx.commit().await.context("commit transaction")
+    }
+
+    async fn publish(&mut self, events: &[Event]) -> anyhow::Result<()> {
+        // delivered once the transaction has been committed
+        notify(&mut *self.tx, events).await
+    }
+
+    async fn select_latest_logs(
+        &mut self,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<Vec<fritz::Log>> {
+        query::select_latest_logs(&mut *self.tx, offset, limit).await
+    }
+
+    async fn select_tail_log(&mut self) -> anyhow::Result<Option<fritz::Log>> {
+        query::select_tail_log(&mut *self.tx).await
+    }
+
+    async fn select_tail_cleared(&mut self) -> anyhow::Result<bool> {
+        query::select_tail_cleared(&mut *self.tx).await
+    }
+
+    async fn select_logs_since(
+        &mut self,
+        datetime: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<fritz::Log>> {
+        query::select_logs_since(&mut *self.tx, datetime).await
+    }
+
+    async fn insert_logs(&mut self, logs: &[fritz::Log], resynced: bool) -> anyhow::Result<()> {
+        query::insert_logs(&mut *self.tx, logs, resynced).await
+    }
+
+    async fn update_log(&mut self, old: &fritz::Log, new: &fritz::Log) -> anyhow::Result<i64> {
+        query::update_log(&mut *self.tx, old, new).await
+    }
+
+    async fn insert_orphan(
+        &mut self,
+        log: &fritz::Log,
+        datetime: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        query::insert_orphan(&mut *self.tx, log, datetime).await
+    }
+
+    async fn insert_clear(
+        &mut self,
+        datetime: DateTime<Utc>,
+        archived_logs: i64,
+    ) -> anyhow::Result<()> {
+        query::insert_clear(&mut *self.tx, datetime, archived_logs).await
+    }
+
+    async fn insert_gap(&mut self, gap: &Gap) -> anyhow::Result<()> {
+        query::insert_gap(&mut *self.tx, gap).await
+    }
+
+    async fn insert_update(&mut self, update: &Update) -> anyhow::Result<()> {
+        query::insert_update(&mut *self.tx, update).await
+    }
+}
@@ -0,0 +1,741 @@
+//! Postgres queries, generic over the executor so they can run as part of a transaction.
+
+use std::ops::Bound;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, Postgres, QueryBuilder};
+
+use crate::db::log_query::{LogQuery, MessageFilter, Order, HIGHLIGHT_END, HIGHLIGHT_START};
+use crate::db::model::{Gap, Log, PingRollup, Resolution, Table, Update};
+use crate::db::request_query::RequestQuery;
+use crate::fritz;
+
+/// Select the `limit` latest logs offset by `offset`.
+pub async fn select_latest_logs<'e>(
+    executor: impl PgExecutor<'e>,
+    offset: usize,
+    limit: usize,
+) -> anyhow::Result<Vec<fritz::Log>> {
+    let offset = i64::try_from(offset).context("cast offset as i64")?;
+    let limit = i64::try_from(limit).context("cast limit as i64")?;
+    sqlx::query_as!(
+        Log,
+        r#"
+    SELECT "id",
+           "datetime",
+           "message",
+           "message_id",
+           "category_id",
+           "repetition_datetime",
+           "repetition_count",
+           "datetime_resolution",
+           "repetition_datetime_resolution"
+    FROM "logs"
+    ORDER BY "id" DESC
+    LIMIT $1
+    OFFSET $2
+        "#,
+        /* 1 */ limit,
+        /* 2 */ offset,
+    )
+    .fetch_all(executor)
+    .await
+    .context("fetch logs")?
+    .into_iter()
+    .map(|log| log.try_into())
+    .collect::<Result<Vec<_>, _>>()
+}
+
+/// Select the most recently appended log, ignoring logs inserted by a resync.
+pub async fn select_tail_log<'e>(
+    executor: impl PgExecutor<'e>,
+) -> anyhow::Result<Option<fritz::Log>> {
+    sqlx::query_as!(
+        Log,
+        r#"
+    SELECT "id",
+           "datetime",
+           "message",
+           "message_id",
+           "category_id",
+           "repetition_datetime",
+           "repetition_count",
+           "datetime_resolution",
+           "repetition_datetime_resolution"
+    FROM "logs"
+    WHERE NOT "resynced"
+    ORDER BY "id" DESC
+    LIMIT 1
+        "#
+    )
+    .fetch_optional(executor)
+    .await
+    .context("fetch tail log")?
+    .map(|log| log.try_into())
+    .transpose()
+}
+
+/// Whether the logs on the FRITZ!Box have been cleared after the most
+/// recently appended log has been archived.
+pub async fn select_tail_cleared<'e>(executor: impl PgExecutor<'e>) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+    SELECT EXISTS (
+        SELECT 1
+        FROM "log_clears"
+        WHERE "log_id" = (
+            SELECT "id"
+            FROM "logs"
+            WHERE NOT "resynced"
+            ORDER BY "id" DESC
+            LIMIT 1
+        )
+    ) AS "cleared!"
+        "#
+    )
+    .fetch_one(executor)
+    .await
+    .context("fetch tail cleared")
+    .map(|row| row.cleared)
+}
+
+/// Select all logs that have been logged at or after `datetime`.
+pub async fn select_logs_since<'e>(
+    executor: impl PgExecutor<'e>,
+    datetime: DateTime<Utc>,
+) -> anyhow::Result<Vec<fritz::Log>> {
+    sqlx::query_as!(
+        Log,
+        r#"
+    SELECT "id",
+           "datetime",
+           "message",
+           "message_id",
+           "category_id",
+           "repetition_datetime",
+           "repetition_count",
+           "datetime_resolution",
+           "repetition_datetime_resolution"
+    FROM "logs"
+    WHERE "datetime" >= $1
+    ORDER BY "id" ASC
+        "#,
+        /* 1 */ datetime,
+    )
+    .fetch_all(executor)
+    .await
+    .context("fetch logs")?
+    .into_iter()
+    .map(|log| log.try_into())
+    .collect::<Result<Vec<_>, _>>()
+}
+
+/// Append logs to the database in a single statement without checking for
+/// consistency and record their repetitions.
+///
+/// Logs inserted by a resync must set `resynced`.
+pub async fn insert_logs<'e>(
+    executor: impl PgExecutor<'e>,
+    logs: &[fritz::Log],
+    resynced: bool,
+) -> anyhow::Result<()> {
+    let len = logs.len();
+    let mut datetimes = Vec::<DateTime<Utc>>::with_capacity(len);
+    let mut messages = Vec::<String>::with_capacity(len);
+    let mut message_ids = Vec::<i64>::with_capacity(len);
+    let mut category_ids = Vec::<i64>::with_capacity(len);
+    let mut repetition_datetimes = Vec::<Option<DateTime<Utc>>>::with_capacity(len);
+    let mut repetition_counts = Vec::<Option<i64>>::with_capacity(len);
+    let mut datetime_resolutions = Vec::<Option<String>>::with_capacity(len);
+    let mut repetition_datetime_resolutions = Vec::<Option<String>>::with_capacity(len);
+
+    for log in logs {
+        let log = Log::from(log.clone());
+        datetimes.push(log.datetime);
+        messages.push(log.message);
+        message_ids.push(log.message_id);
+        category_ids.push(log.category_id);
+        repetition_datetimes.push(log.repetition_datetime);
+        repetition_counts.push(log.repetition_count);
+        datetime_resolutions.push(log.datetime_resolution);
+        repetition_datetime_resolutions.push(log.repetition_datetime_resolution);
+    }
+
+    sqlx::query!(
+        r#"
+    WITH "inserted" AS (
+        INSERT INTO "logs"
+        (
+            "datetime",
+            "message",
+            "message_id",
+            "category_id",
+            "repetition_datetime",
+            "repetition_count",
+            "datetime_resolution",
+            "repetition_datetime_resolution",
+            "resynced"
+        )
+        SELECT "datetime",
+               "message",
+               "message_id",
+               "category_id",
+               "repetition_datetime",
+               "repetition_count",
+               "datetime_resolution",
+               "repetition_datetime_resolution",
+               $9
+        FROM UNNEST($1::TIMESTAMPTZ[], $2::TEXT[], $3::BIGINT[], $4::BIGINT[], $5::TIMESTAMPTZ[], $6::BIGINT[], $7::TEXT[], $8::TEXT[])
+            WITH ORDINALITY AS "new" (
+                "datetime",
+                "message",
+                "message_id",
+                "category_id",
+                "repetition_datetime",
+                "repetition_count",
+                "datetime_resolution",
+                "repetition_datetime_resolution",
+                "index"
+            )
+        ORDER BY "index"
+        RETURNING "id", "datetime", "repetition_count"
+    )
+    INSERT INTO "log_repetitions"
+    (
+        "log_id",
+        "datetime",
+        "repetition_count"
+    )
+    SELECT "id", "datetime", "repetition_count"
+    FROM "inserted"
+    WHERE "repetition_count" IS NOT NULL
+        "#,
+        /* 1 */ &datetimes,
+        /* 2 */ &messages,
+        /* 3 */ &message_ids,
+        /* 4 */ &category_ids,
+        /* 5 */ &repetition_datetimes as &[Option<DateTime<Utc>>],
+        /* 6 */ &repetition_counts as &[Option<i64>],
+        /* 7 */ &datetime_resolutions as &[Option<String>],
+        /* 8 */ &repetition_datetime_resolutions as &[Option<String>],
+        /* 9 */ resynced,
+    )
+    .execute(executor)
+    .await
+    .context("insert logs")?;
+
+    Ok(())
+}
+
+/// Update a log in the database and record its new repetition.
+///
+/// Returns the number of updated logs.
+pub async fn update_log<'e>(
+    executor: impl PgExecutor<'e>,
+    old: &fritz::Log,
+    new: &fritz::Log,
+) -> anyhow::Result<i64> {
+    let old_log = Log::from(old.clone());
+    let new_log = Log::from(new.clone());
+
+    sqlx::query!(
+        r#"
+    WITH "updated" AS (
+        UPDATE "logs"
+        SET "datetime"                       = $1,
+            "message"                        = $2,
+            "message_id"                     = $3,
+            "category_id"                    = $4,
+            "repetition_datetime"            = $5,
+            "repetition_count"               = $6,
+            "datetime_resolution"            = $7,
+            "repetition_datetime_resolution" = $8
+        WHERE "datetime"    = $9 AND
+              "message_id"  = $10 AND
+              "category_id" = $11
+        RETURNING "id", "datetime", "repetition_count"
+    ), "repetitions" AS (
+        INSERT INTO "log_repetitions"
+        (
+            "log_id",
+            "datetime",
+            "repetition_count"
+        )
+        SELECT "id", "datetime", "repetition_count"
+        FROM "updated"
+        WHERE "repetition_count" IS NOT NULL
+    )
+    SELECT count(*) AS "count!"
+    FROM "updated"
+        "#,
+        /* 1 */ new_log.datetime,
+        /* 2 */ new_log.message,
+        /* 3 */ new_log.message_id,
+        /* 4 */ new_log.category_id,
+        /* 5 */ new_log.repetition_datetime,
+        /* 6 */ new_log.repetition_count,
+        /* 7 */ new_log.datetime_resolution,
+        /* 8 */ new_log.repetition_datetime_resolution,
+        /*  9 */ old_log.datetime,
+        /* 10 */ old_log.message_id,
+        /* 11 */ old_log.category_id,
+    )
+    .fetch_one(executor)
+    .await
+    .context("update log")
+    .map(|row| row.count)
+}
+
+/// Flag a log that is in the database but not on the FRITZ!Box, unless it has
+/// already been flagged.
+pub async fn insert_orphan<'e>(
+    executor: impl PgExecutor<'e>,
+    log: &fritz::Log,
+    datetime: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let log = Log::from(log.clone());
+
+    sqlx::query!(
+        r#"
+    INSERT INTO "log_orphans"
+    (
+        "log_id",
+        "datetime"
+    )
+    SELECT "id", $4
+    FROM "logs"
+    WHERE "datetime"    = $1 AND
+          "message_id"  = $2 AND
+          "category_id" = $3
+    ON CONFLICT DO NOTHING
+        "#,
+        /* 1 */ log.datetime,
+        /* 2 */ log.message_id,
+        /* 3 */ log.category_id,
+        /* 4 */ datetime,
+    )
+    .execute(executor)
+    .await
+    .context("insert orphan")?;
+
+    Ok(())
+}
+
+/// Record that the logs on the FRITZ!Box have been cleared after the most
+/// recently appended log has been archived.
+pub async fn insert_clear<'e>(
+    executor: impl PgExecutor<'e>,
+    datetime: DateTime<Utc>,
+    archived_logs: i64,
+) -> anyhow::Result<()> {
+    let result = sqlx::query!(
+        r#"
+    INSERT INTO "log_clears"
+    (
+        "log_id",
+        "datetime",
+        "archived_logs"
+    )
+    SELECT "id", $1, $2
+    FROM "logs"
+    WHERE NOT "resynced"
+    ORDER BY "id" DESC
+    LIMIT 1
+    ON CONFLICT DO NOTHING
+        "#,
+        /* 1 */ datetime,
+        /* 2 */ archived_logs,
+    )
+    .execute(executor)
+    .await
+    .context("insert clear")?;
+
+    if result.rows_affected() == 0 {
+        log::warn!("recorded no clear, the database is empty or the clear was already recorded");
+    }
+    Ok(())
+}
+
+/// Insert a gap unless the same gap has already been recorded.
+pub async fn insert_gap<'e>(executor: impl PgExecutor<'e>, gap: &Gap) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+    INSERT INTO "log_gaps"
+    (
+        "datetime",
+        "kind",
+        "after_datetime",
+        "before_datetime"
+    )
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT DO NOTHING
+        "#,
+        /* 1 */ gap.datetime,
+        /* 2 */ gap.kind,
+        /* 3 */ gap.after_datetime,
+        /* 4 */ gap.before_datetime,
+    )
+    .execute(executor)
+    .await
+    .context("insert gap")?;
+
+    Ok(())
+}
+
+pub async fn insert_update<'e>(
+    executor: impl PgExecutor<'e>,
+    update: &Update,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+    INSERT INTO "updates"
+    (
+        "datetime",
+        "upserted_rows"
+    )
+    VALUES ($1, $2)
+        "#,
+        /* 1 */ update.datetime,
+        /* 2 */ update.upserted_rows,
+    )
+    .execute(executor)
+    .await
+    .context("insert update")?;
+
+    Ok(())
+}
+
+/// Delete at most `limit` rows from `table` that have been recorded before
+/// `before`, oldest first.
+///
+/// Returns the number of deleted rows.
+pub async fn prune<'e>(
+    executor: impl PgExecutor<'e>,
+    table: Table,
+    before: DateTime<Utc>,
+    limit: usize,
+) -> anyhow::Result<u64> {
+    // the most recently appended log must stay, see `Table::Logs`
+    let keep_tail = match table {
+        Table::Logs => {
+            r#"AND "id" <> COALESCE((SELECT max("id") FROM "logs" WHERE NOT "resynced"), 0)"#
+        }
+        Table::Requests
+        | Table::Updates
+        | Table::Ping
+        | Table::Ping1m
+        | Table::Ping1h
+        | Table::PollRuns => "",
+    };
+    let limit = i64::try_from(limit).context("cast limit as i64")?;
+
+    let result = sqlx::query(&format!(
+        r#"
+    DELETE FROM "{table}"
+    WHERE "id" IN (
+        SELECT "id"
+        FROM "{table}"
+        WHERE "datetime" < $1 {keep_tail}
+        ORDER BY "datetime" ASC
+        LIMIT $2
+    )
+        "#,
+        table = table.name(),
+        keep_tail = keep_tail,
+    ))
+    .bind(before)
+    .bind(limit)
+    .execute(executor)
+    .await
+    .with_context(|| format!("prune {}", table))?;
+
+    Ok(result.rows_affected())
+}
+
+/// Build the query selecting the logs matching `query`.
+pub fn select_logs(query: &LogQuery) -> anyhow::Result<QueryBuilder<'static, Postgres>> {
+    let mut builder = QueryBuilder::new(
+        r#"
+    SELECT "id",
+           "datetime",
+           "message",
+           "message_id",
+           "category_id",
+           "repetition_datetime",
+           "repetition_count",
+           "datetime_resolution",
+           "repetition_datetime_resolution"
+    FROM "logs"
+    WHERE TRUE"#,
+    );
+
+    if let Some(since) = query.since {
+        builder.push(r#" AND "datetime" >= "#).push_bind(since);
+    }
+    if let Some(until) = query.until {
+        builder.push(r#" AND "datetime" < "#).push_bind(until);
+    }
+    if let Some(message_ids) = query.message_ids.as_ref() {
+        builder
+            .push(r#" AND "message_id" = ANY("#)
+            .push_bind(message_ids.clone())
+            .push(")");
+    }
+    if let Some(category_id) = query.category_id {
+        builder
+            .push(r#" AND "category_id" = "#)
+            .push_bind(category_id);
+    }
+    match query.message.as_ref() {
+        None => {}
+        Some(MessageFilter::Contains(needle)) => {
+            builder
+                .push(r#" AND strpos("message", "#)
+                .push_bind(needle.clone())
+                .push(") > 0");
+        }
+        Some(MessageFilter::Regex(pattern)) => {
+            builder
+                .push(r#" AND "message" ~ "#)
+                .push_bind(pattern.clone());
+        }
+    }
+    if query.repeated_only {
+        builder.push(r#" AND "repetition_count" IS NOT NULL"#);
+    }
+
+    let (comparison, order) = match query.order {
+        Order::Ascending => (">", "ASC"),
+        Order::Descending => ("<", "DESC"),
+    };
+    if let Some(after) = query.after {
+        builder
+            .push(format_args!(r#" AND "id" {} "#, comparison))
+            .push_bind(after);
+    }
+    builder.push(format_args!(r#" ORDER BY "id" {}"#, order));
+    if let Some(limit) = query.limit {
+        let limit = i64::try_from(limit).context("cast limit as i64")?;
+        builder.push(" LIMIT ").push_bind(limit);
+    }
+
+    Ok(builder)
+}
+
+/// A log matching a full-text search
+#[derive(sqlx::FromRow)]
+pub struct SearchRow {
+    #[sqlx(flatten)]
+    pub log: Log,
+    pub rank: f32,
+    pub snippet: String,
+}
+
+/// Build the query full-text searching the messages of the logs that have
+/// last been logged within `range`.
+///
+/// `text` is parsed by `websearch_to_tsquery`, so it can contain quoted
+/// phrases, `or` and `-` to exclude words.
+pub fn search_logs(
+    text: &str,
+    range: (Bound<DateTime<Utc>>, Bound<DateTime<Utc>>),
+    limit: usize,
+) -> anyhow::Result<QueryBuilder<'static, Postgres>> {
+    let mut builder = QueryBuilder::new(
+        r#"
+    SELECT "id",
+           "datetime",
+           "message",
+           "message_id",
+           "category_id",
+           "repetition_datetime",
+           "repetition_count",
+           "datetime_resolution",
+           "repetition_datetime_resolution",
+           ts_rank("message_search", "query") AS "rank",
+           ts_headline('german', "message", "query", "#,
+    );
+    builder
+        .push_bind(format!(
+            "StartSel={}, StopSel={}",
+            HIGHLIGHT_START, HIGHLIGHT_END
+        ))
+        .push(
+            r#") AS "snippet"
+    FROM "logs", websearch_to_tsquery('german', "#,
+        )
+        .push_bind(text.to_string())
+        .push(
+            r#") AS "query"
+    WHERE "message_search" @@ "query""#,
+        );
+
+    match range.0 {
+        Bound::Included(since) => {
+            builder.push(r#" AND "datetime" >= "#).push_bind(since);
+        }
+        Bound::Excluded(since) => {
+            builder.push(r#" AND "datetime" > "#).push_bind(since);
+        }
+        Bound::Unbounded => {}
+    }
+    match range.1 {
+        Bound::Included(until) => {
+            builder.push(r#" AND "datetime" <= "#).push_bind(until);
+        }
+        Bound::Excluded(until) => {
+            builder.push(r#" AND "datetime" < "#).push_bind(until);
+        }
+        Bound::Unbounded => {}
+    }
+
+    let limit = i64::try_from(limit).context("cast limit as i64")?;
+    builder
+        .push(r#" ORDER BY "rank" DESC, "id" DESC LIMIT "#)
+        .push_bind(limit);
+
+    Ok(builder)
+}
+
+/// Build the query selecting the requests matching `query`.
+pub fn select_requests(query: &RequestQuery) -> anyhow::Result<QueryBuilder<'static, Postgres>> {
+    let mut builder = QueryBuilder::new(
+        r#"
+    SELECT "id",
+           "datetime",
+           "name",
+           "url",
+           "method",
+           "duration_ms",
+           "response_code",
+           "session_id",
+           "error_kind",
+           "error_message",
+           "response_bytes",
+           "attempt",
+           "remote_addr"
+    FROM "requests"
+    WHERE TRUE"#,
+    );
+
+    if let Some(since) = query.since {
+        builder.push(r#" AND "datetime" >= "#).push_bind(since);
+    }
+    if let Some(until) = query.until {
+        builder.push(r#" AND "datetime" < "#).push_bind(until);
+    }
+    if let Some(name) = query.name.as_ref() {
+        builder.push(r#" AND "name" = "#).push_bind(name.clone());
+    }
+    if query.failed_only {
+        builder.push(r#" AND "error_kind" IS NOT NULL"#);
+    }
+    builder.push(r#" ORDER BY "datetime" ASC, "id" ASC"#);
+    if let Some(limit) = query.limit {
+        let limit = i64::try_from(limit).context("cast limit as i64")?;
+        builder.push(" LIMIT ").push_bind(limit);
+    }
+
+    Ok(builder)
+}
+
+const PING_ROLLUP_COLUMNS: [&str; 12] = [
+    "target",
+    "datetime",
+    "count",
+    "loss_count",
+    "loss_ratio",
+    "min_ms",
+    "avg_ms",
+    "max_ms",
+    "p50_ms",
+    "p95_ms",
+    "p99_ms",
+    "jitter_ms",
+];
+
+/// Build the query inserting `rollups`, replacing rollups of the same target
+/// and bucket.
+pub fn upsert_ping_rollups(
+    resolution: Resolution,
+    rollups: &[PingRollup],
+) -> QueryBuilder<'static, Postgres> {
+    let columns = PING_ROLLUP_COLUMNS
+        .iter()
+        .map(|column| format!(r#""{}""#, column))
+        .collect::<Vec<_>>();
+    let mut builder = QueryBuilder::new(format!(
+        r#"INSERT INTO "{}" ({}) "#,
+        resolution.table().name(),
+        columns.join(", ")
+    ));
+    builder.push_values(rollups, |mut row, rollup| {
+        row.push_bind(rollup.target.clone())
+            .push_bind(rollup.datetime)
+            .push_bind(rollup.count)
+            .push_bind(rollup.loss_count)
+            .push_bind(rollup.loss_ratio)
+            .push_bind(rollup.min_ms)
+            .push_bind(rollup.avg_ms)
+            .push_bind(rollup.max_ms)
+            .push_bind(rollup.p50_ms)
+            .push_bind(rollup.p95_ms)
+            .push_bind(rollup.p99_ms)
+            .push_bind(rollup.jitter_ms);
+    });
+    let updates = columns[2..]
+        .iter()
+        .map(|column| format!("{} = EXCLUDED.{}", column, column))
+        .collect::<Vec<_>>();
+    builder.push(format_args!(
+        r#" ON CONFLICT ("target", "datetime") DO UPDATE SET {}"#,
+        updates.join(", ")
+    ));
+    builder
+}
+
+/// Build the query selecting the rollups of `resolution` whose bucket starts
+/// within `range`, optionally only of one target.
+pub fn select_ping_rollups(
+    resolution: Resolution,
+    target: Option<&str>,
+    range: (Bound<DateTime<Utc>>, Bound<DateTime<Utc>>),
+) -> QueryBuilder<'static, Postgres> {
+    let mut builder = QueryBuilder::new(format!(
+        r#"SELECT "id", {} FROM "{}" WHERE TRUE"#,
+        PING_ROLLUP_COLUMNS
+            .iter()
+            .map(|column| format!(r#""{}""#, column))
+            .collect::<Vec<_>>()
+            .join(", "),
+        resolution.table().name()
+    ));
+
+    if let Some(target) = target {
+        builder
+            .push(r#" AND "target" = "#)
+            .push_bind(target.to_string());
+    }
+    match range.0 {
+        Bound::Included(since) => {
+            builder.push(r#" AND "datetime" >= "#).push_bind(since);
+        }
+        Bound::Excluded(since) => {
+            builder.push(r#" AND "datetime" > "#).push_bind(since);
+        }
+        Bound::Unbounded => {}
+    }
+    match range.1 {
+        Bound::Included(until) => {
+            builder.push(r#" AND "datetime" <= "#).push_bind(until);
+        }
+        Bound::Excluded(until) => {
+            builder.push(r#" AND "datetime" < "#).push_bind(until);
+        }
+        Bound::Unbounded => {}
+    }
+    builder.push(r#" ORDER BY "datetime" ASC, "target" ASC"#);
+
+    builder
+}
@@ -0,0 +1,497 @@
+//! Opt-in conversion of the time-series tables into TimescaleDB hypertables
+//! or, without TimescaleDB, into tables partitioned by month.
+//!
+//! Both keep the columns, unique constraints and indexes the queries rely on,
+//! so nothing else has to know which mode a database is in. Only primary keys
+//! change from `("id")` to `("id", "datetime")`, because every unique
+//! constraint has to contain the column a table is partitioned by.
+
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use sqlx::{Connection, PgPool, Postgres};
+
+use crate::db::model::Table;
+
+/// How many months of partitions are created ahead of time
+const MONTHS_AHEAD: u32 = 12;
+
+/// How to convert the time-series tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSeriesMode {
+    /// TimescaleDB if the extension can be installed, partitioning otherwise
+    Auto,
+    /// TimescaleDB hypertables with compression and continuous aggregates
+    Timescale,
+    /// Declarative partitioning by month
+    Partitioned,
+}
+
+impl std::fmt::Display for TimeSeriesMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TimeSeriesMode::Auto => "auto",
+            TimeSeriesMode::Timescale => "timescale",
+            TimeSeriesMode::Partitioned => "partitioned",
+        })
+    }
+}
+
+impl std::str::FromStr for TimeSeriesMode {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "auto" => Ok(TimeSeriesMode::Auto),
+            "timescale" => Ok(TimeSeriesMode::Timescale),
+            "partitioned" => Ok(TimeSeriesMode::Partitioned),
+            _ => anyhow::bail!("unknown time-series mode {:?}", s),
+        }
+    }
+}
+
+/// A table that grows with time and is converted
+struct TimeSeriesTable {
+    table: Table,
+    /// Unique constraint besides the primary key, must contain `"datetime"`
+    unique: Option<&'static str>,
+    /// Column compressed chunks are segmented by
+    segment_by: &'static str,
+    /// Chunks older than this are compressed
+    compress_after: &'static str,
+}
+
+const TIME_SERIES_TABLES: [TimeSeriesTable; 4] = [
+    TimeSeriesTable {
+        table: Table::Ping,
+        unique: None,
+        segment_by: "target",
+        compress_after: "7 days",
+    },
+    TimeSeriesTable {
+        table: Table::Requests,
+        unique: None,
+        segment_by: "name",
+        compress_after: "7 days",
+    },
+    // rollups are upserted for a while after their bucket ended
+    TimeSeriesTable {
+        table: Table::Ping1m,
+        unique: Some(r#""target", "datetime""#),
+        segment_by: "target",
+        compress_after: "30 days",
+    },
+    TimeSeriesTable {
+        table: Table::Ping1h,
+        unique: Some(r#""target", "datetime""#),
+        segment_by: "target",
+        compress_after: "30 days",
+    },
+];
+
+/// Continuous aggregates for reports over long time ranges. Percentiles and
+/// jitter can't be aggregated continuously, they're in the rollup tables.
+const CONTINUOUS_AGGREGATES: [(&str, &str); 2] = [
+    (
+        "ping_1d",
+        r#"
+    SELECT "target",
+           time_bucket(INTERVAL '1 day', "datetime") AS "datetime",
+           count(*) AS "count",
+           count(*) - count("duration_ms") AS "loss_count",
+           min("duration_ms") AS "min_ms",
+           avg("duration_ms")::DOUBLE PRECISION AS "avg_ms",
+           max("duration_ms") AS "max_ms"
+    FROM "ping"
+    GROUP BY "target", time_bucket(INTERVAL '1 day', "datetime")
+        "#,
+    ),
+    (
+        "requests_1h",
+        r#"
+    SELECT "name",
+           time_bucket(INTERVAL '1 hour', "datetime") AS "datetime",
+           count(*) AS "count",
+           sum(CASE WHEN "response_code" IS NULL OR "response_code" >= 400 THEN 1 ELSE 0 END) AS "error_count",
+           avg("duration_ms")::DOUBLE PRECISION AS "avg_ms",
+           max("duration_ms") AS "max_ms"
+    FROM "requests"
+    GROUP BY "name", time_bucket(INTERVAL '1 hour', "datetime")
+        "#,
+    ),
+];
+
+/// Convert every time-series table that hasn't been converted yet.
+///
+/// Returns the mode that has been used, never [`TimeSeriesMode::Auto`].
+pub async fn convert(pool: &PgPool, mode: TimeSeriesMode) -> anyhow::Result<TimeSeriesMode> {
+    let mode = match mode {
+        TimeSeriesMode::Timescale => {
+            install_timescale(pool).await?;
+            TimeSeriesMode::Timescale
+        }
+        TimeSeriesMode::Partitioned => TimeSeriesMode::Partitioned,
+        TimeSeriesMode::Auto => match install_timescale(pool).await {
+            Ok(()) => TimeSeriesMode::Timescale,
+            Err(err) => {
+                log::info!(
+                    "partitioning by month, TimescaleDB isn't available: {:#}",
+                    err
+                );
+                TimeSeriesMode::Partitioned
+            }
+        },
+    };
+
+    for spec in TIME_SERIES_TABLES.iter() {
+        if is_converted(pool, spec.table).await? {
+            log::info!("{} has already been converted", spec.table);
+            continue;
+        }
+        match mode {
+            TimeSeriesMode::Timescale => create_hypertable(pool, spec).await?,
+            TimeSeriesMode::Partitioned | TimeSeriesMode::Auto => partition(pool, spec).await?,
+        }
+        log::info!("converted {} ({})", spec.table, mode);
+    }
+
+    if mode == TimeSeriesMode::Timescale {
+        create_continuous_aggregates(pool).await?;
+    }
+
+    Ok(mode)
+}
+
+/// Create the partitions for the coming months of every partitioned
+/// time-series table.
+///
+/// Rows outside of every monthly partition end up in the default partition,
+/// so inserts never fail, but a partition can't be created anymore once the
+/// default partition contains rows of its month.
+pub async fn create_partitions_ahead(pool: &PgPool) -> anyhow::Result<()> {
+    let this_month = first_of_month(Utc::now().date_naive());
+    let until = this_month + Months::new(MONTHS_AHEAD);
+
+    for spec in TIME_SERIES_TABLES.iter() {
+        if is_partitioned(pool, spec.table).await? {
+            let mut conn = pool.acquire().await.context("acquire connection")?;
+            create_partitions(&mut conn, spec.table, this_month, until).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn install_timescale(pool: &PgPool) -> anyhow::Result<()> {
+    sqlx::query("CREATE EXTENSION IF NOT EXISTS timescaledb")
+        .execute(pool)
+        .await
+        .context("create timescaledb extension")
+        .map(|_| ())
+}
+
+async fn is_partitioned(pool: &PgPool, table: Table) -> anyhow::Result<bool> {
+    sqlx::query_scalar(
+        r#"
+    SELECT EXISTS (
+        SELECT 1
+        FROM "pg_partitioned_table"
+        WHERE "partrelid" = to_regclass($1)
+    )
+        "#,
+    )
+    .bind(format!(r#""{}""#, table.name()))
+    .fetch_one(pool)
+    .await
+    .with_context(|| format!("check whether {} is partitioned", table))
+}
+
+async fn is_converted(pool: &PgPool, table: Table) -> anyhow::Result<bool> {
+    if is_partitioned(pool, table).await? {
+        return Ok(true);
+    }
+
+    let timescale = sqlx::query_scalar::<_, bool>(
+        r#"SELECT EXISTS (SELECT 1 FROM "pg_extension" WHERE "extname" = 'timescaledb')"#,
+    )
+    .fetch_one(pool)
+    .await
+    .context("check for timescaledb")?;
+    if !timescale {
+        return Ok(false);
+    }
+
+    sqlx::query_scalar(
+        r#"
+    SELECT EXISTS (
+        SELECT 1
+        FROM "timescaledb_information"."hypertables"
+        WHERE "hypertable_name" = $1
+    )
+        "#,
+    )
+    .bind(table.name())
+    .fetch_one(pool)
+    .await
+    .with_context(|| format!("check whether {} is a hypertable", table))
+}
+
+/// Replace the primary key `("id")` with `("id", "datetime")`.
+async fn extend_primary_key(conn: &mut sqlx::PgConnection, table: Table) -> anyhow::Result<()> {
+    let sql = format!(
+        r#"
+    ALTER TABLE "{table}" DROP CONSTRAINT "{table}_pkey";
+    ALTER TABLE "{table}" ADD PRIMARY KEY ("id", "datetime");
+        "#,
+        table = table.name()
+    );
+    execute_script(conn, &sql)
+        .await
+        .with_context(|| format!("extend primary key of {}", table))
+}
+
+/// Execute several statements at once, which prepared statements can't.
+async fn execute_script(conn: &mut sqlx::PgConnection, sql: &str) -> sqlx::Result<()> {
+    sqlx::Executor::execute(conn, sql).await.map(|_| ())
+}
+
+async fn create_hypertable(pool: &PgPool, spec: &TimeSeriesTable) -> anyhow::Result<()> {
+    let table = spec.table.name();
+    let mut tx = pool.begin().await.context("begin transaction")?;
+
+    extend_primary_key(&mut tx, spec.table).await?;
+    sqlx::query(
+        "SELECT create_hypertable($1::regclass, 'datetime', \
+         chunk_time_interval => INTERVAL '7 days', migrate_data => true)",
+    )
+    .bind(format!(r#""{}""#, table))
+    .execute(&mut *tx)
+    .await
+    .with_context(|| format!("create hypertable {}", table))?;
+
+    let sql = format!(
+        r#"
+    ALTER TABLE "{table}" SET (
+        timescaledb.compress,
+        timescaledb.compress_segmentby = '{segment_by}',
+        timescaledb.compress_orderby = 'datetime'
+    );
+    SELECT add_compression_policy('"{table}"', INTERVAL '{compress_after}', if_not_exists => true);
+        "#,
+        table = table,
+        segment_by = spec.segment_by,
+        compress_after = spec.compress_after,
+    );
+    execute_script(&mut tx, &sql)
+        .await
+        .with_context(|| format!("enable compression of {}", table))?;
+
+    tx.commit().await.context("commit transaction")
+}
+
+/// Continuous aggregates can't be created within a transaction.
+async fn create_continuous_aggregates(pool: &PgPool) -> anyhow::Result<()> {
+    for (view, select) in CONTINUOUS_AGGREGATES {
+        sqlx::query(&format!(
+            r#"
+    CREATE MATERIALIZED VIEW IF NOT EXISTS "{view}"
+    WITH (timescaledb.continuous) AS {select}
+    WITH NO DATA
+            "#,
+            view = view,
+            select = select,
+        ))
+        .execute(pool)
+        .await
+        .with_context(|| format!("create continuous aggregate {}", view))?;
+
+        sqlx::query(&format!(
+            r#"
+    SELECT add_continuous_aggregate_policy('"{view}"',
+        start_offset => INTERVAL '3 days',
+        end_offset => INTERVAL '1 hour',
+        schedule_interval => INTERVAL '1 hour',
+        if_not_exists => true)
+            "#,
+            view = view,
+        ))
+        .execute(pool)
+        .await
+        .with_context(|| format!("schedule refresh of {}", view))?;
+    }
+    Ok(())
+}
+
+/// Move the rows of `spec.table` into a new table partitioned by month that
+/// takes over its name, columns, sequence, constraints and indexes.
+///
+/// The primary key becomes `("id", "datetime")` and the unique constraint
+/// `spec.unique` is recreated. Other unique indexes can't be recreated unless
+/// they contain `"datetime"`, so the time-series tables must not have any.
+async fn partition(pool: &PgPool, spec: &TimeSeriesTable) -> anyhow::Result<()> {
+    let table = spec.table.name();
+    let mut tx = pool.begin().await.context("begin transaction")?;
+
+    let sql = format!(
+        r#"
+    ALTER TABLE "{table}" RENAME TO "{table}_unpartitioned";
+    CREATE TABLE "{table}" (LIKE "{table}_unpartitioned" INCLUDING ALL EXCLUDING INDEXES)
+        PARTITION BY RANGE ("datetime");
+    CREATE TABLE "{table}_default" PARTITION OF "{table}" DEFAULT;
+        "#,
+        table = table
+    );
+    execute_script(&mut tx, &sql)
+        .await
+        .with_context(|| format!("create partitioned {}", table))?;
+
+    let oldest: Option<DateTime<Utc>> = sqlx::query_scalar(&format!(
+        r#"SELECT min("datetime") FROM "{}_unpartitioned""#,
+        table
+    ))
+    .fetch_one(&mut *tx)
+    .await
+    .with_context(|| format!("fetch oldest row of {}", table))?;
+    let this_month = first_of_month(Utc::now().date_naive());
+    let since = oldest.map_or(this_month, |oldest| first_of_month(oldest.date_naive()));
+    create_partitions(
+        &mut tx,
+        spec.table,
+        since,
+        this_month + Months::new(MONTHS_AHEAD),
+    )
+    .await?;
+
+    // the sequence would be dropped together with the old table
+    let sequence: String = sqlx::query_scalar("SELECT pg_get_serial_sequence($1, 'id')")
+        .bind(format!(r#""{}_unpartitioned""#, table))
+        .fetch_one(&mut *tx)
+        .await
+        .with_context(|| format!("fetch id sequence of {}", table))?;
+
+    // the indexes keep their names until they're dropped with the old table
+    let indexes: Vec<String> = sqlx::query_scalar(
+        r#"
+    SELECT replace(
+        pg_get_indexdef("index"."indexrelid"),
+        ' ON ' || quote_ident("namespace"."nspname") || '.' || quote_ident("table"."relname") || ' ',
+        ' ON ' || quote_ident("namespace"."nspname") || '.' || quote_ident($2) || ' '
+    )
+    FROM "pg_index" AS "index"
+    JOIN "pg_class" AS "table" ON "table"."oid" = "index"."indrelid"
+    JOIN "pg_namespace" AS "namespace" ON "namespace"."oid" = "table"."relnamespace"
+    WHERE "index"."indrelid" = to_regclass($1) AND NOT "index"."indisunique"
+        "#,
+    )
+    .bind(format!(r#""{}_unpartitioned""#, table))
+    .bind(table)
+    .fetch_all(&mut *tx)
+    .await
+    .with_context(|| format!("fetch indexes of {}", table))?;
+
+    let unique = spec
+        .unique
+        .map(|columns| format!(r#"ALTER TABLE "{}" ADD UNIQUE ({});"#, table, columns))
+        .unwrap_or_default();
+    let sql = format!(
+        r#"
+    INSERT INTO "{table}" SELECT * FROM "{table}_unpartitioned";
+    ALTER SEQUENCE {sequence} OWNED BY "{table}"."id";
+    DROP TABLE "{table}_unpartitioned";
+    ALTER TABLE "{table}" ADD PRIMARY KEY ("id", "datetime");
+    {unique}
+    {indexes}
+    CREATE INDEX IF NOT EXISTS "{table}_datetime_idx" ON "{table}" ("datetime");
+        "#,
+        table = table,
+        sequence = sequence,
+        unique = unique,
+        indexes = indexes
+            .iter()
+            .map(|index| format!("{};", index))
+            .collect::<Vec<_>>()
+            .join("\n    "),
+    );
+    execute_script(&mut tx, &sql)
+        .await
+        .with_context(|| format!("move rows into partitioned {}", table))?;
+
+    tx.commit().await.context("commit transaction")
+}
+
+/// Create the monthly partitions of `table` from the month `since` up to the
+/// month `until`, unless they already exist.
+///
+/// A month whose partition can't be created, e.g. because the default
+/// partition already contains rows of it, is skipped with a warning.
+async fn create_partitions(
+    conn: &mut sqlx::PgConnection,
+    table: Table,
+    since: NaiveDate,
+    until: NaiveDate,
+) -> anyhow::Result<()> {
+    let mut month = since;
+    while month < until {
+        let next = month + Months::new(1);
+
+        // a failed statement would abort the surrounding transaction, so
+        // every partition is created within a savepoint of its own
+        let mut savepoint = conn.begin().await.context("begin savepoint")?;
+        let created = sqlx::query::<Postgres>(&format!(
+            r#"
+    CREATE TABLE IF NOT EXISTS "{table}_{year:04}_{month:02}" PARTITION OF "{table}"
+        FOR VALUES FROM ('{from} 00:00:00+00') TO ('{to} 00:00:00+00')
+            "#,
+            table = table.name(),
+            year = month.year(),
+            month = month.month(),
+            from = month,
+            to = next,
+        ))
+        .execute(&mut *savepoint)
+        .await;
+        match created {
+            Ok(_) => savepoint.commit().await.context("release savepoint")?,
+            // e.g. the default partition already contains rows of the month
+            Err(sqlx::Error::Database(err)) => {
+                savepoint.rollback().await.context("roll back savepoint")?;
+                log::warn!("skipped partition of {} for {}: {}", table, month, err);
+            }
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("create partition of {} for {}", table, month))
+            }
+        }
+        month = next;
+    }
+    Ok(())
+}
+
+fn first_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap_or(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::{first_of_month, TimeSeriesMode};
+
+    #[test]
+    fn parse_mode() {
+        for mode in [
+            TimeSeriesMode::Auto,
+            TimeSeriesMode::Timescale,
+            TimeSeriesMode::Partitioned,
+        ] {
+            assert_eq!(mode.to_string().parse::<TimeSeriesMode>().unwrap(), mode);
+        }
+        assert!("Partitioned".parse::<TimeSeriesMode>().is_err());
+        assert!("".parse::<TimeSeriesMode>().is_err());
+    }
+
+    #[test]
+    fn month_start() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(first_of_month(date(2024, 2, 29)), date(2024, 2, 1));
+        assert_eq!(first_of_month(date(2024, 12, 31)), date(2024, 12, 1));
+        assert_eq!(first_of_month(date(2025, 1, 1)), date(2025, 1, 1));
+    }
+}
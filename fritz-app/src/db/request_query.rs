@@ -0,0 +1,152 @@
+//! Typed filters for selecting the metadata of requests to the FRITZ!Box, see
+//! [`super::Database::query_requests`] and [`super::Database::request_stats`].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+
+use super::model::Request;
+use crate::ping::rollup::percentile;
+
+/// Which requests to select.
+///
+/// Every filter that is set must match. Requests are ordered by the time
+/// they've been sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestQuery {
+    pub(crate) since: Option<DateTime<Utc>>,
+    pub(crate) until: Option<DateTime<Utc>>,
+    pub(crate) name: Option<String>,
+    pub(crate) failed_only: bool,
+    pub(crate) limit: Option<usize>,
+}
+
+impl RequestQuery {
+    pub fn new() -> RequestQuery {
+        RequestQuery::default()
+    }
+
+    /// Only requests sent at or after `datetime`.
+    pub fn since(mut self, datetime: impl Into<DateTime<Utc>>) -> RequestQuery {
+        self.since = Some(datetime.into());
+        self
+    }
+
+    /// Only requests sent before `datetime`.
+    pub fn until(mut self, datetime: impl Into<DateTime<Utc>>) -> RequestQuery {
+        self.until = Some(datetime.into());
+        self
+    }
+
+    /// Only requests of the given name, e.g. `logs`.
+    pub fn name(mut self, name: impl Into<String>) -> RequestQuery {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Only requests that failed.
+    pub const fn failed_only(mut self) -> RequestQuery {
+        self.failed_only = true;
+        self
+    }
+
+    /// Select at most `limit` requests, the oldest ones.
+    pub const fn limit(mut self, limit: usize) -> RequestQuery {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Whether `req` matches every filter but the limit.
+    pub(crate) fn matches(&self, req: &Request) -> bool {
+        self.since.is_none_or(|since| req.datetime >= since)
+            && self.until.is_none_or(|until| req.datetime < until)
+            && self.name.as_ref().is_none_or(|name| req.name == *name)
+            && (!self.failed_only || req.is_failed())
+    }
+}
+
+/// How the FRITZ!Box answered the requests of one name
+///
+/// Latencies include failed requests, sizes only consider requests that
+/// received a response.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct RequestStats {
+    pub name: String,
+    pub count: i64,
+    pub failed_count: i64,
+    /// Requests that have been sent again after the previous one failed
+    pub retry_count: i64,
+    /// Failed requests by [`crate::poll::Failure`]
+    pub error_kinds: BTreeMap<String, i64>,
+    /// Requests by HTTP status
+    pub response_codes: BTreeMap<i64, i64>,
+    pub min_ms: Option<i64>,
+    pub avg_ms: Option<f64>,
+    pub max_ms: Option<i64>,
+    pub p95_ms: Option<f64>,
+    pub avg_bytes: Option<f64>,
+    pub max_bytes: Option<i64>,
+    /// Addresses that answered
+    pub remote_addrs: BTreeSet<String>,
+}
+
+impl RequestStats {
+    /// Group `requests` by name and summarize each group.
+    pub(crate) fn of(requests: &[Request]) -> Vec<RequestStats> {
+        let mut names = BTreeMap::<&str, Vec<&Request>>::new();
+        for req in requests {
+            names.entry(req.name.as_str()).or_default().push(req);
+        }
+
+        names
+            .into_iter()
+            .map(|(name, requests)| RequestStats::summarize(name, &requests))
+            .collect()
+    }
+
+    fn summarize(name: &str, requests: &[&Request]) -> RequestStats {
+        let mut error_kinds = BTreeMap::new();
+        let mut response_codes = BTreeMap::new();
+        for req in requests {
+            if let Some(kind) = req.error_kind.as_ref() {
+                *error_kinds.entry(kind.clone()).or_default() += 1;
+            }
+            if let Some(code) = req.response_code {
+                *response_codes.entry(code).or_default() += 1;
+            }
+        }
+
+        let mut durations = requests
+            .iter()
+            .map(|req| req.duration_ms)
+            .collect::<Vec<_>>();
+        durations.sort_unstable();
+        let bytes = requests
+            .iter()
+            .filter_map(|req| req.response_bytes)
+            .collect::<Vec<_>>();
+
+        RequestStats {
+            name: name.to_string(),
+            count: requests.len() as i64,
+            failed_count: requests.iter().filter(|req| req.is_failed()).count() as i64,
+            retry_count: requests.iter().filter(|req| req.attempt > 1).count() as i64,
+            error_kinds,
+            response_codes,
+            min_ms: durations.first().copied(),
+            avg_ms: mean(&durations),
+            max_ms: durations.last().copied(),
+            p95_ms: percentile(&durations, 0.95),
+            avg_bytes: mean(&bytes),
+            max_bytes: bytes.iter().max().copied(),
+            remote_addrs: requests
+                .iter()
+                .filter_map(|req| req.remote_addr.clone())
+                .collect(),
+        }
+    }
+}
+
+fn mean(values: &[i64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<i64>() as f64 / values.len() as f64)
+}
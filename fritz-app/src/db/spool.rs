@@ -0,0 +1,254 @@
+//! Durable local spool for writes that failed because the database was
+//! unavailable, e.g. while Postgres restarts.
+//!
+//! Writes are appended to `spool.jsonl`, one JSON object per line, and
+//! replayed in order by [`replay_loop`] once the database is back. How far
+//! the spool has been replayed is kept in `spool.offset`, so a restart doesn't
+//! lose spooled writes. A write that has been replayed right before a crash
+//! might be replayed again, spooled logs are deduplicated like any other logs
+//! fetched from the FRITZ!Box. Writes the database rejects for another reason
+//! are moved to `rejected.jsonl` instead of blocking the spool forever.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use super::connection::Database;
+use super::model::{Ping, PollRun, Request};
+use crate::fritz;
+
+const SPOOL_FILE: &str = "spool.jsonl";
+const OFFSET_FILE: &str = "spool.offset";
+const REJECTED_FILE: &str = "rejected.jsonl";
+
+/// How many seconds to wait between attempts to replay the spool
+const REPLAY_PAUSE_SECONDS: u64 = 10;
+
+/// A spooled write
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(super) enum Entry {
+    /// Logs fetched from the FRITZ!Box, replayed with
+    /// [`Database::append_new_logs`]
+    Logs {
+        logs: Vec<fritz::Log>,
+    },
+    Ping(Ping),
+    Request(Request),
+    PollRun(PollRun),
+}
+
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Entry::Logs { logs } => write!(f, "{} logs", logs.len()),
+            Entry::Ping(ping) => write!(f, "ping of {}", ping.target),
+            Entry::Request(req) => write!(f, "{} request metadata", req.name),
+            Entry::PollRun(run) => write!(f, "poll run started at {}", run.datetime),
+        }
+    }
+}
+
+pub struct Spool {
+    dir: PathBuf,
+    state: parking_lot::Mutex<State>,
+}
+
+struct State {
+    file: File,
+    /// Byte offset of the oldest entry that hasn't been replayed
+    offset: u64,
+    /// Byte length of the spool file
+    len: u64,
+}
+
+impl Spool {
+    /// Open the spool in `dir`, creating it if necessary.
+    pub fn open(dir: impl AsRef<Path>) -> anyhow::Result<Spool> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("create spool directory {}", dir.display()))?;
+
+        let path = dir.join(SPOOL_FILE);
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("open {}", path.display()))?;
+
+        // drop an entry that has only been written partially before a crash
+        let len = complete_len(&file).with_context(|| format!("read {}", path.display()))?;
+        file.set_len(len)
+            .with_context(|| format!("truncate {}", path.display()))?;
+
+        let offset = match std::fs::read_to_string(dir.join(OFFSET_FILE)) {
+            Ok(offset) => offset.trim().parse::<u64>().context("parse spool offset")?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err).context("read spool offset"),
+        };
+
+        let spool = Spool {
+            dir,
+            state: parking_lot::Mutex::new(State {
+                file,
+                offset: offset.min(len),
+                len,
+            }),
+        };
+        if spool.is_pending() {
+            log::info!(
+                "{} bytes of spooled writes are waiting to be replayed",
+                len - offset
+            );
+        }
+        Ok(spool)
+    }
+
+    /// Open the spool in `FRITZBOX_SPOOL_DIR`, `None` if it isn't set.
+    pub fn try_from_env() -> anyhow::Result<Option<Spool>> {
+        match std::env::var("FRITZBOX_SPOOL_DIR") {
+            Err(_) => Ok(None),
+            Ok(dir) => Spool::open(dir).map(Some),
+        }
+    }
+
+    /// Whether there are spooled writes that haven't been replayed.
+    pub fn is_pending(&self) -> bool {
+        let state = self.state.lock();
+        state.offset < state.len
+    }
+
+    pub(super) fn push(&self, entry: &Entry) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(entry).context("serialize spool entry")?;
+        line.push(b'\n');
+
+        let mut state = self.state.lock();
+        state.file.write_all(&line).context("append to spool")?;
+        state.file.sync_data().context("sync spool")?;
+        state.len += line.len() as u64;
+        drop(state);
+        Ok(())
+    }
+
+    /// The oldest entry that hasn't been replayed and the offset of the entry
+    /// after it.
+    pub(super) fn peek(&self) -> anyhow::Result<Option<(Entry, u64)>> {
+        let state = self.state.lock();
+        if state.offset >= state.len {
+            return Ok(None);
+        }
+
+        let mut reader = BufReader::new(&state.file);
+        reader
+            .seek(SeekFrom::Start(state.offset))
+            .context("seek in spool")?;
+        let mut line = Vec::new();
+        reader
+            .read_until(b'\n', &mut line)
+            .context("read from spool")?;
+        let entry = serde_json::from_slice(&line)
+            .with_context(|| format!("parse spool entry at byte {}", state.offset))?;
+
+        Ok(Some((entry, state.offset + line.len() as u64)))
+    }
+
+    /// Mark every entry before `offset` as replayed.
+    pub(super) fn advance(&self, offset: u64) -> anyhow::Result<()> {
+        let mut state = self.state.lock();
+        if offset < state.len {
+            let tmp = self.dir.join(format!("{}.tmp", OFFSET_FILE));
+            std::fs::write(&tmp, offset.to_string()).context("write spool offset")?;
+            std::fs::rename(&tmp, self.dir.join(OFFSET_FILE)).context("replace spool offset")?;
+            state.offset = offset;
+            return Ok(());
+        }
+
+        // start over with an empty spool once everything has been replayed
+        state.file.set_len(0).context("truncate spool")?;
+        state.offset = 0;
+        state.len = 0;
+        drop(state);
+
+        match std::fs::remove_file(self.dir.join(OFFSET_FILE)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).context("remove spool offset")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Keep an entry the database rejected, so it can be looked into.
+    pub(super) fn reject(&self, entry: &Entry, err: &anyhow::Error) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(&serde_json::json!({
+            "error": format!("{:#}", err),
+            "entry": entry,
+        }))
+        .context("serialize rejected entry")?;
+        line.push(b'\n');
+
+        let path = self.dir.join(REJECTED_FILE);
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(&line))
+            .with_context(|| format!("append to {}", path.display()))
+    }
+}
+
+/// Length of the complete lines at the start of `file`.
+fn complete_len(file: &File) -> std::io::Result<u64> {
+    let mut reader = BufReader::new(file);
+    let mut len = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line)? {
+            0 => return Ok(len),
+            _ if line.ends_with(b"\n") => len += line.len() as u64,
+            _ => return Ok(len),
+        }
+    }
+}
+
+/// Whether `err` means the database couldn't be reached, rather than that it
+/// rejected a write.
+pub(super) fn is_unavailable(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|cause| match cause.downcast_ref::<sqlx::Error>() {
+            Some(
+                sqlx::Error::Io(_)
+                | sqlx::Error::Tls(_)
+                | sqlx::Error::PoolTimedOut
+                | sqlx::Error::PoolClosed
+                | sqlx::Error::WorkerCrashed,
+            ) => true,
+            // connection exceptions and shutdowns, e.g. "the database system
+            // is starting up"
+            Some(sqlx::Error::Database(err)) => err
+                .code()
+                .is_some_and(|code| code.starts_with("08") || code.starts_with("57P")),
+            _ => false,
+        })
+}
+
+pub async fn replay_loop(db: Database) -> ! {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(REPLAY_PAUSE_SECONDS));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        interval.tick().await;
+
+        match db.replay_spool().await {
+            Ok(0) => {}
+            Ok(replayed) => log::info!("replayed {} spooled writes", replayed),
+            Err(err) if is_unavailable(&err) => {
+                log::debug!("database is still unavailable: {:#}", err);
+            }
+            Err(err) => log::warn!("couldn't replay spooled writes: {:?}", err),
+        }
+    }
+}
@@ -0,0 +1,595 @@
+//! SQLite storage backend for small deployments without a Postgres server.
+//!
+//! `sqlx::query!` can only be checked against a single database at compile
+//! time, so the queries of this backend are checked at runtime.
+
+use std::ops::Bound;
+use std::str::FromStr;
+
+use anyhow::Context;
+use chrono::{DateTime, TimeZone, Utc};
+use futures_util::stream::BoxStream;
+use futures_util::TryStreamExt;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::{Sqlite, SqlitePool};
+
+use super::event::{Event, LocalEvents};
+use super::log_query::{LogQuery, SearchHit, StoredLog};
+use super::model::{
+    Gap, Log, LogRepetition, Ping, PingRollup, PollRun, Request, Resolution, Table, Update,
+};
+use super::postgres::TimeSeriesMode;
+use super::request_query::RequestQuery;
+use super::storage::{Leadership, SoleInstance, Storage, Transaction};
+use crate::fritz;
+
+mod query;
+
+/// Load a timestamp from the database.
+///
+/// Timestamps are saved as unixtime with millisecond precision.
+fn from_timestamp(timestamp: i64) -> anyhow::Result<DateTime<Utc>> {
+    Utc.timestamp_millis_opt(timestamp)
+        .single()
+        .context("utc time from timestamp")
+}
+
+/// Save a timestamp to the database.
+///
+/// Timestamps are saved as unixtime with millisecond precision.
+const fn to_timestamp(datetime: DateTime<Utc>) -> i64 {
+    datetime.timestamp_millis()
+}
+
+/// A row of the `logs` table
+#[derive(sqlx::FromRow)]
+struct LogRow {
+    id: i64,
+    datetime: i64,
+    message: String,
+    message_id: i64,
+    category_id: i64,
+    repetition_datetime: Option<i64>,
+    repetition_count: Option<i64>,
+    datetime_resolution: Option<String>,
+    repetition_datetime_resolution: Option<String>,
+}
+
+impl TryFrom<LogRow> for Log {
+    type Error = anyhow::Error;
+    fn try_from(row: LogRow) -> anyhow::Result<Self> {
+        Ok(Log {
+            id: Some(row.id),
+            datetime: from_timestamp(row.datetime)?,
+            message: row.message,
+            message_id: row.message_id,
+            category_id: row.category_id,
+            repetition_datetime: row.repetition_datetime.map(from_timestamp).transpose()?,
+            repetition_count: row.repetition_count,
+            datetime_resolution: row.datetime_resolution,
+            repetition_datetime_resolution: row.repetition_datetime_resolution,
+        })
+    }
+}
+
+pub struct SqliteStorage {
+    pool: SqlitePool,
+    events: LocalEvents,
+}
+
+impl SqliteStorage {
+    pub async fn open(url: &str) -> anyhow::Result<SqliteStorage> {
+        // don't use a write-ahead log, so the database stays a single file
+        // that can simply be copied
+        let options = SqliteConnectOptions::from_str(url)
+            .context("parse sqlite url")?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Delete)
+            .with_regexp();
+        let pool = SqlitePool::connect_with(options)
+            .await
+            .context("connect to sqlite")?;
+
+        Self::migrate(pool).await
+    }
+
+    pub async fn open_in_memory() -> anyhow::Result<SqliteStorage> {
+        // every connection to `:memory:` opens its own database, so there
+        // must be exactly one connection that is never closed
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(SqliteConnectOptions::from_str("sqlite::memory:")?.with_regexp())
+            .await
+            .context("open in-memory sqlite")?;
+
+        Self::migrate(pool).await
+    }
+
+    async fn migrate(pool: SqlitePool) -> anyhow::Result<SqliteStorage> {
+        sqlx::migrate!("./migrations/sqlite/")
+            .run(&pool)
+            .await
+            .context("migrate database")?;
+
+        Ok(SqliteStorage {
+            pool,
+            events: LocalEvents::new(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for SqliteStorage {
+    async fn begin(&self) -> anyhow::Result<Box<dyn Transaction>> {
+        let tx = self.pool.begin().await.context("begin transaction")?;
+        Ok(Box::new(SqliteTransaction {
+            tx,
+            events: self.events.clone(),
+            published: Vec::new(),
+        }))
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    async fn clear_logs(&self) -> anyhow::Result<()> {
+        sqlx::query(r#"DELETE FROM "logs""#)
+            .execute(&self.pool)
+            .await
+            .context("clear logs")
+            .map(|_| ())
+    }
+
+    async fn count_logs(&self) -> anyhow::Result<usize> {
+        let count: i64 = sqlx::query_scalar(r#"SELECT count(*) FROM "logs""#)
+            .fetch_one(&self.pool)
+            .await
+            .context("fetch row count")?;
+
+        usize::try_from(count).context("negative row count")
+    }
+
+    async fn is_empty(&self) -> anyhow::Result<bool> {
+        let exists: bool = sqlx::query_scalar(r#"SELECT EXISTS (SELECT 1 FROM "logs")"#)
+            .fetch_one(&self.pool)
+            .await
+            .context("check database empty")?;
+
+        Ok(!exists)
+    }
+
+    fn stream_logs(&self, query: LogQuery) -> BoxStream<'_, anyhow::Result<StoredLog>> {
+        Box::pin(async_stream::try_stream! {
+            let mut builder = query::select_logs(&query)?;
+            let mut rows = builder.build_query_as::<LogRow>().fetch(&self.pool);
+            while let Some(row) = rows.try_next().await.context("fetch logs")? {
+                yield StoredLog::try_from(Log::try_from(row)?)?;
+            }
+        })
+    }
+
+    async fn search_logs(
+        &self,
+        text: &str,
+        range: (Bound<DateTime<Utc>>, Bound<DateTime<Utc>>),
+        limit: usize,
+    ) -> anyhow::Result<Vec<SearchHit>> {
+        let Some(mut builder) = query::search_logs(text, range, limit)? else {
+            return Ok(Vec::new());
+        };
+        builder
+            .build_query_as::<query::SearchRow>()
+            .fetch_all(&self.pool)
+            .await
+            .context("search logs")?
+            .into_iter()
+            .map(|row| {
+                Ok(SearchHit {
+                    log: Log::try_from(row.log)?.try_into()?,
+                    rank: row.rank,
+                    snippet: row.snippet,
+                })
+            })
+            .collect()
+    }
+
+    async fn select_log_repetitions(&self, log_id: i64) -> anyhow::Result<Vec<LogRepetition>> {
+        let rows: Vec<(i64, i64, i64, i64)> = sqlx::query_as(
+            r#"
+        SELECT "id",
+               "log_id",
+               "datetime",
+               "repetition_count"
+        FROM "log_repetitions"
+        WHERE "log_id" = ?1
+        ORDER BY "id" ASC
+            "#,
+        )
+        .bind(log_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("fetch log repetitions")?;
+
+        rows.into_iter()
+            .map(|(id, log_id, datetime, repetition_count)| {
+                Ok(LogRepetition {
+                    id: Some(id),
+                    log_id,
+                    datetime: from_timestamp(datetime)?,
+                    repetition_count,
+                })
+            })
+            .collect()
+    }
+
+    async fn try_acquire_leadership(
+        &self,
+        _key: i64,
+    ) -> anyhow::Result<Option<Box<dyn Leadership>>> {
+        // SQLite is meant for a single instance, see the README
+        Ok(Some(Box::new(SoleInstance)))
+    }
+
+    async fn publish(&self, events: &[Event]) -> anyhow::Result<()> {
+        self.events.publish(events);
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> anyhow::Result<BoxStream<'static, anyhow::Result<Event>>> {
+        Ok(self.events.subscribe())
+    }
+
+    async fn insert_request(&self, req: &Request) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+        INSERT INTO "requests"
+        (
+            "datetime",
+            "name",
+            "url",
+            "method",
+            "duration_ms",
+            "response_code",
+            "session_id",
+            "error_kind",
+            "error_message",
+            "response_bytes",
+            "attempt",
+            "remote_addr"
+        )
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+            "#,
+        )
+        .bind(to_timestamp(req.datetime))
+        .bind(&req.name)
+        .bind(&req.url)
+        .bind(&req.method)
+        .bind(req.duration_ms)
+        .bind(req.response_code)
+        .bind(&req.session_id)
+        .bind(&req.error_kind)
+        .bind(&req.error_message)
+        .bind(req.response_bytes)
+        .bind(req.attempt)
+        .bind(&req.remote_addr)
+        .execute(&self.pool)
+        .await
+        .context("insert request")?;
+
+        Ok(())
+    }
+
+    async fn select_requests(&self, query: &RequestQuery) -> anyhow::Result<Vec<Request>> {
+        query::select_requests(query)?
+            .build_query_as::<query::RequestRow>()
+            .fetch_all(&self.pool)
+            .await
+            .context("fetch requests")?
+            .into_iter()
+            .map(Request::try_from)
+            .collect()
+    }
+
+    async fn insert_poll_run(&self, run: &PollRun) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+        INSERT INTO "poll_runs"
+        (
+            "datetime",
+            "finished_datetime",
+            "fetched",
+            "inserted",
+            "updated",
+            "gap",
+            "spooled",
+            "clock_drift_ms",
+            "implausible",
+            "error",
+            "error_message",
+            "version",
+            "host"
+        )
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+            "#,
+        )
+        .bind(to_timestamp(run.datetime))
+        .bind(to_timestamp(run.finished_datetime))
+        .bind(run.fetched)
+        .bind(run.inserted)
+        .bind(run.updated)
+        .bind(run.gap)
+        .bind(run.spooled)
+        .bind(run.clock_drift_ms)
+        .bind(run.implausible)
+        .bind(&run.error)
+        .bind(&run.error_message)
+        .bind(&run.version)
+        .bind(&run.host)
+        .execute(&self.pool)
+        .await
+        .context("insert poll run")?;
+
+        Ok(())
+    }
+
+    async fn select_poll_runs(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<PollRun>> {
+        sqlx::query_as::<_, query::PollRunRow>(
+            r#"
+        SELECT "id",
+               "datetime",
+               "finished_datetime",
+               "fetched",
+               "inserted",
+               "updated",
+               "gap",
+               "spooled",
+               "clock_drift_ms",
+               "implausible",
+               "error",
+               "error_message",
+               "version",
+               "host"
+        FROM "poll_runs"
+        WHERE "datetime" >= ?1 AND "datetime" < ?2
+        ORDER BY "datetime" ASC, "id" ASC
+            "#,
+        )
+        .bind(to_timestamp(since))
+        .bind(to_timestamp(until))
+        .fetch_all(&self.pool)
+        .await
+        .context("fetch poll runs")?
+        .into_iter()
+        .map(PollRun::try_from)
+        .collect()
+    }
+
+    async fn prune(
+        &self,
+        table: Table,
+        before: DateTime<Utc>,
+        limit: usize,
+    ) -> anyhow::Result<u64> {
+        query::prune(
+            &mut *self.pool.acquire().await.context("acquire connection")?,
+            table,
+            before,
+            limit,
+        )
+        .await
+    }
+
+    async fn convert_time_series(&self, _mode: TimeSeriesMode) -> anyhow::Result<TimeSeriesMode> {
+        anyhow::bail!("converting time-series tables is only supported by Postgres")
+    }
+
+    async fn insert_ping(&self, ping: &Ping) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+        INSERT INTO "ping"
+        (
+            "datetime",
+            "target",
+            "duration_ms",
+            "ttl",
+            "bytes"
+        )
+        VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+        )
+        .bind(to_timestamp(ping.datetime))
+        .bind(&ping.target)
+        .bind(ping.duration_ms)
+        .bind(ping.ttl)
+        .bind(ping.bytes)
+        .execute(&self.pool)
+        .await
+        .context("insert ping")?;
+
+        Ok(())
+    }
+
+    async fn select_pings(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Ping>> {
+        #[allow(clippy::type_complexity)]
+        let rows: Vec<(i64, i64, String, Option<i64>, Option<i64>, Option<i64>)> = sqlx::query_as(
+            r#"
+        SELECT "id",
+               "datetime",
+               "target",
+               "duration_ms",
+               "ttl",
+               "bytes"
+        FROM "ping"
+        WHERE "datetime" >= ?1 AND "datetime" < ?2
+        ORDER BY "datetime" ASC, "id" ASC
+            "#,
+        )
+        .bind(to_timestamp(since))
+        .bind(to_timestamp(until))
+        .fetch_all(&self.pool)
+        .await
+        .context("fetch pings")?;
+
+        rows.into_iter()
+            .map(|(id, datetime, target, duration_ms, ttl, bytes)| {
+                Ok(Ping {
+                    id: Some(id),
+                    datetime: from_timestamp(datetime)?,
+                    target,
+                    duration_ms,
+                    ttl,
+                    bytes,
+                })
+            })
+            .collect()
+    }
+
+    async fn select_next_ping_datetime(
+        &self,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let datetime: Option<i64> =
+            sqlx::query_scalar(r#"SELECT min("datetime") FROM "ping" WHERE "datetime" >= ?1"#)
+                .bind(to_timestamp(since))
+                .fetch_one(&self.pool)
+                .await
+                .context("fetch next ping")?;
+
+        datetime.map(from_timestamp).transpose()
+    }
+
+    async fn select_latest_ping_rollup_datetime(
+        &self,
+        resolution: Resolution,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let datetime: Option<i64> = sqlx::query_scalar(&format!(
+            r#"SELECT max("datetime") FROM "{}""#,
+            resolution.table().name()
+        ))
+        .fetch_one(&self.pool)
+        .await
+        .context("fetch latest ping rollup")?;
+
+        datetime.map(from_timestamp).transpose()
+    }
+
+    async fn upsert_ping_rollups(
+        &self,
+        resolution: Resolution,
+        rollups: &[PingRollup],
+    ) -> anyhow::Result<()> {
+        // stay well below the limit of bind parameters per statement
+        let mut tx = self.pool.begin().await.context("begin transaction")?;
+        for chunk in rollups.chunks(1000) {
+            query::upsert_ping_rollups(resolution, chunk)
+                .build()
+                .execute(&mut *tx)
+                .await
+                .context("upsert ping rollups")?;
+        }
+        tx.commit().await.context("commit transaction")
+    }
+
+    async fn select_ping_rollups(
+        &self,
+        resolution: Resolution,
+        target: Option<&str>,
+        range: (Bound<DateTime<Utc>>, Bound<DateTime<Utc>>),
+    ) -> anyhow::Result<Vec<PingRollup>> {
+        query::select_ping_rollups(resolution, target, range)
+            .build_query_as::<query::PingRollupRow>()
+            .fetch_all(&self.pool)
+            .await
+            .context("fetch ping rollups")?
+            .into_iter()
+            .map(PingRollup::try_from)
+            .collect()
+    }
+}
+
+struct SqliteTransaction {
+    tx: sqlx::Transaction<'static, Sqlite>,
+    events: LocalEvents,
+    /// Events to publish on commit
+    published: Vec<Event>,
+}
+
+#[async_trait::async_trait]
+impl Transaction for SqliteTransaction {
+    async fn commit(self: Box<Self>) -> anyhow::Result<()> {
+        self.tx.commit().await.context("commit transaction")?;
+        self.events.publish(&self.published);
+        Ok(())
+    }
+
+    async fn publish(&mut self, events: &[Event]) -> anyhow::Result<()> {
+        self.published.extend_from_slice(events);
+        Ok(())
+    }
+
+    async fn select_latest_logs(
+        &mut self,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<Vec<fritz::Log>> {
+        query::select_latest_logs(&mut self.tx, offset, limit).await
+    }
+
+    async fn select_tail_log(&mut self) -> anyhow::Result<Option<fritz::Log>> {
+        query::select_tail_log(&mut self.tx).await
+    }
+
+    async fn select_tail_cleared(&mut self) -> anyhow::Result<bool> {
+        query::select_tail_cleared(&mut self.tx).await
+    }
+
+    async fn select_logs_since(
+        &mut self,
+        datetime: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<fritz::Log>> {
+        query::select_logs_since(&mut self.tx, datetime).await
+    }
+
+    async fn insert_logs(&mut self, logs: &[fritz::Log], resynced: bool) -> anyhow::Result<()> {
+        query::insert_logs(&mut self.tx, logs, resynced).await
+    }
+
+    async fn update_log(&mut self, old: &fritz::Log, new: &fritz::Log) -> anyhow::Result<i64> {
+        query::update_log(&mut self.tx, old, new).await
+    }
+
+    async fn insert_orphan(
+        &mut self,
+        log: &fritz::Log,
+        datetime: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        query::insert_orphan(&mut self.tx, log, datetime).await
+    }
+
+    async fn insert_clear(
+        &mut self,
+        datetime: DateTime<Utc>,
+        archived_logs: i64,
+    ) -> anyhow::Result<()> {
+        query::insert_clear(&mut self.tx, datetime, archived_logs).await
+    }
+
+    async fn insert_gap(&mut self, gap: &Gap) -> anyhow::Result<()> {
+        query::insert_gap(&mut self.tx, gap).await
+    }
+
+    async fn insert_update(&mut self, update: &Update) -> anyhow::Result<()> {
+        query::insert_update(&mut self.tx, update).await
+    }
+}
@@ -0,0 +1,822 @@
+//! SQLite queries that can run as part of a transaction.
+
+use std::ops::Bound;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection};
+
+use super::{from_timestamp, to_timestamp, LogRow};
+use crate::db::log_query::{LogQuery, MessageFilter, Order, HIGHLIGHT_END, HIGHLIGHT_START};
+use crate::db::model::{Gap, Log, PingRollup, PollRun, Request, Resolution, Table, Update};
+use crate::db::request_query::RequestQuery;
+use crate::fritz;
+
+/// Columns of [`LogRow`]
+const LOG_COLUMNS: &str = r#"
+    "id",
+    "datetime",
+    "message",
+    "message_id",
+    "category_id",
+    "repetition_datetime",
+    "repetition_count",
+    "datetime_resolution",
+    "repetition_datetime_resolution"
+"#;
+
+async fn fetch_logs<'q>(
+    conn: &mut SqliteConnection,
+    query: sqlx::query::QueryAs<'q, sqlx::Sqlite, LogRow, sqlx::sqlite::SqliteArguments<'q>>,
+) -> anyhow::Result<Vec<fritz::Log>> {
+    query
+        .fetch_all(conn)
+        .await
+        .context("fetch logs")?
+        .into_iter()
+        .map(|row| Log::try_from(row)?.try_into())
+        .collect()
+}
+
+/// Select the `limit` latest logs offset by `offset`.
+pub async fn select_latest_logs(
+    conn: &mut SqliteConnection,
+    offset: usize,
+    limit: usize,
+) -> anyhow::Result<Vec<fritz::Log>> {
+    let offset = i64::try_from(offset).context("cast offset as i64")?;
+    let limit = i64::try_from(limit).context("cast limit as i64")?;
+    let sql = format!(
+        r#"SELECT {} FROM "logs" ORDER BY "id" DESC LIMIT ?1 OFFSET ?2"#,
+        LOG_COLUMNS
+    );
+    fetch_logs(conn, sqlx::query_as(&sql).bind(limit).bind(offset)).await
+}
+
+/// Select the most recently appended log, ignoring logs inserted by a resync.
+pub async fn select_tail_log(conn: &mut SqliteConnection) -> anyhow::Result<Option<fritz::Log>> {
+    let sql = format!(
+        r#"SELECT {} FROM "logs" WHERE NOT "resynced" ORDER BY "id" DESC LIMIT 1"#,
+        LOG_COLUMNS
+    );
+    Ok(fetch_logs(conn, sqlx::query_as(&sql)).await?.pop())
+}
+
+/// Whether the logs on the FRITZ!Box have been cleared after the most
+/// recently appended log has been archived.
+pub async fn select_tail_cleared(conn: &mut SqliteConnection) -> anyhow::Result<bool> {
+    sqlx::query_scalar(
+        r#"
+    SELECT EXISTS (
+        SELECT 1
+        FROM "log_clears"
+        WHERE "log_id" = (
+            SELECT "id"
+            FROM "logs"
+            WHERE NOT "resynced"
+            ORDER BY "id" DESC
+            LIMIT 1
+        )
+    )
+        "#,
+    )
+    .fetch_one(conn)
+    .await
+    .context("fetch tail cleared")
+}
+
+/// Select all logs that have been logged at or after `datetime`.
+pub async fn select_logs_since(
+    conn: &mut SqliteConnection,
+    datetime: DateTime<Utc>,
+) -> anyhow::Result<Vec<fritz::Log>> {
+    let sql = format!(
+        r#"SELECT {} FROM "logs" WHERE "datetime" >= ?1 ORDER BY "id" ASC"#,
+        LOG_COLUMNS
+    );
+    fetch_logs(conn, sqlx::query_as(&sql).bind(to_timestamp(datetime))).await
+}
+
+/// Append logs to the database without checking for consistency and record
+/// their repetitions.
+///
+/// Logs inserted by a resync must set `resynced`.
+pub async fn insert_logs(
+    conn: &mut SqliteConnection,
+    logs: &[fritz::Log],
+    resynced: bool,
+) -> anyhow::Result<()> {
+    for log in logs {
+        let log = Log::from(log.clone());
+        let id: i64 = sqlx::query_scalar(
+            r#"
+        INSERT INTO "logs"
+        (
+            "datetime",
+            "message",
+            "message_id",
+            "category_id",
+            "repetition_datetime",
+            "repetition_count",
+            "datetime_resolution",
+            "repetition_datetime_resolution",
+            "resynced"
+        )
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+        RETURNING "id"
+            "#,
+        )
+        .bind(to_timestamp(log.datetime))
+        .bind(&log.message)
+        .bind(log.message_id)
+        .bind(log.category_id)
+        .bind(log.repetition_datetime.map(to_timestamp))
+        .bind(log.repetition_count)
+        .bind(&log.datetime_resolution)
+        .bind(&log.repetition_datetime_resolution)
+        .bind(resynced)
+        .fetch_one(&mut *conn)
+        .await
+        .context("insert log")?;
+
+        if let Some(repetition_count) = log.repetition_count {
+            insert_repetition(&mut *conn, id, log.datetime, repetition_count).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn insert_repetition(
+    conn: &mut SqliteConnection,
+    log_id: i64,
+    datetime: DateTime<Utc>,
+    repetition_count: i64,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+    INSERT INTO "log_repetitions"
+    (
+        "log_id",
+        "datetime",
+        "repetition_count"
+    )
+    VALUES (?1, ?2, ?3)
+        "#,
+    )
+    .bind(log_id)
+    .bind(to_timestamp(datetime))
+    .bind(repetition_count)
+    .execute(conn)
+    .await
+    .context("insert log repetition")?;
+
+    Ok(())
+}
+
+/// Update a log in the database and record its new repetition.
+///
+/// Returns the number of updated logs.
+pub async fn update_log(
+    conn: &mut SqliteConnection,
+    old: &fritz::Log,
+    new: &fritz::Log,
+) -> anyhow::Result<i64> {
+    let old_log = Log::from(old.clone());
+    let new_log = Log::from(new.clone());
+
+    let ids: Vec<i64> = sqlx::query_scalar(
+        r#"
+    UPDATE "logs"
+    SET "datetime"                       = ?1,
+        "message"                        = ?2,
+        "message_id"                     = ?3,
+        "category_id"                    = ?4,
+        "repetition_datetime"            = ?5,
+        "repetition_count"               = ?6,
+        "datetime_resolution"            = ?7,
+        "repetition_datetime_resolution" = ?8
+    WHERE "datetime"    = ?9 AND
+          "message_id"  = ?10 AND
+          "category_id" = ?11
+    RETURNING "id"
+        "#,
+    )
+    .bind(to_timestamp(new_log.datetime))
+    .bind(&new_log.message)
+    .bind(new_log.message_id)
+    .bind(new_log.category_id)
+    .bind(new_log.repetition_datetime.map(to_timestamp))
+    .bind(new_log.repetition_count)
+    .bind(&new_log.datetime_resolution)
+    .bind(&new_log.repetition_datetime_resolution)
+    .bind(to_timestamp(old_log.datetime))
+    .bind(old_log.message_id)
+    .bind(old_log.category_id)
+    .fetch_all(&mut *conn)
+    .await
+    .context("update log")?;
+
+    if let Some(repetition_count) = new_log.repetition_count {
+        for id in ids.iter() {
+            insert_repetition(&mut *conn, *id, new_log.datetime, repetition_count).await?;
+        }
+    }
+
+    i64::try_from(ids.len()).context("cast updated rows as i64")
+}
+
+/// Flag a log that is in the database but not on the FRITZ!Box, unless it has
+/// already been flagged.
+pub async fn insert_orphan(
+    conn: &mut SqliteConnection,
+    log: &fritz::Log,
+    datetime: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let log = Log::from(log.clone());
+
+    sqlx::query(
+        r#"
+    INSERT INTO "log_orphans"
+    (
+        "log_id",
+        "datetime"
+    )
+    SELECT "id", ?4
+    FROM "logs"
+    WHERE "datetime"    = ?1 AND
+          "message_id"  = ?2 AND
+          "category_id" = ?3
+    ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(to_timestamp(log.datetime))
+    .bind(log.message_id)
+    .bind(log.category_id)
+    .bind(to_timestamp(datetime))
+    .execute(conn)
+    .await
+    .context("insert orphan")?;
+
+    Ok(())
+}
+
+/// Record that the logs on the FRITZ!Box have been cleared after the most
+/// recently appended log has been archived.
+pub async fn insert_clear(
+    conn: &mut SqliteConnection,
+    datetime: DateTime<Utc>,
+    archived_logs: i64,
+) -> anyhow::Result<()> {
+    let result = sqlx::query(
+        r#"
+    INSERT INTO "log_clears"
+    (
+        "log_id",
+        "datetime",
+        "archived_logs"
+    )
+    SELECT "id", ?1, ?2
+    FROM "logs"
+    WHERE NOT "resynced"
+    ORDER BY "id" DESC
+    LIMIT 1
+    ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(to_timestamp(datetime))
+    .bind(archived_logs)
+    .execute(conn)
+    .await
+    .context("insert clear")?;
+
+    if result.rows_affected() == 0 {
+        log::warn!("recorded no clear, the database is empty or the clear was already recorded");
+    }
+    Ok(())
+}
+
+/// Insert a gap unless the same gap has already been recorded.
+pub async fn insert_gap(conn: &mut SqliteConnection, gap: &Gap) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+    INSERT INTO "log_gaps"
+    (
+        "datetime",
+        "kind",
+        "after_datetime",
+        "before_datetime"
+    )
+    VALUES (?1, ?2, ?3, ?4)
+    ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(to_timestamp(gap.datetime))
+    .bind(&gap.kind)
+    .bind(to_timestamp(gap.after_datetime))
+    .bind(gap.before_datetime.map(to_timestamp))
+    .execute(conn)
+    .await
+    .context("insert gap")?;
+
+    Ok(())
+}
+
+pub async fn insert_update(conn: &mut SqliteConnection, update: &Update) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+    INSERT INTO "updates"
+    (
+        "datetime",
+        "upserted_rows"
+    )
+    VALUES (?1, ?2)
+        "#,
+    )
+    .bind(to_timestamp(update.datetime))
+    .bind(update.upserted_rows)
+    .execute(conn)
+    .await
+    .context("insert update")?;
+
+    Ok(())
+}
+
+/// Delete at most `limit` rows from `table` that have been recorded before
+/// `before`, oldest first.
+///
+/// Returns the number of deleted rows.
+pub async fn prune(
+    conn: &mut SqliteConnection,
+    table: Table,
+    before: DateTime<Utc>,
+    limit: usize,
+) -> anyhow::Result<u64> {
+    // the most recently appended log must stay, see `Table::Logs`
+    let keep_tail = match table {
+        Table::Logs => {
+            r#"AND "id" <> COALESCE((SELECT max("id") FROM "logs" WHERE NOT "resynced"), 0)"#
+        }
+        Table::Requests
+        | Table::Updates
+        | Table::Ping
+        | Table::Ping1m
+        | Table::Ping1h
+        | Table::PollRuns => "",
+    };
+    let limit = i64::try_from(limit).context("cast limit as i64")?;
+
+    let result = sqlx::query(&format!(
+        r#"
+    DELETE FROM "{table}"
+    WHERE "id" IN (
+        SELECT "id"
+        FROM "{table}"
+        WHERE "datetime" < ?1 {keep_tail}
+        ORDER BY "datetime" ASC
+        LIMIT ?2
+    )
+        "#,
+        table = table.name(),
+        keep_tail = keep_tail,
+    ))
+    .bind(to_timestamp(before))
+    .bind(limit)
+    .execute(conn)
+    .await
+    .with_context(|| format!("prune {}", table))?;
+
+    Ok(result.rows_affected())
+}
+
+/// Build the query selecting the logs matching `query`.
+pub fn select_logs(query: &LogQuery) -> anyhow::Result<QueryBuilder<'static, Sqlite>> {
+    let mut builder =
+        QueryBuilder::new(format!(r#"SELECT {} FROM "logs" WHERE TRUE"#, LOG_COLUMNS));
+
+    if let Some(since) = query.since {
+        builder
+            .push(r#" AND "datetime" >= "#)
+            .push_bind(to_timestamp(since));
+    }
+    if let Some(until) = query.until {
+        builder
+            .push(r#" AND "datetime" < "#)
+            .push_bind(to_timestamp(until));
+    }
+    if let Some(message_ids) = query.message_ids.as_ref() {
+        builder.push(r#" AND "message_id" IN ("#);
+        let mut separated = builder.separated(", ");
+        for message_id in message_ids.iter() {
+            separated.push_bind(*message_id);
+        }
+        builder.push(")");
+    }
+    if let Some(category_id) = query.category_id {
+        builder
+            .push(r#" AND "category_id" = "#)
+            .push_bind(category_id);
+    }
+    match query.message.as_ref() {
+        None => {}
+        Some(MessageFilter::Contains(needle)) => {
+            builder
+                .push(r#" AND instr("message", "#)
+                .push_bind(needle.clone())
+                .push(") > 0");
+        }
+        Some(MessageFilter::Regex(pattern)) => {
+            builder
+                .push(r#" AND "message" REGEXP "#)
+                .push_bind(pattern.clone());
+        }
+    }
+    if query.repeated_only {
+        builder.push(r#" AND "repetition_count" IS NOT NULL"#);
+    }
+
+    let (comparison, order) = match query.order {
+        Order::Ascending => (">", "ASC"),
+        Order::Descending => ("<", "DESC"),
+    };
+    if let Some(after) = query.after {
+        builder
+            .push(format_args!(r#" AND "id" {} "#, comparison))
+            .push_bind(after);
+    }
+    builder.push(format_args!(r#" ORDER BY "id" {}"#, order));
+    if let Some(limit) = query.limit {
+        let limit = i64::try_from(limit).context("cast limit as i64")?;
+        builder.push(" LIMIT ").push_bind(limit);
+    }
+
+    Ok(builder)
+}
+
+/// A log matching a full-text search
+#[derive(sqlx::FromRow)]
+pub struct SearchRow {
+    #[sqlx(flatten)]
+    pub log: LogRow,
+    pub rank: f64,
+    pub snippet: String,
+}
+
+/// Turn the search text into an FTS5 query matching logs that contain every
+/// word, or `None` if there are no words.
+///
+/// Every word is quoted, so IPs, MACs and other punctuation can't be mistaken
+/// for FTS5 syntax.
+fn match_expression(text: &str) -> Option<String> {
+    let words = text
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+    (!words.is_empty()).then(|| words.join(" "))
+}
+
+/// Build the query full-text searching the messages of the logs that have
+/// last been logged within `range`, or `None` if nothing can match.
+pub fn search_logs(
+    text: &str,
+    range: (Bound<DateTime<Utc>>, Bound<DateTime<Utc>>),
+    limit: usize,
+) -> anyhow::Result<Option<QueryBuilder<'static, Sqlite>>> {
+    let Some(expression) = match_expression(text) else {
+        return Ok(None);
+    };
+
+    // bm25 is lower for better matches
+    let mut builder = QueryBuilder::new(
+        r#"
+    SELECT "logs"."id",
+           "logs"."datetime",
+           "logs"."message",
+           "logs"."message_id",
+           "logs"."category_id",
+           "logs"."repetition_datetime",
+           "logs"."repetition_count",
+           "logs"."datetime_resolution",
+           "logs"."repetition_datetime_resolution",
+           -bm25("logs_search") AS "rank",
+           snippet("logs_search", 0, "#,
+    );
+    builder
+        .push_bind(HIGHLIGHT_START)
+        .push(", ")
+        .push_bind(HIGHLIGHT_END)
+        .push(
+            r#", '…', 32) AS "snippet"
+    FROM "logs_search"
+    JOIN "logs" ON "logs"."id" = "logs_search"."rowid"
+    WHERE "logs_search" MATCH "#,
+        )
+        .push_bind(expression);
+
+    match range.0 {
+        Bound::Included(since) => {
+            builder
+                .push(r#" AND "logs"."datetime" >= "#)
+                .push_bind(to_timestamp(since));
+        }
+        Bound::Excluded(since) => {
+            builder
+                .push(r#" AND "logs"."datetime" > "#)
+                .push_bind(to_timestamp(since));
+        }
+        Bound::Unbounded => {}
+    }
+    match range.1 {
+        Bound::Included(until) => {
+            builder
+                .push(r#" AND "logs"."datetime" <= "#)
+                .push_bind(to_timestamp(until));
+        }
+        Bound::Excluded(until) => {
+            builder
+                .push(r#" AND "logs"."datetime" < "#)
+                .push_bind(to_timestamp(until));
+        }
+        Bound::Unbounded => {}
+    }
+
+    let limit = i64::try_from(limit).context("cast limit as i64")?;
+    builder
+        .push(r#" ORDER BY bm25("logs_search") ASC, "logs"."id" DESC LIMIT "#)
+        .push_bind(limit);
+
+    Ok(Some(builder))
+}
+
+const PING_ROLLUP_COLUMNS: [&str; 12] = [
+    "target",
+    "datetime",
+    "count",
+    "loss_count",
+    "loss_ratio",
+    "min_ms",
+    "avg_ms",
+    "max_ms",
+    "p50_ms",
+    "p95_ms",
+    "p99_ms",
+    "jitter_ms",
+];
+
+/// A row of the `ping_1m` or `ping_1h` table
+#[derive(sqlx::FromRow)]
+pub struct PingRollupRow {
+    id: i64,
+    target: String,
+    datetime: i64,
+    count: i64,
+    loss_count: i64,
+    loss_ratio: f64,
+    min_ms: Option<i64>,
+    avg_ms: Option<f64>,
+    max_ms: Option<i64>,
+    p50_ms: Option<f64>,
+    p95_ms: Option<f64>,
+    p99_ms: Option<f64>,
+    jitter_ms: Option<f64>,
+}
+
+impl TryFrom<PingRollupRow> for PingRollup {
+    type Error = anyhow::Error;
+    fn try_from(row: PingRollupRow) -> anyhow::Result<Self> {
+        Ok(PingRollup {
+            id: Some(row.id),
+            target: row.target,
+            datetime: from_timestamp(row.datetime)?,
+            count: row.count,
+            loss_count: row.loss_count,
+            loss_ratio: row.loss_ratio,
+            min_ms: row.min_ms,
+            avg_ms: row.avg_ms,
+            max_ms: row.max_ms,
+            p50_ms: row.p50_ms,
+            p95_ms: row.p95_ms,
+            p99_ms: row.p99_ms,
+            jitter_ms: row.jitter_ms,
+        })
+    }
+}
+
+/// A row of the `requests` table
+#[derive(sqlx::FromRow)]
+pub struct RequestRow {
+    id: i64,
+    datetime: i64,
+    name: String,
+    url: String,
+    method: String,
+    duration_ms: i64,
+    response_code: Option<i64>,
+    session_id: Option<String>,
+    error_kind: Option<String>,
+    error_message: Option<String>,
+    response_bytes: Option<i64>,
+    attempt: i64,
+    remote_addr: Option<String>,
+}
+
+impl TryFrom<RequestRow> for Request {
+    type Error = anyhow::Error;
+    fn try_from(row: RequestRow) -> anyhow::Result<Self> {
+        Ok(Request {
+            id: Some(row.id),
+            datetime: from_timestamp(row.datetime)?,
+            name: row.name,
+            url: row.url,
+            method: row.method,
+            duration_ms: row.duration_ms,
+            response_code: row.response_code,
+            session_id: row.session_id,
+            error_kind: row.error_kind,
+            error_message: row.error_message,
+            response_bytes: row.response_bytes,
+            attempt: row.attempt,
+            remote_addr: row.remote_addr,
+        })
+    }
+}
+
+/// Build the query selecting the requests matching `query`.
+pub fn select_requests(query: &RequestQuery) -> anyhow::Result<QueryBuilder<'static, Sqlite>> {
+    let mut builder = QueryBuilder::new(
+        r#"
+    SELECT "id",
+           "datetime",
+           "name",
+           "url",
+           "method",
+           "duration_ms",
+           "response_code",
+           "session_id",
+           "error_kind",
+           "error_message",
+           "response_bytes",
+           "attempt",
+           "remote_addr"
+    FROM "requests"
+    WHERE TRUE"#,
+    );
+
+    if let Some(since) = query.since {
+        builder
+            .push(r#" AND "datetime" >= "#)
+            .push_bind(to_timestamp(since));
+    }
+    if let Some(until) = query.until {
+        builder
+            .push(r#" AND "datetime" < "#)
+            .push_bind(to_timestamp(until));
+    }
+    if let Some(name) = query.name.as_ref() {
+        builder.push(r#" AND "name" = "#).push_bind(name.clone());
+    }
+    if query.failed_only {
+        builder.push(r#" AND "error_kind" IS NOT NULL"#);
+    }
+    builder.push(r#" ORDER BY "datetime" ASC, "id" ASC"#);
+    if let Some(limit) = query.limit {
+        let limit = i64::try_from(limit).context("cast limit as i64")?;
+        builder.push(" LIMIT ").push_bind(limit);
+    }
+
+    Ok(builder)
+}
+
+/// A row of the `poll_runs` table
+#[derive(sqlx::FromRow)]
+pub struct PollRunRow {
+    id: i64,
+    datetime: i64,
+    finished_datetime: i64,
+    fetched: i64,
+    inserted: i64,
+    updated: i64,
+    gap: bool,
+    spooled: bool,
+    clock_drift_ms: Option<i64>,
+    implausible: i64,
+    error: Option<String>,
+    error_message: Option<String>,
+    version: String,
+    host: String,
+}
+
+impl TryFrom<PollRunRow> for PollRun {
+    type Error = anyhow::Error;
+    fn try_from(row: PollRunRow) -> anyhow::Result<Self> {
+        Ok(PollRun {
+            id: Some(row.id),
+            datetime: from_timestamp(row.datetime)?,
+            finished_datetime: from_timestamp(row.finished_datetime)?,
+            fetched: row.fetched,
+            inserted: row.inserted,
+            updated: row.updated,
+            gap: row.gap,
+            spooled: row.spooled,
+            clock_drift_ms: row.clock_drift_ms,
+            implausible: row.implausible,
+            error: row.error,
+            error_message: row.error_message,
+            version: row.version,
+            host: row.host,
+        })
+    }
+}
+
+/// Build the query inserting `rollups`, replacing rollups of the same target
+/// and bucket.
+pub fn upsert_ping_rollups(
+    resolution: Resolution,
+    rollups: &[PingRollup],
+) -> QueryBuilder<'static, Sqlite> {
+    let columns = PING_ROLLUP_COLUMNS
+        .iter()
+        .map(|column| format!(r#""{}""#, column))
+        .collect::<Vec<_>>();
+    let mut builder = QueryBuilder::new(format!(
+        r#"INSERT INTO "{}" ({}) "#,
+        resolution.table().name(),
+        columns.join(", ")
+    ));
+    builder.push_values(rollups, |mut row, rollup| {
+        row.push_bind(rollup.target.clone())
+            .push_bind(to_timestamp(rollup.datetime))
+            .push_bind(rollup.count)
+            .push_bind(rollup.loss_count)
+            .push_bind(rollup.loss_ratio)
+            .push_bind(rollup.min_ms)
+            .push_bind(rollup.avg_ms)
+            .push_bind(rollup.max_ms)
+            .push_bind(rollup.p50_ms)
+            .push_bind(rollup.p95_ms)
+            .push_bind(rollup.p99_ms)
+            .push_bind(rollup.jitter_ms);
+    });
+    let updates = columns[2..]
+        .iter()
+        .map(|column| format!("{} = excluded.{}", column, column))
+        .collect::<Vec<_>>();
+    builder.push(format_args!(
+        r#" ON CONFLICT ("target", "datetime") DO UPDATE SET {}"#,
+        updates.join(", ")
+    ));
+    builder
+}
+
+/// Build the query selecting the rollups of `resolution` whose bucket starts
+/// within `range`, optionally only of one target.
+pub fn select_ping_rollups(
+    resolution: Resolution,
+    target: Option<&str>,
+    range: (Bound<DateTime<Utc>>, Bound<DateTime<Utc>>),
+) -> QueryBuilder<'static, Sqlite> {
+    let mut builder = QueryBuilder::new(format!(
+        r#"SELECT "id", {} FROM "{}" WHERE TRUE"#,
+        PING_ROLLUP_COLUMNS
+            .iter()
+            .map(|column| format!(r#""{}""#, column))
+            .collect::<Vec<_>>()
+            .join(", "),
+        resolution.table().name()
+    ));
+
+    if let Some(target) = target {
+        builder
+            .push(r#" AND "target" = "#)
+            .push_bind(target.to_string());
+    }
+    match range.0 {
+        Bound::Included(since) => {
+            builder
+                .push(r#" AND "datetime" >= "#)
+                .push_bind(to_timestamp(since));
+        }
+        Bound::Excluded(since) => {
+            builder
+                .push(r#" AND "datetime" > "#)
+                .push_bind(to_timestamp(since));
+        }
+        Bound::Unbounded => {}
+    }
+    match range.1 {
+        Bound::Included(until) => {
+            builder
+                .push(r#" AND "datetime" <= "#)
+                .push_bind(to_timestamp(until));
+        }
+        Bound::Excluded(until) => {
+            builder
+                .push(r#" AND "datetime" < "#)
+                .push_bind(to_timestamp(until));
+        }
+        Bound::Unbounded => {}
+    }
+    builder.push(r#" ORDER BY "datetime" ASC, "target" ASC"#);
+
+    builder
+}
@@ -0,0 +1,210 @@
+//! Interface every storage backend implements.
+//!
+//! [`super::Database`] contains the logic shared by all backends and only
+//! calls into a backend to read and write rows.
+
+use std::ops::Bound;
+
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+
+use super::event::Event;
+use super::log_query::{LogQuery, SearchHit, StoredLog};
+use super::model::{
+    Gap, LogRepetition, Ping, PingRollup, PollRun, Request, Resolution, Table, Update,
+};
+use super::postgres::TimeSeriesMode;
+use super::request_query::RequestQuery;
+use crate::fritz;
+
+/// A storage backend, e.g. a Postgres or SQLite database.
+#[async_trait::async_trait]
+pub trait Storage: Send + Sync {
+    /// Start a transaction, nothing is written until it's committed.
+    async fn begin(&self) -> anyhow::Result<Box<dyn Transaction>>;
+
+    async fn close(&self);
+
+    async fn clear_logs(&self) -> anyhow::Result<()>;
+
+    async fn count_logs(&self) -> anyhow::Result<usize>;
+
+    async fn is_empty(&self) -> anyhow::Result<bool>;
+
+    /// Select the logs matching `query` without loading all of them at once.
+    fn stream_logs(&self, query: LogQuery) -> BoxStream<'_, anyhow::Result<StoredLog>>;
+
+    /// Full-text search the messages of the logs that have last been logged
+    /// within `range`, best matches first.
+    async fn search_logs(
+        &self,
+        text: &str,
+        range: (Bound<DateTime<Utc>>, Bound<DateTime<Utc>>),
+        limit: usize,
+    ) -> anyhow::Result<Vec<SearchHit>>;
+
+    /// Select the repetition history of the log with the given id, from old to new.
+    async fn select_log_repetitions(&self, log_id: i64) -> anyhow::Result<Vec<LogRepetition>>;
+
+    /// Try to become the only instance writing to the database, `None` if
+    /// another instance holds the lock with the given key.
+    async fn try_acquire_leadership(&self, key: i64)
+        -> anyhow::Result<Option<Box<dyn Leadership>>>;
+
+    /// Send events of committed changes to every subscriber.
+    async fn publish(&self, events: &[Event]) -> anyhow::Result<()>;
+
+    /// Receive the events published from now on.
+    async fn subscribe(&self) -> anyhow::Result<BoxStream<'static, anyhow::Result<Event>>>;
+
+    async fn insert_request(&self, req: &Request) -> anyhow::Result<()>;
+
+    /// Select the requests matching `query`, from old to new.
+    async fn select_requests(&self, query: &RequestQuery) -> anyhow::Result<Vec<Request>>;
+
+    async fn insert_poll_run(&self, run: &PollRun) -> anyhow::Result<()>;
+
+    /// Select the poll runs started at or after `since` and before `until`,
+    /// from old to new.
+    async fn select_poll_runs(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<PollRun>>;
+
+    /// Delete at most `limit` rows from `table` that have been recorded before
+    /// `before`, oldest first.
+    ///
+    /// Returns the number of deleted rows.
+    async fn prune(&self, table: Table, before: DateTime<Utc>, limit: usize)
+        -> anyhow::Result<u64>;
+
+    /// Convert the time-series tables into TimescaleDB hypertables or tables
+    /// partitioned by month, only supported by Postgres.
+    ///
+    /// Returns the mode that has been used, never [`TimeSeriesMode::Auto`].
+    async fn convert_time_series(&self, mode: TimeSeriesMode) -> anyhow::Result<TimeSeriesMode>;
+
+    async fn insert_ping(&self, ping: &Ping) -> anyhow::Result<()>;
+
+    /// Select the pings of all targets recorded at or after `since` and before
+    /// `until`, from old to new.
+    async fn select_pings(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Ping>>;
+
+    /// Select when the first ping at or after `since` has been recorded.
+    async fn select_next_ping_datetime(
+        &self,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Option<DateTime<Utc>>>;
+
+    /// Select the start of the most recent bucket of `resolution` that has
+    /// been rolled up.
+    async fn select_latest_ping_rollup_datetime(
+        &self,
+        resolution: Resolution,
+    ) -> anyhow::Result<Option<DateTime<Utc>>>;
+
+    /// Insert rollups, replacing rollups of the same target and bucket.
+    async fn upsert_ping_rollups(
+        &self,
+        resolution: Resolution,
+        rollups: &[PingRollup],
+    ) -> anyhow::Result<()>;
+
+    /// Select the rollups of `resolution` whose bucket starts within `range`,
+    /// optionally only of one target, from old to new.
+    async fn select_ping_rollups(
+        &self,
+        resolution: Resolution,
+        target: Option<&str>,
+        range: (Bound<DateTime<Utc>>, Bound<DateTime<Utc>>),
+    ) -> anyhow::Result<Vec<PingRollup>>;
+}
+
+/// Leadership among the instances writing to the same database, released
+/// when it's dropped.
+#[async_trait::async_trait]
+pub trait Leadership: Send + Sync {
+    /// Check that leadership is still held, an error means it has been lost
+    /// and another instance might have taken over.
+    async fn check(&mut self) -> anyhow::Result<()>;
+}
+
+/// Leadership of a backend that can't be shared between processes, so there
+/// is only ever one instance and it always leads.
+pub(super) struct SoleInstance;
+
+#[async_trait::async_trait]
+impl Leadership for SoleInstance {
+    async fn check(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// A transaction of a [`Storage`] backend.
+///
+/// Dropping a transaction without committing it rolls it back.
+#[async_trait::async_trait]
+pub trait Transaction: Send {
+    async fn commit(self: Box<Self>) -> anyhow::Result<()>;
+
+    /// Send events of the changes of this transaction to every subscriber once
+    /// it has been committed, none if it's rolled back.
+    async fn publish(&mut self, events: &[Event]) -> anyhow::Result<()>;
+
+    /// Select the `limit` latest logs offset by `offset`.
+    async fn select_latest_logs(
+        &mut self,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<Vec<fritz::Log>>;
+
+    /// Select the most recently appended log, ignoring logs inserted by a resync.
+    async fn select_tail_log(&mut self) -> anyhow::Result<Option<fritz::Log>>;
+
+    /// Whether the logs on the FRITZ!Box have been cleared after the most
+    /// recently appended log has been archived.
+    async fn select_tail_cleared(&mut self) -> anyhow::Result<bool>;
+
+    /// Select all logs that have been logged at or after `datetime`, in the
+    /// order they have been inserted.
+    async fn select_logs_since(
+        &mut self,
+        datetime: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<fritz::Log>>;
+
+    /// Append logs without checking for consistency and record their repetitions.
+    ///
+    /// Logs inserted by a resync must set `resynced`.
+    async fn insert_logs(&mut self, logs: &[fritz::Log], resynced: bool) -> anyhow::Result<()>;
+
+    /// Update a log and record its new repetition.
+    ///
+    /// Returns the number of updated logs.
+    async fn update_log(&mut self, old: &fritz::Log, new: &fritz::Log) -> anyhow::Result<i64>;
+
+    /// Flag a log that is in the database but not on the FRITZ!Box, unless it
+    /// has already been flagged.
+    async fn insert_orphan(
+        &mut self,
+        log: &fritz::Log,
+        datetime: DateTime<Utc>,
+    ) -> anyhow::Result<()>;
+
+    /// Record that the logs on the FRITZ!Box have been cleared after the most
+    /// recently appended log has been archived.
+    async fn insert_clear(
+        &mut self,
+        datetime: DateTime<Utc>,
+        archived_logs: i64,
+    ) -> anyhow::Result<()>;
+
+    /// Insert a gap unless the same gap has already been recorded.
+    async fn insert_gap(&mut self, gap: &Gap) -> anyhow::Result<()>;
+
+    async fn insert_update(&mut self, update: &Update) -> anyhow::Result<()>;
+}
@@ -0,0 +1,142 @@
+//! Whether the clock of the FRITZ!Box can be trusted.
+//!
+//! The FRITZ!Box logs with a two-digit year and its clock can be wrong, e.g.
+//! it starts at 01.01.70 after a power loss until it has synced with NTP.
+//! Logs with such timestamps break the assumption that logs are ordered by
+//! time, so their timestamps are replaced with the time of a neighbouring log.
+
+use chrono::{DateTime, Datelike, Duration, Local, Months, Utc};
+
+use super::{Log, TimeResolution};
+
+/// Logs before this year are implausible, the clock of the FRITZ!Box starts
+/// in 1970 after a power loss
+const MIN_PLAUSIBLE_YEAR: i32 = 2000;
+
+/// How far the clock of the FRITZ!Box may be ahead of the clock of the host
+/// before its logs are implausible
+const MAX_CLOCK_AHEAD_MINUTES: i64 = 10;
+
+/// A two-digit year is in one of these centuries
+const CENTURY: Months = Months::new(100 * 12);
+
+/// How the clock of the FRITZ!Box compares to the clock of the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ClockCheck {
+    /// How far the newest log is ahead of the request that fetched it, `None`
+    /// if there are no logs.
+    ///
+    /// Right after a login, the newest log is the login so this is the drift
+    /// of the clock of the FRITZ!Box. Otherwise it also includes the time
+    /// since the newest log has been logged.
+    pub drift: Option<Duration>,
+    /// Number of logs with implausible timestamps
+    pub implausible: usize,
+}
+
+/// Compare the logs fetched by a request at `requested` against the clock of
+/// the host and fix implausible timestamps.
+///
+/// Logs are ordered from **new to old** so the **newest log is at index 0**.
+///
+/// Timestamps a century off are moved into the right century. Other logs
+/// before 2000 or more than 10 minutes after `requested` get the time of the
+/// older log next to them, or if there is none, the time of the newer log next
+/// to them, a millisecond apart to keep them in order. If no log is plausible,
+/// the logs are left as they are.
+pub fn check_clock(logs: &mut [Log], requested: DateTime<Utc>) -> ClockCheck {
+    let latest = requested + Duration::minutes(MAX_CLOCK_AHEAD_MINUTES);
+    let is_plausible = |datetime: &DateTime<Local>| {
+        datetime.year() >= MIN_PLAUSIBLE_YEAR && datetime.with_timezone(&Utc) <= latest
+    };
+
+    for log in logs.iter_mut() {
+        roll_over(
+            &mut log.datetime,
+            &mut log.datetime_resolution,
+            is_plausible,
+        );
+        if let Some(rep) = log.repetition.as_mut() {
+            roll_over(
+                &mut rep.datetime,
+                &mut rep.datetime_resolution,
+                is_plausible,
+            );
+        }
+    }
+
+    let drift = logs
+        .first()
+        .map(|newest| newest.datetime.with_timezone(&Utc) - requested);
+    let plausible = logs
+        .iter()
+        .map(|log| is_plausible(&log.earliest_datetime()) && is_plausible(&log.datetime))
+        .collect::<Vec<_>>();
+    let implausible = plausible.iter().filter(|plausible| !**plausible).count();
+
+    let Some(oldest_plausible) = plausible.iter().rposition(|plausible| *plausible) else {
+        log::warn!("all {} logs have implausible timestamps", implausible);
+        return ClockCheck { drift, implausible };
+    };
+
+    // logs older than the oldest plausible log precede it
+    let newer = logs[oldest_plausible].earliest_datetime();
+    for (offset, log) in (1..).zip(logs[oldest_plausible + 1..].iter_mut()) {
+        clamp(log, newer - Duration::milliseconds(offset));
+    }
+
+    // from old to new, other implausible logs follow the older log next to them
+    let mut older = logs[oldest_plausible].datetime;
+    let mut offset = 0;
+    for (log, plausible) in logs[..oldest_plausible].iter_mut().zip(&plausible).rev() {
+        if *plausible {
+            older = log.datetime;
+            offset = 0;
+        } else {
+            offset += 1;
+            clamp(log, older + Duration::milliseconds(offset));
+        }
+    }
+
+    ClockCheck { drift, implausible }
+}
+
+/// Move `datetime` by a century if that makes it plausible.
+fn roll_over(
+    datetime: &mut DateTime<Local>,
+    resolution: &mut Option<TimeResolution>,
+    is_plausible: impl Fn(&DateTime<Local>) -> bool,
+) {
+    if is_plausible(datetime) {
+        return;
+    }
+
+    let rolled_over = [
+        datetime.checked_add_months(CENTURY),
+        datetime.checked_sub_months(CENTURY),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|datetime| is_plausible(datetime));
+
+    if let Some(rolled_over) = rolled_over {
+        log::warn!("moved {} by a century to {}", datetime, rolled_over);
+        *datetime = rolled_over;
+        *resolution = Some(TimeResolution::Rollover);
+    }
+}
+
+/// Replace the implausible timestamps of `log` with `datetime`.
+fn clamp(log: &mut Log, datetime: DateTime<Local>) {
+    log::warn!(
+        "replaced implausible timestamp of {} with {}",
+        log,
+        datetime
+    );
+    log.datetime = datetime;
+    log.datetime_resolution = Some(TimeResolution::Clamped);
+    if let Some(rep) = log.repetition.as_mut() {
+        rep.datetime = datetime;
+        rep.datetime_resolution = Some(TimeResolution::Clamped);
+    }
+}
@@ -1,20 +1,35 @@
 use anyhow::Context;
-use chrono::{DateTime, Local};
-use serde::Serialize;
+use chrono::{DateTime, Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
 
 use crate::api;
 use crate::db::util::local_to_utc_timestamp;
 use crate::db::{self};
 
+mod clock;
+pub use clock::{check_clock, ClockCheck};
+
+mod reconcile;
+pub use reconcile::{reconcile, GapKind, Op};
+
+mod resync;
+pub use resync::{resync, Resync};
+
+mod timezone;
+pub use timezone::{BoxTimezone, TimeResolution, TimezoneSetting};
+
 /// If a message was logged multiple times, this struct contains
 /// the date at which it was *first* logged and the number of times it was logged.
-#[derive(Debug, Clone, Serialize, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
 pub struct Repetition {
     pub datetime: DateTime<Local>,
     pub count: i64,
+    /// How `datetime` has been resolved, if it wasn't a single point in time
+    #[serde(default)]
+    pub datetime_resolution: Option<TimeResolution>,
 }
 
-#[derive(Debug, Clone, Serialize, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
 pub struct Log {
     /// Timestamp at which this log entry was last updated.
     ///
@@ -24,9 +39,32 @@ pub struct Log {
     pub message_id: i64,
     pub category_id: i64,
     pub repetition: Option<Repetition>,
+    /// How `datetime` has been resolved, if it wasn't a single point in time
+    #[serde(default)]
+    pub datetime_resolution: Option<TimeResolution>,
 }
 
 impl Log {
+    /// Timestamp at which this log entry was first logged.
+    pub fn earliest_datetime(&self) -> DateTime<Local> {
+        self.repetition
+            .as_ref()
+            .map_or(self.datetime, |rep| rep.datetime)
+    }
+    /// Whether `self` and `other` are the same entry, ignoring updates to its repetition.
+    pub fn is_same_entry(&self, other: &Log) -> bool {
+        self.earliest_timestamp_utc() == other.earliest_timestamp_utc()
+            && self.message_id == other.message_id
+            && self.category_id == other.category_id
+    }
+    /// Whether `self` and `other` are the same entry with the same repetition,
+    /// ignoring how their timestamps have been resolved.
+    pub fn is_same_repetition(&self, other: &Log) -> bool {
+        self.is_same_entry(other)
+            && self.latest_timestamp_utc() == other.latest_timestamp_utc()
+            && self.repetition.as_ref().map(|rep| rep.count)
+                == other.repetition.as_ref().map(|rep| rep.count)
+    }
     pub fn earliest_timestamp_utc(&self) -> i64 {
         match &self.repetition {
             Some(rep) => local_to_utc_timestamp(rep.datetime),
@@ -54,9 +92,13 @@ impl std::fmt::Display for Log {
 
 impl From<Log> for db::Log {
     fn from(value: Log) -> Self {
-        let (datetime, count) = match value.repetition {
-            Some(Repetition { datetime, count }) => (Some(datetime), Some(count)),
-            None => (None, None),
+        let (datetime, count, resolution) = match value.repetition {
+            Some(Repetition {
+                datetime,
+                count,
+                datetime_resolution,
+            }) => (Some(datetime), Some(count), datetime_resolution),
+            None => (None, None, None),
         };
 
         db::Log {
@@ -67,6 +109,10 @@ impl From<Log> for db::Log {
             category_id: value.category_id,
             repetition_datetime: datetime.map(|datetime| datetime.into()),
             repetition_count: count,
+            datetime_resolution: value
+                .datetime_resolution
+                .map(|resolution| resolution.to_string()),
+            repetition_datetime_resolution: resolution.map(|resolution| resolution.to_string()),
         }
     }
 }
@@ -75,6 +121,12 @@ impl TryFrom<db::Log> for Log {
     type Error = anyhow::Error;
     /// Convert logs from the database format into a common format
     fn try_from(value: db::Log) -> Result<Self, Self::Error> {
+        let parse_resolution = |resolution: Option<String>| {
+            resolution
+                .map(|resolution| resolution.parse::<TimeResolution>())
+                .transpose()
+        };
+
         Ok(Log {
             datetime: value.datetime.into(),
             message: value.message,
@@ -85,17 +137,28 @@ impl TryFrom<db::Log> for Log {
                     .repetition_datetime
                     .map(|repetition_datetime| repetition_datetime.into()),
                 value.repetition_count,
+                parse_resolution(value.repetition_datetime_resolution)?,
             )?,
+            datetime_resolution: parse_resolution(value.datetime_resolution)?,
         })
     }
 }
 
-impl TryFrom<api::Log> for Log {
+/// A log from the API whose timestamps are still in the local time of the FRITZ!Box
+struct NaiveLog {
+    datetime: NaiveDateTime,
+    message: String,
+    message_id: i64,
+    category_id: i64,
+    /// Timestamp at which it was first logged and the number of times it was logged
+    repetition: Option<(NaiveDateTime, i64)>,
+}
+
+impl TryFrom<api::Log> for NaiveLog {
     type Error = anyhow::Error;
-    /// Convert logs from the API into a common format.
     fn try_from(value: api::Log) -> Result<Self, Self::Error> {
         let [date, time, mut message, message_id, category_id, _] = value.0;
-        let datetime = util::parse_datetime(&date, &time)?;
+        let datetime = util::parse_naive_datetime(&date, &time)?;
         let message_id = message_id.parse().context("parse message id")?;
         let category_id = category_id.parse().context("parse category id")?;
 
@@ -108,10 +171,9 @@ impl TryFrom<api::Log> for Log {
             )
             // if important parts are there, parse them
             .map(|(whole_match, count, date, time)| -> anyhow::Result<_> {
-                let datetime = util::parse_datetime(date, time)?;
+                let datetime = util::parse_naive_datetime(date, time)?;
                 let count = count.parse().context("parse count")?;
-                let repetition = Repetition { datetime, count };
-                Ok((repetition, whole_match.len()))
+                Ok(((datetime, count), whole_match.len()))
             })
             // handle possible error from parsing
             .transpose()
@@ -123,7 +185,7 @@ impl TryFrom<api::Log> for Log {
             })
         };
 
-        Ok(Log {
+        Ok(NaiveLog {
             datetime,
             message,
             message_id,
@@ -133,28 +195,130 @@ impl TryFrom<api::Log> for Log {
     }
 }
 
+impl NaiveLog {
+    /// Whether `log`, converted from `timezone`, is the same entry, see
+    /// [`Log::is_same_entry`].
+    fn is_same_entry(&self, log: &Log, timezone: &BoxTimezone) -> bool {
+        let earliest = self
+            .repetition
+            .map_or(self.datetime, |(datetime, _)| datetime);
+        earliest == timezone.naive_local(log.earliest_datetime())
+            && self.message_id == log.message_id
+            && self.category_id == log.category_id
+    }
+
+    /// Convert the timestamps in `timezone`, neither of them is before
+    /// `not_before` if possible.
+    fn resolve(
+        self,
+        timezone: &BoxTimezone,
+        not_before: Option<DateTime<Local>>,
+    ) -> anyhow::Result<Log> {
+        let repetition = self
+            .repetition
+            .map(|(datetime, count)| -> anyhow::Result<_> {
+                let (datetime, datetime_resolution) = timezone.resolve(&datetime, not_before)?;
+                Ok(Repetition {
+                    datetime,
+                    count,
+                    datetime_resolution,
+                })
+            })
+            .transpose()
+            .context("resolve repetition datetime")?;
+
+        let not_before = repetition
+            .as_ref()
+            .map_or(not_before, |rep| Some(rep.datetime));
+        let (datetime, datetime_resolution) = timezone
+            .resolve(&self.datetime, not_before)
+            .context("resolve datetime")?;
+
+        Ok(Log {
+            datetime,
+            message: self.message,
+            message_id: self.message_id,
+            category_id: self.category_id,
+            repetition,
+            datetime_resolution,
+        })
+    }
+}
+
+impl Log {
+    /// Convert logs from the API into a common format, their timestamps are
+    /// in `timezone`.
+    ///
+    /// Logs are ordered from **new to old** so the **newest log is at index 0**.
+    /// Timestamps that aren't a single point in time because of daylight
+    /// saving time are resolved using the order of the logs, see
+    /// [`BoxTimezone::resolve`].
+    ///
+    /// `tail` is the newest log that has already been archived. Logs after it
+    /// haven't been logged before it, even if the logs in between have been
+    /// dropped because the buffer of the FRITZ!Box has wrapped.
+    pub fn from_api(
+        logs: Vec<api::Log>,
+        timezone: &BoxTimezone,
+        tail: Option<&Log>,
+    ) -> anyhow::Result<Vec<Log>> {
+        let mut logs = logs
+            .into_iter()
+            .map(NaiveLog::try_from)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        logs.reverse();
+
+        // the tail continues at its own log if it's still in the buffer,
+        // otherwise at the oldest log
+        let tail = tail.map(|tail| {
+            let index = logs
+                .iter()
+                .rposition(|log| log.is_same_entry(tail, timezone))
+                .unwrap_or(0);
+            (index, tail.earliest_datetime())
+        });
+
+        // from old to new, every log has been first logged after the older ones
+        let mut resolved = Vec::with_capacity(logs.len());
+        let mut not_before = None;
+        for (index, log) in logs.into_iter().enumerate() {
+            if let Some((_, datetime)) = tail.filter(|(tail, _)| *tail == index) {
+                not_before = Some(datetime);
+            }
+            let log = log.resolve(timezone, not_before)?;
+            not_before = Some(log.earliest_datetime());
+            resolved.push(log);
+        }
+
+        resolved.reverse();
+        Ok(resolved)
+    }
+}
+
 mod util {
     use anyhow::Context;
     use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
 
-    use super::Repetition;
+    use super::{Repetition, TimeResolution};
 
-    /// DateTimes from the API are in the local timezone
-    pub fn parse_datetime(date: &str, time: &str) -> anyhow::Result<DateTime<Local>> {
+    /// DateTimes from the API are in the local time of the FRITZ!Box
+    pub fn parse_naive_datetime(date: &str, time: &str) -> anyhow::Result<NaiveDateTime> {
         let date = NaiveDate::parse_from_str(date, "%d.%m.%y").context("parse datetime date")?;
         let time = NaiveTime::parse_from_str(time, "%H:%M:%S").context("parse datetime time")?;
-        NaiveDateTime::new(date, time)
-            .and_local_timezone(Local)
-            .single()
-            .context("datetime into local timezone")
+        Ok(NaiveDateTime::new(date, time))
     }
 
     pub fn parse_repetition(
         datetime: Option<DateTime<Local>>,
         count: Option<i64>,
+        datetime_resolution: Option<TimeResolution>,
     ) -> anyhow::Result<Option<Repetition>> {
         match (datetime, count) {
-            (Some(datetime), Some(count)) => Ok(Some(Repetition { datetime, count })),
+            (Some(datetime), Some(count)) => Ok(Some(Repetition {
+                datetime,
+                count,
+                datetime_resolution,
+            })),
             (None, None) => Ok(None),
             // Either both are set or none
             v => Err(anyhow::anyhow!("invalid repetition {:?}", v)),
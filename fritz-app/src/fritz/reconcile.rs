@@ -0,0 +1,143 @@
+//! Decide which logs of a FRITZ!Box snapshot have to be written to the database.
+
+use chrono::{DateTime, Local};
+
+use super::Log;
+
+/// A single change to apply to the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// Append a new log.
+    Insert(Log),
+    /// The repetition of the most recent log in the database changed.
+    UpdateRepetition { old: Log, new: Log },
+    /// The most recent log in the database is missing from the snapshot, so
+    /// logs might have been lost between `after` and `before`.
+    ///
+    /// `before` is `None` if the snapshot doesn't contain any newer logs.
+    Gap {
+        kind: GapKind,
+        after: DateTime<Local>,
+        before: Option<DateTime<Local>>,
+    },
+}
+
+/// Why the most recent log in the database is missing from the snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GapKind {
+    /// All logs in the snapshot are newer, so the ring buffer rolled over
+    /// since the last poll. This also happens if the logs have been cleared
+    /// and new logs arrived since.
+    Wrapped,
+    /// The snapshot is empty, so the logs have been cleared.
+    Cleared,
+    /// The snapshot contains older logs but not the most recent one, so the
+    /// logs have been replaced, e.g. by a factory reset.
+    Replaced,
+}
+
+impl GapKind {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            GapKind::Wrapped => "wrapped",
+            GapKind::Cleared => "cleared",
+            GapKind::Replaced => "replaced",
+        }
+    }
+}
+
+impl std::fmt::Display for GapKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Compare a snapshot of the FRITZ!Box logs against the most recent log in the
+/// database (`previous_tail`) and return the operations needed to bring the
+/// database up to date.
+///
+/// Logs must be sorted from **old to new** so the oldest log is at index 0.
+///
+/// Operations are returned in the order they have to be applied in.
+pub fn reconcile(previous_tail: Option<&Log>, snapshot: &[Log]) -> anyhow::Result<Vec<Op>> {
+    // Database: [3,2,1]
+    //
+    // [4,5]   -> [5,4,3,2,1]: All logs are new
+    // [1,2]   ->     [3,2,1]: All logs are old
+    // [2,3,4] ->   [4,3,2,1]: Some logs are new
+
+    // for some reason, the FRITZ!Box premium software updates the repetition of a existing
+    // log entry after inserting a new log entry which shouldn't happen... because of that
+    // we need to compare the earliest timestamp of the earlier log entry with the latest
+    // timestamp of the following entry.
+    if !snapshot
+        .windows(2)
+        .all(|w| w[0].earliest_timestamp_utc() <= w[1].latest_timestamp_utc())
+    {
+        log::warn!("called reconcile with unsorted logs: {:#?}", snapshot);
+        return Err(anyhow::anyhow!("logs must be sorted from old to new"));
+    }
+
+    let insert_all = |logs: &[Log]| logs.iter().cloned().map(Op::Insert).collect::<Vec<_>>();
+
+    // the database is empty, all logs must be new
+    let Some(tail) = previous_tail else {
+        return Ok(insert_all(snapshot));
+    };
+
+    // check if _all_ new logs are actually old
+    //
+    // if the newest log in the snapshot is older than the latest
+    // log in the database, all logs in the snapshot must be old.
+    if snapshot
+        .last()
+        .is_some_and(|log| log.latest_timestamp_utc() < tail.latest_timestamp_utc())
+    {
+        return Ok(Vec::new());
+    }
+
+    let Some(tail_index) = snapshot.iter().position(|log| log.is_same_entry(tail)) else {
+        // the most recent log in the database is gone, keep everything
+        // that happened after it and remember that something is missing
+        let (kind, newer) = match snapshot.first() {
+            None => (GapKind::Cleared, snapshot),
+            // if the oldest log in the snapshot is newer than the latest
+            // log in the database, all logs in the snapshot must be new.
+            Some(log) if log.earliest_timestamp_utc() > tail.latest_timestamp_utc() => {
+                (GapKind::Wrapped, snapshot)
+            }
+            Some(_) => (
+                GapKind::Replaced,
+                snapshot
+                    .iter()
+                    .position(|log| log.latest_timestamp_utc() > tail.latest_timestamp_utc())
+                    .map_or(&[][..], |index| &snapshot[index..]),
+            ),
+        };
+
+        let mut ops = vec![Op::Gap {
+            kind,
+            after: tail.datetime,
+            before: newer.first().map(Log::earliest_datetime),
+        }];
+        ops.extend(insert_all(newer));
+        return Ok(ops);
+    };
+
+    let (current, newer) = snapshot[tail_index..]
+        .split_first()
+        .expect("at least one candidate");
+
+    let mut ops = Vec::with_capacity(newer.len() + 1);
+
+    // if the repetition changed, update it in the database
+    if current.repetition != tail.repetition {
+        ops.push(Op::UpdateRepetition {
+            old: tail.clone(),
+            new: current.clone(),
+        });
+    }
+
+    ops.extend(insert_all(newer));
+    Ok(ops)
+}
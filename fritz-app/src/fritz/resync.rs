@@ -0,0 +1,114 @@
+//! Compare a whole snapshot of the FRITZ!Box logs against the database.
+
+use std::collections::{HashMap, HashSet};
+
+use super::Log;
+
+/// Differences between the FRITZ!Box and the database found by [`resync`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Resync {
+    /// Logs on the FRITZ!Box that are missing from the database, from old to new
+    pub missing: Vec<Log>,
+    /// Logs whose repetition differs, as `(database, FRITZ!Box)`
+    pub diverged: Vec<(Log, Log)>,
+    /// Logs in the database that aren't on the FRITZ!Box even though they
+    /// should be
+    pub orphaned: Vec<Log>,
+}
+
+impl Resync {
+    pub const fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.diverged.is_empty() && self.orphaned.is_empty()
+    }
+}
+
+impl std::fmt::Display for Resync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} missing, {} diverged, {} orphaned",
+            self.missing.len(),
+            self.diverged.len(),
+            self.orphaned.len()
+        )?;
+        for log in self.missing.iter() {
+            writeln!(f, "missing:  {}", log)?;
+        }
+        for (db_log, box_log) in self.diverged.iter() {
+            writeln!(f, "diverged: {} -> {}", db_log, box_log)?;
+        }
+        for log in self.orphaned.iter() {
+            writeln!(f, "orphaned: {}", log)?;
+        }
+        Ok(())
+    }
+}
+
+/// Identifies a log entry, ignoring updates to its repetition.
+fn key(log: &Log) -> (i64, i64, i64) {
+    (
+        log.earliest_timestamp_utc(),
+        log.message_id,
+        log.category_id,
+    )
+}
+
+/// Compare the part of a snapshot of the FRITZ!Box logs that is already
+/// covered by the database against the logs in the database.
+///
+/// Only logs up to the most recent log in the database (`previous_tail`) are
+/// compared, newer logs are left to [`super::reconcile`]. `db_logs` must
+/// contain every log in the database that is at least as new as the oldest
+/// log in the snapshot.
+///
+/// Logs must be sorted from **old to new** so the oldest log is at index 0.
+pub fn resync(previous_tail: Option<&Log>, db_logs: &[Log], snapshot: &[Log]) -> Resync {
+    let Some(tail) = previous_tail else {
+        return Resync::default();
+    };
+
+    let covered = match snapshot.iter().position(|log| key(log) == key(tail)) {
+        Some(index) => &snapshot[..=index],
+        None => {
+            let end = snapshot
+                .iter()
+                .position(|log| log.earliest_timestamp_utc() > tail.latest_timestamp_utc())
+                .unwrap_or(snapshot.len());
+            &snapshot[..end]
+        }
+    };
+    let Some(oldest) = covered.first() else {
+        return Resync::default();
+    };
+
+    let db_by_key = db_logs
+        .iter()
+        .map(|log| (key(log), log))
+        .collect::<HashMap<_, _>>();
+
+    let mut resync = Resync::default();
+    for log in covered {
+        match db_by_key.get(&key(log)) {
+            None => resync.missing.push(log.clone()),
+            Some(db_log) if db_log.repetition != log.repetition => {
+                resync.diverged.push(((*db_log).clone(), log.clone()));
+            }
+            Some(_) => {}
+        }
+    }
+
+    // the oldest log might have been preceded by logs that aren't on the
+    // FRITZ!Box anymore, so only logs newer than it must be on the FRITZ!Box
+    let covered_keys = covered.iter().map(key).collect::<HashSet<_>>();
+    resync.orphaned = db_logs
+        .iter()
+        .filter(|log| {
+            log.latest_timestamp_utc() > oldest.latest_timestamp_utc()
+                && log.latest_timestamp_utc() <= tail.latest_timestamp_utc()
+                && !covered_keys.contains(&key(log))
+        })
+        .cloned()
+        .collect();
+
+    resync
+}
@@ -0,0 +1,249 @@
+//! The timezone timestamps of the FRITZ!Box are in.
+//!
+//! The FRITZ!Box logs in its own local time without any offset, so the same
+//! timezone has to be used to parse its timestamps, independent of the
+//! timezone of the host this app runs on.
+//!
+//! Local times within the hour the clock is turned back for daylight saving
+//! time occur twice, local times within the hour the clock is turned forward
+//! don't exist at all. How such a time has been converted into a point in time
+//! is recorded as a [`TimeResolution`].
+
+use std::str::FromStr;
+
+use anyhow::Context;
+use chrono::{
+    DateTime, Duration, FixedOffset, Local, LocalResult, NaiveDateTime, Offset, TimeZone, Utc,
+};
+
+use crate::api;
+
+/// Offsets from UTC are multiples of this many minutes
+const OFFSET_GRANULARITY_MINUTES: i64 = 15;
+
+/// How far the time of a log may be off from the time of the request that
+/// caused it, to still detect the offset from it
+const MAX_DETECTION_ERROR_MINUTES: i64 = 2;
+
+/// Message id of the log of a login to the user interface
+const LOGIN_MESSAGE_ID: &str = "344";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoxTimezone {
+    /// The timezone of the host, `chrono::Local`
+    Host,
+    /// An IANA timezone, e.g. `Europe/Berlin`
+    Named(chrono_tz::Tz),
+    /// A fixed offset from UTC, e.g. as detected from the FRITZ!Box
+    Offset(FixedOffset),
+}
+
+/// How a local time of the FRITZ!Box that isn't a single, plausible point in
+/// time has been converted into one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeResolution {
+    /// The time occurred twice because the clock has been turned back, this
+    /// is its first occurrence
+    First,
+    /// The time occurred twice because the clock has been turned back, this
+    /// is its second occurrence
+    Second,
+    /// The time has been skipped because the clock has been turned forward,
+    /// it has been moved forward by the length of the gap
+    Shifted,
+    /// The two-digit year has been parsed into the wrong century, it has been
+    /// moved by 100 years, see [`super::check_clock`]
+    Rollover,
+    /// The clock of the FRITZ!Box was wrong, e.g. after a power loss, the time
+    /// has been replaced by the time of a neighbouring log, see
+    /// [`super::check_clock`]
+    Clamped,
+}
+
+impl TimeResolution {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            TimeResolution::First => "first",
+            TimeResolution::Second => "second",
+            TimeResolution::Shifted => "shifted",
+            TimeResolution::Rollover => "rollover",
+            TimeResolution::Clamped => "clamped",
+        }
+    }
+}
+
+impl std::fmt::Display for TimeResolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TimeResolution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<TimeResolution> {
+        match s {
+            "first" => Ok(TimeResolution::First),
+            "second" => Ok(TimeResolution::Second),
+            "shifted" => Ok(TimeResolution::Shifted),
+            "rollover" => Ok(TimeResolution::Rollover),
+            "clamped" => Ok(TimeResolution::Clamped),
+            _ => Err(anyhow::anyhow!("invalid time resolution {:?}", s)),
+        }
+    }
+}
+
+/// How the timezone of the FRITZ!Box is determined, see `FRITZBOX_TIMEZONE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimezoneSetting {
+    Fixed(BoxTimezone),
+    /// Detect the offset from the FRITZ!Box
+    Detect,
+}
+
+impl TimezoneSetting {
+    /// Load `FRITZBOX_TIMEZONE`, which is either an IANA timezone or `auto`.
+    ///
+    /// Defaults to the timezone of the host.
+    pub fn try_from_env() -> anyhow::Result<TimezoneSetting> {
+        match dotenv::var("FRITZBOX_TIMEZONE") {
+            Err(_) => Ok(TimezoneSetting::Fixed(BoxTimezone::Host)),
+            Ok(setting) if setting.eq_ignore_ascii_case("auto") => Ok(TimezoneSetting::Detect),
+            Ok(setting) => Ok(TimezoneSetting::Fixed(
+                setting.parse().context("parse FRITZBOX_TIMEZONE")?,
+            )),
+        }
+    }
+}
+
+impl BoxTimezone {
+    /// The point in time of `datetime` in this timezone.
+    pub fn from_local_datetime(&self, datetime: &NaiveDateTime) -> LocalResult<DateTime<Local>> {
+        match self {
+            BoxTimezone::Host => datetime.and_local_timezone(Local),
+            BoxTimezone::Named(tz) => tz
+                .from_local_datetime(datetime)
+                .map(|datetime| datetime.with_timezone(&Local)),
+            BoxTimezone::Offset(offset) => offset
+                .from_local_datetime(datetime)
+                .map(|datetime| datetime.with_timezone(&Local)),
+        }
+    }
+
+    /// The local time of `datetime` in this timezone.
+    pub fn naive_local(&self, datetime: DateTime<Local>) -> NaiveDateTime {
+        datetime.naive_utc() + self.offset(datetime.with_timezone(&Utc))
+    }
+
+    /// Convert `datetime` into a point in time that isn't before `not_before`,
+    /// the time of the previous log, if possible.
+    ///
+    /// Logs are logged in order, so an ambiguous time is its first occurrence
+    /// unless that's before the previous log, then the clock must have been
+    /// turned back in between. A skipped time is interpreted with the offset
+    /// from before the gap, which moves it forward by the length of the gap.
+    pub fn resolve(
+        &self,
+        datetime: &NaiveDateTime,
+        not_before: Option<DateTime<Local>>,
+    ) -> anyhow::Result<(DateTime<Local>, Option<TimeResolution>)> {
+        match self.from_local_datetime(datetime) {
+            LocalResult::Single(resolved) => Ok((resolved, None)),
+            LocalResult::Ambiguous(first, second) => {
+                if not_before.is_some_and(|not_before| first < not_before) {
+                    Ok((second, Some(TimeResolution::Second)))
+                } else {
+                    Ok((first, Some(TimeResolution::First)))
+                }
+            }
+            LocalResult::None => {
+                let day_before = self
+                    .from_local_datetime(&(*datetime - Duration::days(1)))
+                    .earliest()
+                    .with_context(|| format!("{} a day before {}", datetime, self))?;
+                Ok((
+                    day_before + Duration::days(1),
+                    Some(TimeResolution::Shifted),
+                ))
+            }
+        }
+    }
+
+    /// Detect the offset of the FRITZ!Box from the `log` of the login of
+    /// `username` requested at `requested`.
+    ///
+    /// Returns `None` if the log isn't a login or is too far off to have been
+    /// caused by the request.
+    pub fn detect(
+        log: &api::Log,
+        requested: DateTime<Utc>,
+        username: &str,
+    ) -> anyhow::Result<Option<BoxTimezone>> {
+        let [date, time, message, id, ..] = &log.0;
+        let is_login =
+            id == LOGIN_MESSAGE_ID || (!username.is_empty() && message.contains(username));
+        if !is_login {
+            return Ok(None);
+        }
+
+        let datetime = super::util::parse_naive_datetime(date, time)?;
+        Ok(BoxTimezone::detect_offset(&datetime, requested))
+    }
+
+    /// `timezone` if it has the same offset as this one at `at`, otherwise
+    /// this one.
+    ///
+    /// A detected offset is only right until the clock is turned for daylight
+    /// saving time, a timezone with the same offset, e.g. the one of the host,
+    /// most likely follows the FRITZ!Box when it's turned.
+    pub fn prefer(self, timezone: BoxTimezone, at: DateTime<Utc>) -> BoxTimezone {
+        if self.offset(at) == timezone.offset(at) {
+            timezone
+        } else {
+            self
+        }
+    }
+
+    /// The offset from UTC at `at`.
+    fn offset(&self, at: DateTime<Utc>) -> FixedOffset {
+        match self {
+            BoxTimezone::Host => Local.offset_from_utc_datetime(&at.naive_utc()).fix(),
+            BoxTimezone::Named(tz) => tz.offset_from_utc_datetime(&at.naive_utc()).fix(),
+            BoxTimezone::Offset(offset) => *offset,
+        }
+    }
+
+    fn detect_offset(datetime: &NaiveDateTime, requested: DateTime<Utc>) -> Option<BoxTimezone> {
+        let minutes = (*datetime - requested.naive_utc()).num_minutes();
+        let offset = (minutes + OFFSET_GRANULARITY_MINUTES / 2)
+            .div_euclid(OFFSET_GRANULARITY_MINUTES)
+            * OFFSET_GRANULARITY_MINUTES;
+        if (minutes - offset).abs() > MAX_DETECTION_ERROR_MINUTES {
+            return None;
+        }
+
+        let seconds = i32::try_from(Duration::minutes(offset).num_seconds()).ok()?;
+        FixedOffset::east_opt(seconds).map(BoxTimezone::Offset)
+    }
+}
+
+impl FromStr for BoxTimezone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<BoxTimezone> {
+        s.parse()
+            .map(BoxTimezone::Named)
+            .map_err(|err| anyhow::anyhow!("invalid IANA timezone {:?}: {}", s, err))
+    }
+}
+
+impl std::fmt::Display for BoxTimezone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BoxTimezone::Host => f.write_str("host timezone"),
+            BoxTimezone::Named(tz) => f.write_str(tz.name()),
+            BoxTimezone::Offset(offset) => write!(f, "UTC{}", offset),
+        }
+    }
+}
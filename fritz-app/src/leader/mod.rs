@@ -0,0 +1,125 @@
+//! Leader election, so only one of several instances archiving the same
+//! FRITZ!Box into the same database polls and pings, e.g. during a rolling
+//! redeploy. A standby takes over once the leader releases its lock or dies.
+
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::db;
+
+pub struct LeaderOptions {
+    key: i64,
+    retry: Duration,
+}
+
+impl LeaderOptions {
+    /// Instances elect a leader per FRITZ!Box, so the key is derived from
+    /// `FRITZBOX_DOMAIN`.
+    pub fn try_from_env() -> anyhow::Result<LeaderOptions> {
+        let domain = std::env::var("FRITZBOX_DOMAIN").unwrap_or_default();
+
+        let retry_seconds = match std::env::var("FRITZBOX_LEADER_RETRY_SECONDS") {
+            Err(_) => 10,
+            Ok(retry_seconds) => retry_seconds
+                .parse::<u64>()
+                .context("parse FRITZBOX_LEADER_RETRY_SECONDS")?,
+        };
+        if retry_seconds == 0 {
+            anyhow::bail!("FRITZBOX_LEADER_RETRY_SECONDS must be at least 1");
+        }
+
+        Ok(LeaderOptions::new(
+            &domain,
+            Duration::from_secs(retry_seconds),
+        ))
+    }
+
+    /// Elect a leader for the FRITZ!Box at `domain`, trying again every `retry`.
+    pub fn new(domain: &str, retry: Duration) -> LeaderOptions {
+        LeaderOptions {
+            key: lock_key(domain),
+            retry,
+        }
+    }
+}
+
+/// Derive the key of the advisory lock from the domain of the FRITZ!Box.
+///
+/// Uses FNV-1a, which unlike [`std::hash::DefaultHasher`] is the same for
+/// every build, so different versions agree on the key during a redeploy.
+pub fn lock_key(domain: &str) -> i64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    let hash = "fritz-app:"
+        .bytes()
+        .chain(domain.bytes())
+        .fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+        });
+    i64::from_ne_bytes(hash.to_ne_bytes())
+}
+
+/// Wait until this instance is the leader.
+pub async fn acquire(db: &db::Database, opts: &LeaderOptions) -> Box<dyn db::Leadership> {
+    let mut interval = tokio::time::interval(opts.retry);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    let mut standing_by = false;
+    loop {
+        interval.tick().await;
+
+        match db.try_acquire_leadership(opts.key).await {
+            Ok(Some(leadership)) => {
+                log::info!("became the leader");
+                return leadership;
+            }
+            Ok(None) if standing_by => {}
+            Ok(None) => {
+                log::info!("another instance is the leader, standing by");
+                standing_by = true;
+            }
+            Err(err) => log::warn!("couldn't try to become the leader: {:?}", err),
+        }
+    }
+}
+
+/// Wait until another instance has become the leader.
+///
+/// While the database is unreachable, no other instance can take the lock
+/// either, so this instance keeps leading and spooling writes. Once the
+/// database is reachable again the lock is reacquired, unless a standby that
+/// could still reach the database has taken over in the meantime.
+pub async fn lost(
+    db: &db::Database,
+    leadership: &mut Box<dyn db::Leadership>,
+    opts: &LeaderOptions,
+) -> anyhow::Error {
+    let mut interval = tokio::time::interval(opts.retry);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    let mut unreachable = false;
+    loop {
+        interval.tick().await;
+
+        let Err(err) = leadership.check().await else {
+            continue;
+        };
+        match db.try_acquire_leadership(opts.key).await {
+            Ok(Some(reacquired)) => {
+                log::info!("reacquired leadership after {:#}", err);
+                *leadership = reacquired;
+                unreachable = false;
+            }
+            Ok(None) => return err,
+            Err(err) if unreachable => {
+                log::debug!("database is still unreachable, leading on: {:#}", err);
+            }
+            Err(err) => {
+                log::warn!("couldn't confirm leadership, leading on: {:#}", err);
+                unreachable = true;
+            }
+        }
+    }
+}
@@ -19,7 +19,6 @@
     clippy::manual_ok_or,
     clippy::manual_string_new,
     clippy::map_unwrap_or,
-    clippy::match_on_vec_items,
     clippy::match_same_arms,
     clippy::redundant_else,
     clippy::semicolon_if_nothing_returned,
@@ -67,8 +66,13 @@
 pub mod api;
 pub mod db;
 pub mod fritz;
+pub mod leader;
 pub mod log;
 pub mod ping;
+pub mod poll;
+pub mod retention;
+#[cfg(feature = "simulator")]
+pub mod simulator;
 
 #[cfg(test)]
 mod test;
@@ -7,6 +7,8 @@ use surge_ping::{IcmpPacket, PingIdentifier, PingSequence};
 
 use crate::db;
 
+pub mod rollup;
+
 pub struct PingLoopOptions {
     db: db::Database,
     client: surge_ping::Client,
@@ -0,0 +1,167 @@
+//! Roll pings up into per-target buckets, so dashboards don't need to scan
+//! raw pings and raw pings can be pruned without losing their history.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::db::{self, PingRollup, Resolution};
+
+/// Buckets are only rolled up once they ended this many seconds ago, so pings
+/// that were still waiting for an answer at the end are included.
+const ROLLUP_DELAY_SECONDS: i64 = 30;
+
+/// How many buckets are rolled up at once
+const CHUNK_BUCKETS: i32 = 60;
+
+/// Linearly interpolate the `p`-th percentile of sorted `values`.
+pub(crate) fn percentile(sorted: &[i64], p: f64) -> Option<f64> {
+    let last = sorted.len().checked_sub(1)?;
+    let rank = p * last as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    let (lower_value, upper_value) = (sorted[lower] as f64, sorted[upper] as f64);
+    Some(lower_value + (upper_value - lower_value) * (rank - lower as f64))
+}
+
+/// Summarize the pings of a target within the bucket starting at `datetime`.
+///
+/// `pings` must be sorted from old to new.
+pub fn summarize(target: &str, datetime: DateTime<Utc>, pings: &[&db::Ping]) -> PingRollup {
+    let latencies = pings
+        .iter()
+        .filter_map(|ping| ping.duration_ms)
+        .collect::<Vec<_>>();
+    let mut sorted = latencies.clone();
+    sorted.sort_unstable();
+
+    let count = pings.len() as i64;
+    let loss_count = count - latencies.len() as i64;
+    let avg_ms = (!latencies.is_empty())
+        .then(|| latencies.iter().sum::<i64>() as f64 / latencies.len() as f64);
+    let jitter_ms = (latencies.len() > 1).then(|| {
+        let differences = latencies
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).abs())
+            .sum::<i64>();
+        differences as f64 / (latencies.len() - 1) as f64
+    });
+
+    PingRollup {
+        id: None,
+        target: target.to_string(),
+        datetime,
+        count,
+        loss_count,
+        loss_ratio: if count == 0 {
+            0.0
+        } else {
+            loss_count as f64 / count as f64
+        },
+        min_ms: sorted.first().copied(),
+        avg_ms,
+        max_ms: sorted.last().copied(),
+        p50_ms: percentile(&sorted, 0.50),
+        p95_ms: percentile(&sorted, 0.95),
+        p99_ms: percentile(&sorted, 0.99),
+        jitter_ms,
+    }
+}
+
+/// Group `pings` by target and bucket and summarize each group.
+///
+/// `pings` must be sorted from old to new.
+pub fn aggregate(resolution: Resolution, pings: &[db::Ping]) -> Vec<PingRollup> {
+    let mut buckets = BTreeMap::<(DateTime<Utc>, &str), Vec<&db::Ping>>::new();
+    for ping in pings {
+        buckets
+            .entry((resolution.bucket(ping.datetime), ping.target.as_str()))
+            .or_default()
+            .push(ping);
+    }
+
+    buckets
+        .into_iter()
+        .map(|((datetime, target), pings)| summarize(target, datetime, &pings))
+        .collect()
+}
+
+/// Roll up the pings of every bucket of `resolution` that ended before `now`
+/// and hasn't been rolled up yet, or has been rolled up before pings of it
+/// have been replayed from the spool.
+///
+/// Returns the number of upserted rollups.
+pub async fn rollup(
+    db: &db::Database,
+    resolution: Resolution,
+    now: DateTime<Utc>,
+) -> anyhow::Result<usize> {
+    let late = db.take_late_ping(resolution);
+    let result = rollup_since(db, resolution, late, now).await;
+    if let (Err(_), Some(late)) = (&result, late) {
+        db.mark_late_ping(resolution, late);
+    }
+    result
+}
+
+async fn rollup_since(
+    db: &db::Database,
+    resolution: Resolution,
+    late: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> anyhow::Result<usize> {
+    let end = resolution.bucket(now - chrono::Duration::seconds(ROLLUP_DELAY_SECONDS));
+    let mut since = match db.select_latest_ping_rollup_datetime(resolution).await? {
+        Some(latest) => latest + resolution.duration(),
+        None => DateTime::<Utc>::UNIX_EPOCH,
+    };
+    if let Some(late) = late {
+        since = since.min(resolution.bucket(late));
+    }
+
+    let mut upserted = 0;
+    loop {
+        // skip buckets without any pings, e.g. while the service was down
+        let Some(next) = db.select_next_ping_datetime(since).await? else {
+            break;
+        };
+        let start = resolution.bucket(next);
+        if start >= end {
+            break;
+        }
+        let until = (start + resolution.duration() * CHUNK_BUCKETS).min(end);
+
+        let pings = db.select_pings(start, until).await?;
+        let rollups = aggregate(resolution, &pings);
+        db.upsert_ping_rollups(resolution, &rollups).await?;
+        upserted += rollups.len();
+        since = until;
+    }
+
+    Ok(upserted)
+}
+
+pub async fn rollup_loop(db: db::Database) -> ! {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        interval.tick().await;
+
+        for resolution in Resolution::ALL {
+            match rollup(&db, resolution, Utc::now()).await {
+                Ok(0) => {}
+                Ok(upserted) => log::info!(
+                    "rolled up {} buckets of pings into {}",
+                    upserted,
+                    resolution.table()
+                ),
+                Err(err) => log::warn!(
+                    "couldn't roll up pings into {}: {:?}",
+                    resolution.table(),
+                    err
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Every attempt to fetch the logs from the FRITZ!Box and append them to the
+//! database is recorded as a [`crate::db::PollRun`], whether it succeeded or
+//! not, so gaps in the logs can be traced back to what went wrong.
+
+use crate::api;
+
+/// Version of the app recorded with every poll run
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Why a poll run failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The FRITZ!Box didn't answer in time
+    Timeout,
+    /// The FRITZ!Box couldn't be reached, e.g. because it's restarting
+    Connect,
+    /// The FRITZ!Box answered with a non 2XX status
+    Status,
+    /// Any other error while sending a request or receiving the response
+    Request,
+    /// The FRITZ!Box rejected the login
+    Login,
+    /// The response couldn't be parsed
+    Response,
+    /// The logs couldn't be appended to the database
+    Database,
+}
+
+impl Failure {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Failure::Timeout => "timeout",
+            Failure::Connect => "connect",
+            Failure::Status => "status",
+            Failure::Request => "request",
+            Failure::Login => "login",
+            Failure::Response => "response",
+            Failure::Database => "database",
+        }
+    }
+
+    /// Classify an error while talking to the FRITZ!Box, e.g. while fetching
+    /// the logs.
+    pub fn of_fetch(err: &anyhow::Error) -> Failure {
+        let reqwest_err = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<reqwest::Error>());
+        match reqwest_err {
+            Some(err) if err.is_timeout() => Failure::Timeout,
+            Some(err) if err.is_connect() => Failure::Connect,
+            Some(err) if err.is_status() => Failure::Status,
+            Some(_) => Failure::Request,
+            None if err.downcast_ref::<api::StatusError>().is_some() => Failure::Status,
+            None if err.downcast_ref::<api::LoginFailed>().is_some() => Failure::Login,
+            None => Failure::Response,
+        }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Name of the host this instance is running on, from `HOSTNAME` or
+/// `/etc/hostname`.
+pub fn host() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -0,0 +1,103 @@
+//! Prune rows from high-volume tables, like `ping` and `requests`, once
+//! they've expired. Tables without a policy are kept forever.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+
+use crate::db;
+
+/// How long rows of a table are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub table: db::Table,
+    pub max_age: chrono::Duration,
+}
+
+/// Parse comma separated `<table>=<days>` pairs, e.g. `ping=30,requests=90`.
+pub fn parse_policies(s: &str) -> anyhow::Result<Vec<RetentionPolicy>> {
+    let mut policies = Vec::<RetentionPolicy>::new();
+    for policy in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (table, days) = policy
+            .split_once('=')
+            .with_context(|| format!("expected <table>=<days>, got {:?}", policy))?;
+        let table = table.trim().parse::<db::Table>()?;
+        let days = days
+            .trim()
+            .parse::<u32>()
+            .with_context(|| format!("parse days of {}", table))?;
+        if days == 0 {
+            anyhow::bail!("rows of {} must be kept for at least a day", table);
+        }
+        if policies.iter().any(|other| other.table == table) {
+            anyhow::bail!("more than one policy for {}", table);
+        }
+
+        policies.push(RetentionPolicy {
+            table,
+            max_age: chrono::Duration::days(i64::from(days)),
+        });
+    }
+    Ok(policies)
+}
+
+pub struct PruneLoopOptions {
+    db: db::Database,
+    pause: Duration,
+    policies: Vec<RetentionPolicy>,
+}
+
+impl PruneLoopOptions {
+    /// Load the policies from `FRITZBOX_RETENTION_DAYS`, `None` if no table
+    /// ever expires.
+    pub fn try_from_env(db: db::Database) -> anyhow::Result<Option<PruneLoopOptions>> {
+        let policies = match std::env::var("FRITZBOX_RETENTION_DAYS") {
+            Err(_) => return Ok(None),
+            Ok(policies) => parse_policies(&policies).context("parse FRITZBOX_RETENTION_DAYS")?,
+        };
+        if policies.is_empty() {
+            return Ok(None);
+        }
+
+        let pause_seconds = match std::env::var("FRITZBOX_RETENTION_PAUSE_SECONDS") {
+            Err(_) => 60 * 60,
+            Ok(pause_seconds) => pause_seconds
+                .parse::<u64>()
+                .context("parse FRITZBOX_RETENTION_PAUSE_SECONDS")?,
+        };
+
+        Ok(Some(PruneLoopOptions {
+            db,
+            pause: Duration::from_secs(pause_seconds),
+            policies,
+        }))
+    }
+}
+
+/// Prune every table once according to its policy.
+pub async fn prune(db: &db::Database, policies: &[RetentionPolicy]) {
+    for policy in policies {
+        let before = Utc::now() - policy.max_age;
+        match db.prune(policy.table, before).await {
+            Ok(0) => log::debug!("no rows older than {} in {}", before, policy.table),
+            Ok(pruned) => log::info!(
+                "pruned {} rows older than {} from {}",
+                pruned,
+                before,
+                policy.table
+            ),
+            Err(err) => log::warn!("couldn't prune {}: {:?}", policy.table, err),
+        }
+    }
+}
+
+pub async fn prune_loop(opts: PruneLoopOptions) -> ! {
+    let mut interval = tokio::time::interval(opts.pause);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        interval.tick().await;
+        prune(&opts.db, &opts.policies).await;
+    }
+}
@@ -0,0 +1,109 @@
+//! The event log of the simulated FRITZ!Box.
+
+use std::collections::VecDeque;
+
+use chrono::NaiveDateTime;
+
+use crate::api;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    /// Timestamp at which this entry was last logged
+    datetime: NaiveDateTime,
+    message: String,
+    message_id: i64,
+    category_id: i64,
+    /// Timestamp at which this entry was first logged and how often it was
+    /// logged, if it was logged more than once in a row
+    repetition: Option<(NaiveDateTime, i64)>,
+}
+
+impl Entry {
+    fn to_api(&self) -> api::Log {
+        let message = match self.repetition {
+            None => self.message.clone(),
+            Some((since, count)) => format!(
+                "{} [{} Meldungen seit {}]",
+                self.message,
+                count,
+                since.format("%d.%m.%y %H:%M:%S")
+            ),
+        };
+
+        api::Log([
+            self.datetime.format("%d.%m.%y").to_string(),
+            self.datetime.format("%H:%M:%S").to_string(),
+            message,
+            self.message_id.to_string(),
+            self.category_id.to_string(),
+            format!("/help/help.lua?helppage=log_{}", self.message_id),
+        ])
+    }
+}
+
+/// Ring buffer holding the most recent logs, like the FRITZ!Box does.
+///
+/// A log with the same message as the most recent one isn't added again, the
+/// most recent one is updated and counts the repetitions instead.
+#[derive(Debug, Clone)]
+pub struct LogBuffer {
+    /// Newest entry at the front
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> LogBuffer {
+        LogBuffer {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Log a message at `datetime`, dropping the oldest entry if the buffer is full.
+    pub fn push(
+        &mut self,
+        datetime: NaiveDateTime,
+        message: &str,
+        message_id: i64,
+        category_id: i64,
+    ) {
+        if let Some(newest) = self.entries.front_mut() {
+            let is_repetition = newest.message == message
+                && newest.message_id == message_id
+                && newest.category_id == category_id;
+            if is_repetition {
+                let (since, count) = newest.repetition.unwrap_or((newest.datetime, 1));
+                newest.repetition = Some((since, count + 1));
+                newest.datetime = datetime;
+                return;
+            }
+        }
+
+        self.entries.push_front(Entry {
+            datetime,
+            message: message.to_string(),
+            message_id,
+            category_id,
+            repetition: None,
+        });
+        self.entries.truncate(self.capacity);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The logs as returned by the API, **newest log at index 0**.
+    pub fn to_api(&self) -> Vec<api::Log> {
+        self.entries.iter().map(Entry::to_api).collect()
+    }
+}
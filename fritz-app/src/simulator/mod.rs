@@ -0,0 +1,324 @@
+//! Emulates the FRITZ!Box web API on localhost so the `Client` and the
+//! daemon can be tested end to end without a FRITZ!Box.
+//!
+//! The simulator serves HTTPS with a freshly generated self-signed
+//! certificate, just like the FRITZ!Box, and implements
+//!
+//! - the `login_sid.lua?version=2` PBKDF2 challenge-response login, including
+//!   the `BlockTime` after failed logins and logging successful logins
+//! - the event log on `data.lua` (`page=log`), a ring buffer folding
+//!   repetitions, and clearing it
+//! - exporting the certificate with `cgi-bin/firmwarecfg`
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use axum_server::tls_rustls::RustlsConfig;
+use chrono::{NaiveDateTime, Utc};
+use parking_lot::Mutex;
+use pbkdf2::pbkdf2_hmac;
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use sha2::Sha256;
+
+use crate::api;
+
+mod buffer;
+pub use buffer::LogBuffer;
+
+mod routes;
+
+/// Failed logins in a row after which the block time stops doubling
+const MAX_BLOCK_DOUBLINGS: u32 = 10;
+
+/// Message id of the log of a login to the web interface
+const LOGIN_MESSAGE_ID: i64 = 344;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The only user that can log in
+    pub username: String,
+    pub password: String,
+    /// PBKDF2 iterations of the static part of the challenge, the FRITZ!Box uses 60000
+    pub rounds_1: u32,
+    /// PBKDF2 iterations of the dynamic part of the challenge, the FRITZ!Box uses 6000
+    pub rounds_2: u32,
+    /// How many logs the event log holds before the oldest are dropped
+    pub capacity: usize,
+    /// How long logins are blocked after a failed login, doubled with every
+    /// further failed login in a row
+    pub block_time: Duration,
+    /// Timezone of the clock of the FRITZ!Box
+    pub timezone: chrono_tz::Tz,
+    /// Whether successful logins are logged, like the FRITZ!Box does by default
+    pub log_logins: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            username: "fritz3713".to_string(),
+            password: "password".to_string(),
+            rounds_1: 60000,
+            rounds_2: 6000,
+            capacity: 400,
+            block_time: Duration::from_secs(1),
+            timezone: chrono_tz::Europe::Berlin,
+            log_logins: true,
+        }
+    }
+}
+
+struct State {
+    config: Config,
+    /// Static part of the challenge
+    salt_1: [u8; 16],
+    /// PBKDF2 hash of the password using `salt_1`, which is all the FRITZ!Box
+    /// needs to verify responses
+    hash_1: [u8; 32],
+    /// Dynamic part of the challenge that hasn't been answered yet
+    salt_2: Option<[u8; 16]>,
+    sessions: HashSet<api::SessionId>,
+    /// Failed logins in a row
+    failed_logins: u32,
+    blocked_until: Option<Instant>,
+    logs: LogBuffer,
+}
+
+impl State {
+    fn new(config: Config) -> State {
+        let salt_1: [u8; 16] = rand::random();
+        let mut hash_1 = [0u8; 32];
+        pbkdf2_hmac::<Sha256>(
+            config.password.as_bytes(),
+            &salt_1,
+            config.rounds_1,
+            &mut hash_1,
+        );
+
+        State {
+            salt_1,
+            hash_1,
+            salt_2: None,
+            sessions: HashSet::new(),
+            failed_logins: 0,
+            blocked_until: None,
+            logs: LogBuffer::new(config.capacity),
+            config,
+        }
+    }
+
+    /// The current challenge, a new one if the last one has been answered.
+    fn challenge(&mut self) -> String {
+        let salt_2 = *self.salt_2.get_or_insert_with(rand::random);
+        format!(
+            "2${}${}${}${}",
+            self.config.rounds_1,
+            hex::encode(self.salt_1),
+            self.config.rounds_2,
+            hex::encode(salt_2)
+        )
+    }
+
+    /// Seconds until logins are accepted again.
+    fn block_time(&self) -> u64 {
+        self.blocked_until.map_or(0, |until| {
+            let remaining = until.saturating_duration_since(Instant::now());
+            remaining.as_secs() + u64::from(remaining.subsec_nanos() != 0)
+        })
+    }
+
+    /// Check the `response` of `username` to the current challenge and open a
+    /// new session if it's correct and logins aren't blocked.
+    fn login(&mut self, username: &str, response: &str) -> Option<api::SessionId> {
+        // every challenge can only be answered once
+        let salt_2 = self.salt_2.take();
+        if self.block_time() != 0 {
+            return None;
+        }
+
+        let is_correct = username == self.config.username
+            && salt_2.is_some_and(|salt_2| self.verify(&salt_2, response));
+        if !is_correct {
+            let doublings = self.failed_logins.min(MAX_BLOCK_DOUBLINGS);
+            self.failed_logins += 1;
+            self.blocked_until = Some(Instant::now() + self.config.block_time * (1 << doublings));
+            return None;
+        }
+
+        self.failed_logins = 0;
+        self.blocked_until = None;
+
+        if self.config.log_logins {
+            let message = format!(
+                "Anmeldung des Benutzers {} an der FRITZ!Box-Benutzeroberfläche.",
+                self.config.username
+            );
+            self.logs.push(self.now(), &message, LOGIN_MESSAGE_ID, 1);
+        }
+
+        let session_id = loop {
+            let session_id = api::SessionId { id: rand::random() };
+            if session_id.is_valid() {
+                break session_id;
+            }
+        };
+        self.sessions.insert(session_id);
+        Some(session_id)
+    }
+
+    /// Whether `response` is `<salt_2>$<hash>` with the correct hash.
+    fn verify(&self, salt_2: &[u8; 16], response: &str) -> bool {
+        let Some((salt, hash)) = response.split_once('$') else {
+            return false;
+        };
+        if salt != hex::encode(salt_2) {
+            return false;
+        }
+
+        let mut expected = [0u8; 32];
+        pbkdf2_hmac::<Sha256>(&self.hash_1, salt_2, self.config.rounds_2, &mut expected);
+        hash == hex::encode(expected)
+    }
+
+    /// The current local time of the FRITZ!Box.
+    fn now(&self) -> NaiveDateTime {
+        Utc::now()
+            .with_timezone(&self.config.timezone)
+            .naive_local()
+    }
+
+    /// The session with id `sid`, if it's open.
+    fn session(&self, sid: Option<&str>) -> Option<api::SessionId> {
+        let session_id = sid?.parse().ok()?;
+        self.sessions.contains(&session_id).then_some(session_id)
+    }
+}
+
+/// A simulated FRITZ!Box, cloning it shares its state.
+#[derive(Clone)]
+pub struct Simulator {
+    state: Arc<Mutex<State>>,
+    /// Self-signed certificate, PEM encoded
+    cert_pem: Arc<str>,
+    tls: Arc<rustls::ServerConfig>,
+}
+
+impl Simulator {
+    /// Create a simulator with a freshly generated certificate for
+    /// `localhost`, `127.0.0.1` and `fritz.box`.
+    pub fn new(config: Config) -> anyhow::Result<Simulator> {
+        let names = ["localhost", "127.0.0.1", "fritz.box"].map(str::to_string);
+        let certified =
+            rcgen::generate_simple_self_signed(names).context("generate certificate")?;
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let tls = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .context("configure tls versions")?
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![certified.cert.der().clone()],
+                PrivateKeyDer::Pkcs8(key),
+            )
+            .context("configure tls certificate")?;
+
+        Ok(Simulator {
+            state: Arc::new(Mutex::new(State::new(config))),
+            cert_pem: certified.cert.pem().into(),
+            tls: Arc::new(tls),
+        })
+    }
+
+    /// The certificate the simulator serves and exports, PEM encoded.
+    pub fn cert_pem(&self) -> &str {
+        &self.cert_pem
+    }
+
+    /// The current local time of the FRITZ!Box.
+    pub fn now(&self) -> NaiveDateTime {
+        self.state.lock().now()
+    }
+
+    /// Log a message at `datetime`, which is the local time of the FRITZ!Box.
+    pub fn push_log(
+        &self,
+        datetime: NaiveDateTime,
+        message: &str,
+        message_id: i64,
+        category_id: i64,
+    ) {
+        self.state
+            .lock()
+            .logs
+            .push(datetime, message, message_id, category_id);
+    }
+
+    /// The logs as returned by the API, **newest log at index 0**.
+    pub fn logs(&self) -> Vec<api::Log> {
+        self.state.lock().logs.to_api()
+    }
+
+    pub fn clear_logs(&self) {
+        self.state.lock().logs.clear();
+    }
+
+    /// Seconds until logins are accepted again, as reported in `BlockTime`.
+    pub fn block_time(&self) -> u64 {
+        self.state.lock().block_time()
+    }
+
+    /// Close all sessions, e.g. like they timed out or the FRITZ!Box rebooted.
+    pub fn expire_sessions(&self) {
+        self.state.lock().sessions.clear();
+    }
+
+    /// Serve the API over HTTPS on `addr` until the returned [`Server`] is dropped.
+    ///
+    /// Use port 0 to pick a free port, see [`Server::addr`].
+    pub async fn serve(&self, addr: SocketAddr) -> anyhow::Result<Server> {
+        let handle = axum_server::Handle::new();
+        let server = axum_server::bind_rustls(addr, RustlsConfig::from_config(self.tls.clone()))
+            .handle(handle.clone());
+        let app = routes::router(self.clone());
+
+        tokio::spawn(async move {
+            if let Err(err) = server.serve(app.into_make_service()).await {
+                log::error!("simulator stopped: {:?}", err);
+            }
+        });
+
+        let addr = handle
+            .listening()
+            .await
+            .with_context(|| format!("listen on {}", addr))?;
+        Ok(Server { addr, handle })
+    }
+}
+
+/// A running simulator, stops serving when dropped.
+pub struct Server {
+    addr: SocketAddr,
+    handle: axum_server::Handle,
+}
+
+impl Server {
+    /// Address the simulator is listening on.
+    pub const fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Domain to create a `Client` with, e.g. `127.0.0.1:44321`.
+    pub fn domain(&self) -> String {
+        self.addr.to_string()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.handle.shutdown();
+    }
+}
@@ -0,0 +1,136 @@
+//! HTTP handlers of the simulated FRITZ!Box.
+
+use std::collections::HashMap;
+
+use axum::extract::{Multipart, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Form, Router};
+
+use super::Simulator;
+use crate::api;
+
+type Params = HashMap<String, String>;
+
+pub fn router(simulator: Simulator) -> Router {
+    Router::new()
+        .route("/login_sid.lua", get(login_challenge).post(login_sid))
+        .route("/data.lua", post(data))
+        .route("/cgi-bin/firmwarecfg", post(firmwarecfg))
+        .with_state(simulator)
+}
+
+/// The `SessionInfo` XML, with the rights of the user if `session_id` is set.
+fn session_info(state: &mut super::State, session_id: Option<api::SessionId>) -> String {
+    let rights = match session_id {
+        None => "",
+        Some(_) => {
+            "<Name>Dial</Name><Access>2</Access><Name>App</Name><Access>2</Access>\
+             <Name>HomeAuto</Name><Access>2</Access><Name>BoxAdmin</Name><Access>2</Access>\
+             <Name>Phone</Name><Access>2</Access><Name>NAS</Name><Access>2</Access>"
+        }
+    };
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?><SessionInfo><SID>{}</SID>\
+         <Challenge>{}</Challenge><BlockTime>{}</BlockTime><Rights>{}</Rights>\
+         <Users><User last=\"1\">{}</User></Users></SessionInfo>\n",
+        session_id.unwrap_or(api::SessionId { id: [0; 8] }),
+        state.challenge(),
+        state.block_time(),
+        rights,
+        quick_xml::escape::escape(state.config.username.as_str()),
+    )
+}
+
+fn xml(body: String) -> Response {
+    ([(header::CONTENT_TYPE, "text/xml")], body).into_response()
+}
+
+async fn login_challenge(State(simulator): State<Simulator>) -> Response {
+    xml(session_info(&mut simulator.state.lock(), None))
+}
+
+/// Log in, log out or check a session id, depending on the parameters.
+async fn login_sid(State(simulator): State<Simulator>, Form(params): Form<Params>) -> Response {
+    let mut state = simulator.state.lock();
+    let sid = params.get("sid").map(String::as_str);
+
+    if params.get("logout").is_some_and(|logout| logout == "1") {
+        if let Some(session_id) = state.session(sid) {
+            state.sessions.remove(&session_id);
+        }
+        return xml(session_info(&mut state, None));
+    }
+
+    let session_id = match (params.get("username"), params.get("response")) {
+        (Some(username), Some(response)) => state.login(username, response),
+        _ => state.session(sid),
+    };
+    let body = session_info(&mut state, session_id);
+    drop(state);
+    xml(body)
+}
+
+/// The event log, or clear it if `del` is set.
+async fn data(State(simulator): State<Simulator>, Form(params): Form<Params>) -> Response {
+    let mut state = simulator.state.lock();
+    let sid = params.get("sid").map(String::as_str);
+
+    let Some(session_id) = state.session(sid) else {
+        return StatusCode::FORBIDDEN.into_response();
+    };
+    if params.get("page").map(String::as_str) != Some("log") {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    if params.get("del").is_some_and(|del| del == "1") {
+        state.logs.clear();
+    }
+    let logs: Vec<_> = state.logs.to_api().into_iter().map(|log| log.0).collect();
+    drop(state);
+
+    let body = serde_json::json!({
+        "pid": "log",
+        "timeTillLogout": "1200",
+        "time": [],
+        "data": { "filter": "0", "log": logs },
+        "sid": session_id.to_string(),
+    });
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        body.to_string(),
+    )
+        .into_response()
+}
+
+/// Export the certificate of the simulator.
+async fn firmwarecfg(State(simulator): State<Simulator>, mut multipart: Multipart) -> Response {
+    let mut params = Params::new();
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => return err.into_response(),
+        };
+        let name = field.name().unwrap_or_default().to_string();
+        match field.text().await {
+            Ok(value) => params.insert(name, value),
+            Err(err) => return err.into_response(),
+        };
+    }
+
+    let sid = params.get("sid").map(String::as_str);
+    if simulator.state.lock().session(sid).is_none() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if !params.contains_key("BoxCertExport") {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    (
+        [(header::CONTENT_TYPE, "application/x-pem-file")],
+        simulator.cert_pem().to_string(),
+    )
+        .into_response()
+}
@@ -0,0 +1,134 @@
+use chrono::{DateTime, Duration, FixedOffset, TimeZone, Utc};
+
+use crate::api;
+use crate::fritz::{check_clock, reconcile, BoxTimezone, Log, TimeResolution};
+
+/// Parse a snapshot of the FRITZ!Box in UTC, newest log first
+fn logs(logs: &[(&str, &str, &str)]) -> Vec<Log> {
+    let logs = logs
+        .iter()
+        .map(|&(date, time, message_id)| {
+            api::Log([
+                date.to_string(),
+                time.to_string(),
+                "Meldung".to_string(),
+                message_id.to_string(),
+                "1".to_string(),
+                String::new(),
+            ])
+        })
+        .collect();
+    let utc = BoxTimezone::Offset(FixedOffset::east_opt(0).unwrap());
+    Log::from_api(logs, &utc, None).unwrap()
+}
+
+fn utc(datetime: &DateTime<chrono::Local>) -> DateTime<Utc> {
+    datetime.with_timezone(&Utc)
+}
+
+/// The logs are sorted the way [`reconcile`] expects them
+fn assert_sorted(logs: &[Log]) {
+    let mut logs = logs.to_vec();
+    logs.reverse();
+    assert!(reconcile(None, &logs).is_ok());
+}
+
+#[test]
+fn plausible() {
+    let requested = Utc.with_ymd_and_hms(2023, 7, 1, 10, 0, 5).unwrap();
+    let mut snapshot = logs(&[("01.07.23", "10:00:00", "2"), ("01.07.23", "09:00:00", "1")]);
+    let expected = snapshot.clone();
+
+    let clock = check_clock(&mut snapshot, requested);
+    assert_eq!(clock.drift, Some(Duration::seconds(-5)));
+    assert_eq!(clock.implausible, 0);
+    assert_eq!(snapshot, expected);
+}
+
+#[test]
+fn power_loss() {
+    // the FRITZ!Box restarted at 01.01.70 and synced with NTP later
+    let requested = Utc.with_ymd_and_hms(2023, 7, 1, 10, 0, 0).unwrap();
+    let mut snapshot = logs(&[
+        ("01.07.23", "09:30:00", "4"),
+        ("01.01.70", "00:00:20", "3"),
+        ("01.01.70", "00:00:10", "3"),
+        ("01.07.23", "09:00:00", "1"),
+    ]);
+
+    let clock = check_clock(&mut snapshot, requested);
+    assert_eq!(clock.drift, Some(Duration::minutes(-30)));
+    assert_eq!(clock.implausible, 2);
+
+    let after = Utc.with_ymd_and_hms(2023, 7, 1, 9, 0, 0).unwrap();
+    assert_eq!(
+        utc(&snapshot[2].datetime),
+        after + Duration::milliseconds(1)
+    );
+    assert_eq!(
+        utc(&snapshot[1].datetime),
+        after + Duration::milliseconds(2)
+    );
+    assert_eq!(
+        snapshot[1].datetime_resolution,
+        Some(TimeResolution::Clamped)
+    );
+    assert_eq!(snapshot[0].datetime_resolution, None);
+    assert_sorted(&snapshot);
+}
+
+#[test]
+fn ahead() {
+    // the clock of the FRITZ!Box was a day ahead before it synced with NTP
+    let requested = Utc.with_ymd_and_hms(2023, 7, 1, 10, 0, 0).unwrap();
+    let mut snapshot = logs(&[
+        ("01.07.23", "09:59:00", "3"),
+        ("02.07.23", "09:00:00", "2"),
+        ("02.07.23", "08:00:00", "1"),
+    ]);
+
+    let clock = check_clock(&mut snapshot, requested);
+    assert_eq!(clock.implausible, 2);
+
+    let before = Utc.with_ymd_and_hms(2023, 7, 1, 9, 59, 0).unwrap();
+    assert_eq!(
+        utc(&snapshot[1].datetime),
+        before - Duration::milliseconds(1)
+    );
+    assert_eq!(
+        utc(&snapshot[2].datetime),
+        before - Duration::milliseconds(2)
+    );
+    assert_sorted(&snapshot);
+}
+
+#[test]
+fn rollover() {
+    // two-digit years from 70 on are parsed as 19XX
+    let requested = Utc.with_ymd_and_hms(2070, 1, 1, 0, 5, 0).unwrap();
+    let mut snapshot = logs(&[("01.01.70", "00:01:00", "2"), ("31.12.69", "23:59:00", "1")]);
+
+    let clock = check_clock(&mut snapshot, requested);
+    assert_eq!(clock.implausible, 0);
+    assert_eq!(
+        utc(&snapshot[0].datetime),
+        Utc.with_ymd_and_hms(2070, 1, 1, 0, 1, 0).unwrap()
+    );
+    assert_eq!(
+        snapshot[0].datetime_resolution,
+        Some(TimeResolution::Rollover)
+    );
+    assert_eq!(snapshot[1].datetime_resolution, None);
+    assert_sorted(&snapshot);
+}
+
+#[test]
+fn all_implausible() {
+    let requested = Utc.with_ymd_and_hms(2023, 7, 1, 10, 0, 0).unwrap();
+    let mut snapshot = logs(&[("01.01.70", "00:00:10", "1")]);
+    let expected = snapshot.clone();
+
+    let clock = check_clock(&mut snapshot, requested);
+    assert_eq!(clock.implausible, 1);
+    assert_eq!(snapshot, expected);
+}
@@ -0,0 +1,920 @@
+use futures_util::TryStreamExt;
+
+use crate::db::{self, Database, Event, LogQuery, Order, RequestQuery, Resolution, Table};
+use crate::fritz::{Log, TimeResolution};
+use crate::ping::rollup::rollup;
+use crate::poll::Failure;
+
+/// Run the same test against every backend, Postgres only if `DATABASE_URL`
+/// points to a Postgres server, see [`open_postgres`].
+macro_rules! backends {
+    ($name:ident) => {
+        mod $name {
+            #[tokio::test(flavor = "current_thread")]
+            async fn sqlite() -> anyhow::Result<()> {
+                super::$name(crate::db::Database::open_sqlite_in_memory().await?).await
+            }
+
+            #[tokio::test(flavor = "current_thread")]
+            async fn memory() -> anyhow::Result<()> {
+                super::$name(crate::db::Database::open_in_memory()).await
+            }
+
+            #[tokio::test(flavor = "current_thread")]
+            async fn postgres() -> anyhow::Result<()> {
+                match super::open_postgres(stringify!($name)).await? {
+                    Some(db) => super::$name(db).await,
+                    None => Ok(()),
+                }
+            }
+        }
+    };
+    // tests of time-series tables, also against Postgres after converting
+    // them into partitioned tables or hypertables
+    (time_series $name:ident) => {
+        mod $name {
+            #[tokio::test(flavor = "current_thread")]
+            async fn sqlite() -> anyhow::Result<()> {
+                super::$name(crate::db::Database::open_sqlite_in_memory().await?).await
+            }
+
+            #[tokio::test(flavor = "current_thread")]
+            async fn memory() -> anyhow::Result<()> {
+                super::$name(crate::db::Database::open_in_memory()).await
+            }
+
+            #[tokio::test(flavor = "current_thread")]
+            async fn postgres() -> anyhow::Result<()> {
+                match super::open_postgres(stringify!($name)).await? {
+                    Some(db) => super::$name(db).await,
+                    None => Ok(()),
+                }
+            }
+
+            #[tokio::test(flavor = "current_thread")]
+            async fn partitioned() -> anyhow::Result<()> {
+                let name = concat!(stringify!($name), "_partitioned");
+                match super::open_postgres(name).await? {
+                    Some(db) => {
+                        let mode = crate::db::TimeSeriesMode::Partitioned;
+                        assert_eq!(db.convert_time_series(mode).await?, mode);
+                        super::$name(db).await
+                    }
+                    None => Ok(()),
+                }
+            }
+
+            #[tokio::test(flavor = "current_thread")]
+            async fn timescale() -> anyhow::Result<()> {
+                let name = concat!(stringify!($name), "_timescale");
+                match super::open_timescale(name).await? {
+                    Some(db) => {
+                        let mode = crate::db::TimeSeriesMode::Timescale;
+                        assert_eq!(db.convert_time_series(mode).await?, mode);
+                        super::$name(db).await
+                    }
+                    None => Ok(()),
+                }
+            }
+        }
+    };
+    // backends that aren't shared between processes
+    (local $name:ident) => {
+        mod $name {
+            #[tokio::test(flavor = "current_thread")]
+            async fn sqlite() -> anyhow::Result<()> {
+                super::$name(crate::db::Database::open_sqlite_in_memory().await?).await
+            }
+
+            #[tokio::test(flavor = "current_thread")]
+            async fn memory() -> anyhow::Result<()> {
+                super::$name(crate::db::Database::open_in_memory()).await
+            }
+        }
+    };
+}
+
+/// Open a fresh Postgres database, see [`create_postgres`].
+pub async fn open_postgres(name: &str) -> anyhow::Result<Option<Database>> {
+    match create_postgres(name).await? {
+        Some(url) => Database::open(&url).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Open a fresh Postgres database, see [`create_postgres`], if its server
+/// has the TimescaleDB extension.
+///
+/// Returns `None` otherwise, so the tests of hypertables are skipped.
+pub async fn open_timescale(name: &str) -> anyhow::Result<Option<Database>> {
+    use sqlx::Connection;
+
+    let Some(url) = create_postgres(name).await? else {
+        return Ok(None);
+    };
+    let mut conn = sqlx::PgConnection::connect(&url).await?;
+    let available: bool = sqlx::query_scalar(
+        r#"SELECT EXISTS (SELECT 1 FROM "pg_available_extensions" WHERE "name" = 'timescaledb')"#,
+    )
+    .fetch_one(&mut conn)
+    .await?;
+    conn.close().await?;
+
+    if !available {
+        return Ok(None);
+    }
+    Database::open(&url).await.map(Some)
+}
+
+/// Create a fresh Postgres database `fritz_test_{name}` on the server
+/// `DATABASE_URL` points to, replacing it if it exists, and return its url.
+///
+/// Returns `None` if `DATABASE_URL` isn't a Postgres url, so the tests that
+/// need a server are skipped.
+pub async fn create_postgres(name: &str) -> anyhow::Result<Option<String>> {
+    use sqlx::Connection;
+
+    let Ok(url) = std::env::var("DATABASE_URL") else {
+        return Ok(None);
+    };
+    if !url.starts_with("postgres://") && !url.starts_with("postgresql://") {
+        return Ok(None);
+    }
+
+    let name = format!("fritz_test_{}", name);
+    let mut conn = sqlx::PgConnection::connect(&url).await?;
+    sqlx::query(&format!(
+        r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE)"#,
+        name
+    ))
+    .execute(&mut conn)
+    .await?;
+    sqlx::query(&format!(r#"CREATE DATABASE "{}""#, name))
+        .execute(&mut conn)
+        .await?;
+    conn.close().await?;
+
+    // replace the database in the path, keeping the query
+    let (url, query) = url
+        .split_once('?')
+        .map_or((&url[..], None), |(url, query)| (url, Some(query)));
+    let (server, _) = url.rsplit_once('/').expect("postgres url has a path");
+    Ok(Some(match query {
+        Some(query) => format!("{}/{}?{}", server, name, query),
+        None => format!("{}/{}", server, name),
+    }))
+}
+
+backends!(append_and_resync);
+backends!(repetition_history);
+backends!(datetime_resolution);
+backends!(query_logs);
+backends!(page_logs);
+backends!(search_logs);
+backends!(time_series prune);
+backends!(prune_logs);
+backends!(time_series rollup_pings);
+backends!(time_series rollup_pings_after_pause);
+backends!(local leadership);
+backends!(subscribe);
+backends!(poll_runs);
+backends!(clear);
+backends!(time_series query_requests);
+
+async fn append_and_resync(db: Database) -> anyhow::Result<()> {
+    assert!(db.is_empty().await?);
+
+    let snapshot = [
+        log!([1, 1, 1], 1, 1, repetition!()),
+        log!([1, 1, 2], 2, 1, repetition!()),
+        log!([1, 1, 4], 3, 1, repetition!([1, 1, 3], 2)),
+    ];
+
+    // the second log got lost somehow
+    db.append_new_logs(&[snapshot[0].clone(), snapshot[2].clone()])
+        .await?;
+    assert_eq!(db.missing_logs(&snapshot).await?, [snapshot[1].clone()]);
+
+    let resync = db.resync(&snapshot, false).await?;
+    assert_eq!(resync.missing, [snapshot[1].clone()]);
+    assert!(db.resync(&snapshot, false).await?.is_empty());
+    assert!(db.missing_logs(&snapshot).await?.is_empty());
+
+    // the log inserted by the resync is older than the tail
+    assert_eq!(db.select_latest_log().await?, Some(snapshot[1].clone()));
+    assert_eq!(db.select_tail_log().await?, Some(snapshot[2].clone()));
+
+    // so it isn't used as the most recent log
+    let updated = log!([1, 1, 5], 3, 1, repetition!([1, 1, 3], 3));
+    let new = log!([1, 1, 6], 4, 1, repetition!());
+    let upserted = db
+        .append_new_logs(&[snapshot[0].clone(), updated.clone(), new.clone()])
+        .await?;
+    assert_eq!(upserted, [updated.clone(), new.clone()]);
+
+    // logs are ordered by the time they've been inserted
+    assert_eq!(
+        db.select_latest_logs(0, 10).await?,
+        [new, snapshot[1].clone(), updated, snapshot[0].clone()]
+    );
+
+    db.close().await;
+    Ok(())
+}
+
+async fn repetition_history(db: Database) -> anyhow::Result<()> {
+    for count in 2..5 {
+        db.append_new_logs(&[log!(
+            [1, 1, count],
+            1,
+            1,
+            repetition!([1, 1, 1], count.into())
+        )])
+        .await?;
+    }
+
+    let counts = db
+        .select_log_repetitions(1)
+        .await?
+        .into_iter()
+        .map(|rep| rep.repetition_count)
+        .collect::<Vec<_>>();
+    assert_eq!(counts, [2, 3, 4]);
+
+    db.clear_logs().await?;
+    assert!(db.is_empty().await?);
+    assert!(db.select_log_repetitions(1).await?.is_empty());
+
+    db.close().await;
+    Ok(())
+}
+
+async fn datetime_resolution(db: Database) -> anyhow::Result<()> {
+    let mut log = log!([1, 1, 2], 1, 1, repetition!([1, 1, 1], 2));
+    log.datetime_resolution = Some(TimeResolution::Second);
+    log.repetition.as_mut().unwrap().datetime_resolution = Some(TimeResolution::First);
+    db.append_new_logs(&[log.clone()]).await?;
+    assert_eq!(db.select_latest_log().await?, Some(log.clone()));
+
+    // another fetch might resolve the same entry differently
+    let mut fetched = log.clone();
+    fetched.datetime_resolution = None;
+    assert!(db.missing_logs(&[fetched]).await?.is_empty());
+
+    let mut updated = log!([1, 1, 3], 1, 1, repetition!([1, 1, 1], 3));
+    updated.datetime_resolution = Some(TimeResolution::Shifted);
+    db.update_log(&log, &updated).await?;
+    assert_eq!(db.select_latest_log().await?, Some(updated));
+
+    db.close().await;
+    Ok(())
+}
+
+/// Logs with distinct messages, categories and repetitions to filter by.
+fn query_logs_fixture() -> [Log; 4] {
+    [
+        Log {
+            message: "WLAN-Gerät angemeldet".to_string(),
+            ..log!([1, 1, 1], 1, 1, repetition!())
+        },
+        Log {
+            message: "DSL antwortet nicht".to_string(),
+            ..log!([1, 1, 3], 2, 2, repetition!([1, 1, 2], 2))
+        },
+        Log {
+            message: "WLAN-Gerät abgemeldet".to_string(),
+            ..log!([1, 1, 4], 3, 1, repetition!())
+        },
+        Log {
+            message: "DSL ist verfügbar".to_string(),
+            ..log!([1, 1, 5], 4, 2, repetition!())
+        },
+    ]
+}
+
+async fn query_logs(db: Database) -> anyhow::Result<()> {
+    let logs = query_logs_fixture();
+    db.append_new_logs(&logs).await?;
+
+    let select = |query: LogQuery| {
+        let db = &db;
+        async move {
+            let logs = db.query_logs(&query).await?;
+            anyhow::Ok(
+                logs.into_iter()
+                    .map(|log| log.log.message_id)
+                    .collect::<Vec<_>>(),
+            )
+        }
+    };
+
+    assert_eq!(select(LogQuery::new()).await?, [1, 2, 3, 4]);
+    assert_eq!(
+        select(
+            LogQuery::new()
+                .since(logs[1].datetime)
+                .until(logs[3].datetime)
+        )
+        .await?,
+        [2, 3]
+    );
+    assert_eq!(
+        select(LogQuery::new().message_ids([1, 4, 9])).await?,
+        [1, 4]
+    );
+    assert_eq!(select(LogQuery::new().message_ids([])).await?, [0; 0]);
+    assert_eq!(select(LogQuery::new().category_id(2)).await?, [2, 4]);
+    assert_eq!(
+        select(LogQuery::new().message_contains("WLAN")).await?,
+        [1, 3]
+    );
+    assert_eq!(
+        select(LogQuery::new().message_matches("^DSL.*t$")).await?,
+        [2]
+    );
+    assert_eq!(select(LogQuery::new().repeated_only()).await?, [2]);
+    assert_eq!(
+        select(LogQuery::new().category_id(1).order(Order::Descending)).await?,
+        [3, 1]
+    );
+
+    db.close().await;
+    Ok(())
+}
+
+async fn page_logs(db: Database) -> anyhow::Result<()> {
+    let logs = query_logs_fixture();
+    db.append_new_logs(&logs).await?;
+
+    // page through all logs, newest first
+    let mut pages = Vec::new();
+    let mut query = LogQuery::new().order(Order::Descending).limit(3);
+    loop {
+        let page = db.query_logs(&query).await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        query = query.after(last.id);
+        pages.push(
+            page.iter()
+                .map(|log| log.log.message_id)
+                .collect::<Vec<_>>(),
+        );
+    }
+    assert_eq!(pages, [vec![4, 3, 2], vec![1]]);
+
+    let streamed = db
+        .stream_logs(&LogQuery::new().message_contains("DSL"))
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert_eq!(streamed.len(), 2);
+    assert_eq!(streamed[0].log, logs[1]);
+
+    db.close().await;
+    Ok(())
+}
+
+async fn search_logs(db: Database) -> anyhow::Result<()> {
+    let logs = [
+        Log {
+            message: "Anmeldung der NAS von 192.168.178.20 fehlgeschlagen".to_string(),
+            ..log!([1, 1, 1], 1, 1, repetition!())
+        },
+        Log {
+            message: "WLAN-Gerät angemeldet: nas, IP 192.168.178.20".to_string(),
+            ..log!([1, 1, 2], 2, 1, repetition!())
+        },
+        Log {
+            message: "NAS antwortet nicht, NAS neu gestartet".to_string(),
+            ..log!([1, 1, 3], 3, 1, repetition!())
+        },
+        Log {
+            message: "DSL ist verfügbar".to_string(),
+            ..log!([1, 1, 4], 4, 2, repetition!())
+        },
+    ];
+    db.append_new_logs(&logs).await?;
+
+    let hits = db.search_logs("NAS", .., 10).await?;
+    let mut ids = hits
+        .iter()
+        .map(|hit| hit.log.log.message_id)
+        .collect::<Vec<_>>();
+    // the log mentioning the NAS twice ranks best
+    assert_eq!(ids.first(), Some(&3));
+    ids.sort_unstable();
+    assert_eq!(ids, [1, 2, 3]);
+    assert!(hits.windows(2).all(|pair| pair[0].rank >= pair[1].rank));
+    assert!(hits[0].snippet.contains("**NAS**"), "{}", hits[0].snippet);
+
+    let hits = db.search_logs("nas 192.168.178.20", .., 10).await?;
+    let mut ids = hits
+        .iter()
+        .map(|hit| hit.log.log.message_id)
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    assert_eq!(ids, [1, 2]);
+
+    let hits = db
+        .search_logs(
+            "nas",
+            logs[1].datetime.to_utc()..logs[2].datetime.to_utc(),
+            10,
+        )
+        .await?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].log.log, logs[1]);
+
+    assert_eq!(db.search_logs("nas", .., 1).await?.len(), 1);
+    assert!(db.search_logs("drucker", .., 10).await?.is_empty());
+    assert!(db.search_logs(" ", .., 10).await?.is_empty());
+
+    db.close().await;
+    Ok(())
+}
+
+async fn prune(db: Database) -> anyhow::Result<()> {
+    let logs = [
+        log!([1, 1, 1], 1, 1, repetition!([1, 1, 0], 2)),
+        log!([1, 1, 2], 2, 1, repetition!()),
+        log!([1, 1, 3], 3, 1, repetition!()),
+    ];
+    db.append_new_logs(&logs).await?;
+    let after_logs = logs[2].datetime.to_utc() + chrono::Duration::hours(1);
+
+    for minute in 0..10 {
+        let datetime = logs[0].datetime.to_utc() + chrono::Duration::minutes(minute);
+        db.insert_ping(&db::Ping {
+            id: None,
+            datetime,
+            target: "192.168.178.1".to_string(),
+            duration_ms: Some(1),
+            ttl: Some(64),
+            bytes: Some(56),
+        })
+        .await?;
+        db.insert_request(&db::Request {
+            datetime,
+            ..Default::default()
+        })
+        .await?;
+    }
+
+    // only the rows before the cutoff are pruned
+    let cutoff = logs[0].datetime.to_utc() + chrono::Duration::minutes(4);
+    assert_eq!(db.prune(Table::Ping, cutoff).await?, 4);
+    assert_eq!(db.prune(Table::Ping, cutoff).await?, 0);
+    assert_eq!(db.prune(Table::Ping, after_logs).await?, 6);
+    assert_eq!(db.prune(Table::Requests, after_logs).await?, 10);
+    assert_eq!(db.prune(Table::Updates, after_logs).await?, 0);
+
+    db.close().await;
+    Ok(())
+}
+
+async fn prune_logs(db: Database) -> anyhow::Result<()> {
+    let logs = [
+        log!([1, 1, 1], 1, 1, repetition!([1, 1, 0], 2)),
+        log!([1, 1, 2], 2, 1, repetition!()),
+        log!([1, 1, 3], 3, 1, repetition!()),
+    ];
+    db.append_new_logs(&logs).await?;
+    let after_logs = logs[2].datetime.to_utc() + chrono::Duration::hours(1);
+
+    // the most recently appended log is kept, so appending continues after it
+    assert_eq!(db.prune(Table::Logs, after_logs).await?, 2);
+    assert_eq!(db.count_logs().await?, 1);
+    assert_eq!(db.select_tail_log().await?.as_ref(), Some(&logs[2]));
+    assert!(db.select_log_repetitions(1).await?.is_empty());
+    assert!(db.append_new_logs(&logs).await?.is_empty());
+
+    db.close().await;
+    Ok(())
+}
+
+/// Two minutes of pings, then nothing for a day, then another minute.
+///
+/// Returns the start of the first minute.
+async fn insert_pings_fixture(db: &Database) -> anyhow::Result<chrono::DateTime<chrono::Utc>> {
+    use chrono::TimeZone;
+
+    let start = chrono::Utc.with_ymd_and_hms(2024, 6, 28, 11, 0, 0).unwrap();
+    let offsets = [0, 10, 20, 70, 80, 24 * 60 * 60, 24 * 60 * 60 + 5];
+    for (i, seconds) in offsets.into_iter().enumerate() {
+        db.insert_ping(&db::Ping {
+            id: None,
+            datetime: start + chrono::Duration::seconds(seconds),
+            target: "192.168.178.1".to_string(),
+            duration_ms: (i != 1).then_some(10 + i as i64),
+            ttl: Some(64),
+            bytes: Some(56),
+        })
+        .await?;
+    }
+    Ok(start)
+}
+
+async fn rollup_pings(db: Database) -> anyhow::Result<()> {
+    let start = insert_pings_fixture(&db).await?;
+
+    // the hour of the first pings hasn't ended yet
+    let now = start + chrono::Duration::minutes(5);
+    assert_eq!(rollup(&db, Resolution::Minute, now).await?, 2);
+    assert_eq!(rollup(&db, Resolution::Hour, now).await?, 0);
+    assert_eq!(rollup(&db, Resolution::Minute, now).await?, 0);
+
+    let minutes = db.select_ping_rollups(Resolution::Minute, None, ..).await?;
+    assert_eq!(minutes.len(), 2);
+    assert_eq!(minutes[0].datetime, start);
+    assert_eq!((minutes[0].count, minutes[0].loss_count), (3, 1));
+    assert_eq!(minutes[1].count, 2);
+
+    // only the complete buckets after the latest rollup are added
+    let now = start + chrono::Duration::days(2);
+    assert_eq!(rollup(&db, Resolution::Minute, now).await?, 1);
+
+    db.close().await;
+    Ok(())
+}
+
+async fn rollup_pings_after_pause(db: Database) -> anyhow::Result<()> {
+    let start = insert_pings_fixture(&db).await?;
+
+    let now = start + chrono::Duration::days(2);
+    assert_eq!(rollup(&db, Resolution::Hour, now).await?, 2);
+    let hours = db
+        .select_ping_rollups(Resolution::Hour, Some("192.168.178.1"), start..)
+        .await?;
+    assert_eq!(
+        hours.iter().map(|hour| hour.count).collect::<Vec<_>>(),
+        [5, 2]
+    );
+    assert!(db
+        .select_ping_rollups(Resolution::Hour, Some("1.1.1.1"), ..)
+        .await?
+        .is_empty());
+
+    // rolling up again replaces the buckets instead of duplicating them
+    db.upsert_ping_rollups(Resolution::Hour, &hours).await?;
+    assert_eq!(
+        db.select_ping_rollups(Resolution::Hour, None, ..).await?,
+        hours
+    );
+
+    db.close().await;
+    Ok(())
+}
+
+async fn leadership(db: Database) -> anyhow::Result<()> {
+    // only Postgres is shared between processes, every other backend leads
+    let mut leadership = db.try_acquire_leadership(1).await?.expect("leader");
+    leadership.check().await?;
+    assert!(db.try_acquire_leadership(1).await?.is_some());
+
+    db.close().await;
+    Ok(())
+}
+
+async fn subscribe(db: Database) -> anyhow::Result<()> {
+    use futures_util::StreamExt;
+
+    let events = db.subscribe().await?;
+    let repeated = log!([1, 1, 2], 1, 1, repetition!([1, 1, 1], 2));
+    let repeated_again = log!([1, 1, 3], 1, 1, repetition!([1, 1, 1], 3));
+    db.append_new_logs(std::slice::from_ref(&repeated)).await?;
+    db.append_new_logs(std::slice::from_ref(&repeated_again))
+        .await?;
+    db.append_new_logs(&[]).await?;
+
+    let lost = db::Ping {
+        id: None,
+        datetime: repeated.datetime.to_utc(),
+        target: "192.168.178.1".to_string(),
+        duration_ms: None,
+        ttl: None,
+        bytes: None,
+    };
+    let minute = Resolution::Minute.bucket(lost.datetime);
+    let outage = crate::ping::rollup::summarize(&lost.target, minute, &[&lost, &lost]);
+    db.upsert_ping_rollups(Resolution::Minute, &[outage])
+        .await?;
+
+    let received = events.take(4).try_collect::<Vec<_>>().await?;
+    for event in received.iter() {
+        let json = serde_json::to_string(event)?;
+        assert_eq!(&serde_json::from_str::<Event>(&json)?, event);
+    }
+    assert_eq!(
+        received[..2],
+        [
+            Event::LogInserted {
+                log: repeated.clone(),
+                resynced: false,
+            },
+            Event::LogUpdated {
+                old: repeated,
+                new: repeated_again,
+            },
+        ]
+    );
+    assert!(matches!(&received[2], Event::Gap(gap) if gap.kind == "cleared"));
+    assert_eq!(
+        received[3],
+        Event::Outage {
+            target: lost.target,
+            datetime: minute,
+            count: 2,
+        }
+    );
+
+    db.close().await;
+    Ok(())
+}
+
+async fn poll_runs(db: Database) -> anyhow::Result<()> {
+    let first = log!([1, 1, 1], 1, 1, repetition!());
+    let second = log!([1, 1, 2], 2, 1, repetition!());
+    let repeated = log!([1, 1, 3], 2, 1, repetition!([1, 1, 2], 2));
+
+    let appended = db.append(&[first.clone(), second]).await?;
+    assert_eq!((appended.inserted, appended.updated), (2, 0));
+    assert!(!appended.gap && !appended.spooled);
+
+    let succeeded = db::PollRun {
+        fetched: 2,
+        clock_drift_ms: Some(-1500),
+        implausible: 1,
+        ..db::PollRun::start("host")
+    }
+    .succeed(&db.append(&[first, repeated]).await?);
+    assert_eq!((succeeded.inserted, succeeded.updated), (0, 1));
+
+    // the logs have been cleared, but not on purpose
+    let appended = db.append(&[]).await?;
+    assert!(appended.gap && appended.upserted.is_empty());
+
+    let err = anyhow::anyhow!("unexpected response").context(crate::api::LoginFailed);
+    let failed = db::PollRun::start("host").fail(Failure::of_fetch(&err), &err);
+    assert_eq!(failed.error.as_deref(), Some("login"));
+    assert_eq!(
+        failed.error_message.as_deref(),
+        Some("login failed: unexpected response")
+    );
+
+    db.insert_poll_run(&succeeded).await?;
+    db.insert_poll_run(&failed).await?;
+
+    // timestamps are saved with millisecond precision
+    let since = succeeded.datetime - chrono::Duration::seconds(1);
+    let until = failed.datetime + chrono::Duration::seconds(1);
+    let runs = db.select_poll_runs(since, until).await?;
+    assert_eq!(runs.len(), 2);
+    assert_eq!(
+        (runs[0].fetched, runs[0].updated, runs[0].error.as_deref()),
+        (2, 1, None)
+    );
+    assert_eq!(
+        (runs[0].clock_drift_ms, runs[0].implausible),
+        (Some(-1500), 1)
+    );
+    assert_eq!((runs[1].clock_drift_ms, runs[1].implausible), (None, 0));
+    assert_eq!(runs[1].error.as_deref(), Some("login"));
+    assert_eq!(runs[1].version, crate::poll::VERSION);
+    assert_eq!(runs[1].host, "host");
+
+    assert_eq!(db.prune(Table::PollRuns, until).await?, 2);
+    assert!(db.select_poll_runs(since, until).await?.is_empty());
+
+    db.close().await;
+    Ok(())
+}
+
+async fn clear(db: Database) -> anyhow::Result<()> {
+    let first = log!([1, 1, 1], 1, 1, repetition!());
+    let second = log!([1, 1, 2], 2, 1, repetition!());
+    let third = log!([1, 1, 3], 3, 1, repetition!());
+
+    // nothing to record before anything has been archived
+    db.insert_clear(0).await?;
+
+    let snapshot = [first, second];
+    db.append_new_logs(&snapshot).await?;
+    assert!(db.missing_logs(&snapshot).await?.is_empty());
+    // a log whose repetition changed is missing as well
+    let repeated = log!([1, 1, 3], 2, 1, repetition!([1, 1, 2], 2));
+    let repeated = std::slice::from_ref(&repeated);
+    assert_eq!(db.missing_logs(repeated).await?, repeated);
+
+    // the logs on the FRITZ!Box have been cleared on purpose
+    db.insert_clear(snapshot.len()).await?;
+    db.insert_clear(snapshot.len()).await?;
+    let appended = db.append(&[]).await?;
+    assert!(!appended.gap && appended.upserted.is_empty());
+    let appended = db.append(std::slice::from_ref(&third)).await?;
+    assert!(!appended.gap);
+    assert_eq!(appended.upserted, std::slice::from_ref(&third));
+
+    // but not again since the most recent log
+    let appended = db.append(&[]).await?;
+    assert!(appended.gap);
+    assert_eq!(db.select_latest_logs(0, 10).await?.first(), Some(&third));
+
+    db.close().await;
+    Ok(())
+}
+
+async fn query_requests(db: Database) -> anyhow::Result<()> {
+    let start = log!([1, 1, 1], 1, 1, repetition!()).datetime.to_utc();
+    let ok = |minute, duration_ms, response_bytes| db::Request {
+        datetime: start + chrono::Duration::minutes(minute),
+        name: "logs".to_string(),
+        method: "POST".to_string(),
+        duration_ms,
+        response_code: Some(200),
+        response_bytes: Some(response_bytes),
+        attempt: 1,
+        remote_addr: Some("192.168.178.1:443".to_string()),
+        ..Default::default()
+    };
+    let timeout = |minute, attempt| db::Request {
+        datetime: start + chrono::Duration::minutes(minute),
+        name: "logs".to_string(),
+        duration_ms: 30_000,
+        error_kind: Some("timeout".to_string()),
+        error_message: Some("send request: operation timed out".to_string()),
+        attempt,
+        ..Default::default()
+    };
+    let requests = [
+        ok(0, 100, 2000),
+        timeout(1, 1),
+        timeout(2, 2),
+        ok(3, 300, 4000),
+        db::Request {
+            name: "login-challenge".to_string(),
+            response_code: Some(503),
+            error_kind: Some("status".to_string()),
+            ..ok(4, 10, 0)
+        },
+    ];
+    for req in &requests {
+        db.insert_request(req).await?;
+    }
+
+    let failed = db
+        .query_requests(&RequestQuery::new().name("logs").failed_only())
+        .await?;
+    assert_eq!(
+        failed
+            .into_iter()
+            .map(|req| db::Request { id: None, ..req })
+            .collect::<Vec<_>>(),
+        [requests[1].clone(), requests[2].clone()]
+    );
+    let since = db
+        .query_requests(&RequestQuery::new().since(requests[3].datetime).limit(1))
+        .await?;
+    assert_eq!(since.len(), 1);
+    assert_eq!(since[0].response_bytes, Some(4000));
+
+    let stats = db.request_stats(&RequestQuery::new()).await?;
+    assert_eq!(stats.len(), 2);
+    let (login, logs) = (&stats[0], &stats[1]);
+    assert_eq!(
+        (login.name.as_str(), login.failed_count),
+        ("login-challenge", 1)
+    );
+    assert_eq!(login.response_codes.get(&503), Some(&1));
+    assert_eq!((logs.count, logs.failed_count, logs.retry_count), (4, 2, 1));
+    assert_eq!(logs.error_kinds.get("timeout"), Some(&2));
+    assert_eq!((logs.min_ms, logs.max_ms), (Some(100), Some(30_000)));
+    assert_eq!((logs.avg_bytes, logs.max_bytes), (Some(3000.0), Some(4000)));
+    assert_eq!(logs.remote_addrs.len(), 1);
+
+    db.close().await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn dry_run() -> anyhow::Result<()> {
+    let first = log!([1, 1, 1], 1, 1, repetition!());
+    let second = log!([1, 1, 2], 2, 1, repetition!());
+    let db = Database::open_dry_run(std::slice::from_ref(&first));
+
+    // logs are kept to tell which logs are new
+    assert!(db
+        .append_new_logs(std::slice::from_ref(&first))
+        .await?
+        .is_empty());
+    assert_eq!(
+        db.append_new_logs(&[first, second.clone()]).await?,
+        [second]
+    );
+
+    // everything else is only logged
+    let start = insert_pings_fixture(&db).await?;
+    let end = start + chrono::Duration::days(2);
+    assert!(db.select_pings(start, end).await?.is_empty());
+    db.insert_request(&db::Request {
+        datetime: start,
+        name: "logs".to_string(),
+        ..Default::default()
+    })
+    .await?;
+    assert!(db.query_requests(&RequestQuery::new()).await?.is_empty());
+    db.insert_poll_run(&db::PollRun::start("host")).await?;
+    assert!(db
+        .select_poll_runs(chrono::DateTime::<chrono::Utc>::MIN_UTC, chrono::Utc::now())
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn convert_time_series() -> anyhow::Result<()> {
+    use chrono::{Datelike, Months};
+
+    let Some(url) = create_postgres("convert_time_series").await? else {
+        return Ok(());
+    };
+    let db = Database::open(&url).await?;
+    let start = insert_pings_fixture(&db).await?;
+    // an index that isn't created by the migrations
+    let mut conn = <sqlx::PgConnection as sqlx::Connection>::connect(&url).await?;
+    sqlx::query(r#"CREATE INDEX "ping_target_idx" ON "ping" ("target", "datetime")"#)
+        .execute(&mut conn)
+        .await?;
+
+    let mode = db::TimeSeriesMode::Partitioned;
+    assert_eq!(db.convert_time_series(mode).await?, mode);
+    assert_eq!(db.convert_time_series(mode).await?, mode);
+
+    // every index is recreated on the partitioned table
+    let indexes: Vec<String> = sqlx::query_scalar(
+        r#"SELECT "indexname" FROM "pg_indexes" WHERE "tablename" = 'ping' ORDER BY "indexname""#,
+    )
+    .fetch_all(&mut conn)
+    .await?;
+    assert_eq!(
+        indexes,
+        ["ping_datetime_idx", "ping_pkey", "ping_target_idx"]
+    );
+    drop(conn);
+
+    // rows from before the conversion are still there
+    let end = start + chrono::Duration::days(2);
+    assert_eq!(db.select_pings(start, end).await?.len(), 7);
+    assert_eq!(rollup(&db, Resolution::Minute, end).await?, 3);
+    db.close().await;
+
+    // the default partition already contains a row of next month, so its
+    // partition is skipped, but the months after it are still created
+    let table_exists = |month: chrono::NaiveDate| {
+        let url = url.clone();
+        async move {
+            let mut conn = <sqlx::PgConnection as sqlx::Connection>::connect(&url).await?;
+            let name = format!("ping_{:04}_{:02}", month.year(), month.month());
+            sqlx::query_scalar::<_, bool>("SELECT to_regclass($1) IS NOT NULL")
+                .bind(name)
+                .fetch_one(&mut conn)
+                .await
+                .map_err(anyhow::Error::from)
+        }
+    };
+    let this_month = chrono::Utc::now().date_naive().with_day(1).unwrap();
+    let next_month = this_month + Months::new(1);
+    let month_after = this_month + Months::new(2);
+    assert!(table_exists(next_month).await? && table_exists(month_after).await?);
+
+    let next_month_ping = next_month.and_hms_opt(12, 0, 0).unwrap().and_utc();
+    let mut conn = <sqlx::PgConnection as sqlx::Connection>::connect(&url).await?;
+    for month in [next_month, month_after] {
+        sqlx::query(&format!(
+            r#"DROP TABLE "ping_{:04}_{:02}""#,
+            month.year(),
+            month.month()
+        ))
+        .execute(&mut conn)
+        .await?;
+    }
+    sqlx::query(r#"INSERT INTO "ping" ("datetime", "target") VALUES ($1, '192.168.178.1')"#)
+        .bind(next_month_ping)
+        .execute(&mut conn)
+        .await?;
+    drop(conn);
+
+    let db = Database::open(&url).await?;
+    assert!(!table_exists(next_month).await?);
+    assert!(table_exists(month_after).await?);
+    let pings = db
+        .select_pings(
+            next_month_ping,
+            month_after.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+        )
+        .await?;
+    assert_eq!(pings.len(), 1);
+
+    db.close().await;
+    Ok(())
+}
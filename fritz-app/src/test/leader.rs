@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use crate::db::{self, Database, Spool};
+use crate::leader::{self, lock_key, LeaderOptions};
+use crate::test::database::create_postgres;
+use crate::test::spool::{ping, test_dir};
+
+/// Leadership whose connection is gone
+struct Gone;
+
+#[async_trait::async_trait]
+impl db::Leadership for Gone {
+    async fn check(&mut self) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!("connection is gone"))
+    }
+}
+
+#[test]
+fn key() {
+    // instances of different versions must agree on the key
+    assert_eq!(lock_key("192.168.178.1"), 1_140_474_358_065_106_102);
+    assert_ne!(lock_key("fritz.box"), lock_key("192.168.178.1"));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn postgres_lock() -> anyhow::Result<()> {
+    let Some(url) = create_postgres("leader_lock").await? else {
+        return Ok(());
+    };
+    let first = Database::open(&url).await?;
+    let second = Database::open(&url).await?;
+
+    let mut leadership = first.try_acquire_leadership(1).await?.expect("leader");
+    leadership.check().await?;
+    assert!(second.try_acquire_leadership(1).await?.is_none());
+    // other keys are independent
+    assert!(second.try_acquire_leadership(2).await?.is_some());
+
+    // the lock is released once the connection holding it is closed
+    drop(leadership);
+    let mut acquired = None;
+    for _ in 0..50 {
+        acquired = second.try_acquire_leadership(1).await?;
+        if acquired.is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(acquired.is_some());
+
+    first.close().await;
+    second.close().await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn lead_through_outage() -> anyhow::Result<()> {
+    let Some(url) = create_postgres("leader_outage").await? else {
+        return Ok(());
+    };
+    let dir = test_dir("lead-through-outage");
+    let opts = LeaderOptions::new("192.168.178.1", Duration::from_millis(10));
+    let timeout = Duration::from_millis(200);
+
+    // no other instance can take over while the database is down, so this one
+    // keeps leading and spooling, a closed pool fails like an unreachable database
+    let db = Database::open(&url).await?.with_spool(Spool::open(&dir)?);
+    db.clone().close().await;
+    let mut leadership: Box<dyn db::Leadership> = Box::new(Gone);
+    let lost = tokio::time::timeout(timeout, leader::lost(&db, &mut leadership, &opts)).await;
+    assert!(lost.is_err());
+    db.insert_ping(&ping(0)).await?;
+    db.insert_ping(&ping(1)).await?;
+
+    // once it's back, the lock is reacquired and the spooled pings are replayed
+    let db = Database::open(&url).await?.with_spool(Spool::open(&dir)?);
+    let lost = tokio::time::timeout(timeout, leader::lost(&db, &mut leadership, &opts)).await;
+    assert!(lost.is_err());
+    leadership.check().await?;
+    assert_eq!(db.replay_spool().await?, 2);
+    let pings = db.select_pings(ping(0).datetime, ping(2).datetime).await?;
+    assert_eq!(pings.len(), 2);
+
+    // unless another instance has taken over in the meantime
+    let standby = Database::open(&url).await?;
+    let mut gone: Box<dyn db::Leadership> = Box::new(Gone);
+    let lost = tokio::time::timeout(timeout, leader::lost(&standby, &mut gone, &opts)).await;
+    assert!(lost.is_ok());
+
+    drop(leadership);
+    db.close().await;
+    standby.close().await;
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
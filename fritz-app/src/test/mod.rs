@@ -1 +1,60 @@
+macro_rules! repetition {
+    () => {
+        None
+    };
+    ([$hour:expr, $minute:expr, $second:expr], $count:expr) => {
+        Some(crate::fritz::Repetition {
+            datetime: chrono::TimeZone::with_ymd_and_hms(
+                &chrono::Local,
+                2023,
+                1,
+                1,
+                $hour,
+                $minute,
+                $second,
+            )
+            .single()
+            .unwrap(),
+            count: $count,
+            datetime_resolution: None,
+        })
+    };
+}
+
+macro_rules! log {
+    ([$hour:expr, $minute:expr, $second:expr], $message_id:expr, $category_id:expr, $($repetition:tt)+) => {
+        crate::fritz::Log {
+            datetime: chrono::TimeZone::with_ymd_and_hms(
+                &chrono::Local,
+                2023,
+                1,
+                1,
+                $hour,
+                $minute,
+                $second,
+            )
+            .single()
+            .unwrap(),
+            message: "message".to_string(),
+            message_id: $message_id,
+            category_id: $category_id,
+            repetition: $($repetition)+,
+            datetime_resolution: None,
+        }
+    };
+}
+
+mod clock;
+mod database;
 mod insert_new;
+mod leader;
+mod poll;
+mod reconcile;
+mod resync;
+mod retention;
+mod rollup;
+#[cfg(feature = "simulator")]
+mod simulator;
+mod spool;
+mod timezone;
+mod transport;
@@ -0,0 +1,24 @@
+use anyhow::Context;
+
+use crate::api::{LoginFailed, StatusError};
+use crate::poll::Failure;
+
+#[test]
+fn classify_fetch() {
+    let rejected = anyhow::anyhow!("trying to login with invalid user").context(LoginFailed);
+    assert_eq!(Failure::of_fetch(&rejected), Failure::Login);
+
+    // the context of the login stays visible through later contexts
+    let fetch = Err::<(), _>(rejected).context("fetch logs").unwrap_err();
+    assert_eq!(Failure::of_fetch(&fetch), Failure::Login);
+
+    let parse = serde_json::from_str::<serde_json::Value>("<html>")
+        .context("parse json")
+        .unwrap_err();
+    assert_eq!(Failure::of_fetch(&parse), Failure::Response);
+
+    let status = Err::<(), _>(StatusError { status: 503 })
+        .context("response status non 2XX")
+        .unwrap_err();
+    assert_eq!(Failure::of_fetch(&status), Failure::Status);
+}
@@ -0,0 +1,344 @@
+use proptest::prelude::*;
+
+use crate::fritz::{reconcile, GapKind, Log, Op, Repetition};
+
+fn inserts(logs: &[Log]) -> Vec<Op> {
+    logs.iter().cloned().map(Op::Insert).collect()
+}
+
+#[test]
+fn empty_database() {
+    let snapshot = [
+        log!([1, 1, 1], 1, 1, repetition!()),
+        log!([1, 1, 2], 2, 1, repetition!([1, 1, 1], 2)),
+    ];
+
+    assert_eq!(reconcile(None, &snapshot).unwrap(), inserts(&snapshot));
+    assert_eq!(reconcile(None, &[]).unwrap(), []);
+}
+
+#[test]
+fn all_old() {
+    let tail = log!([1, 1, 5], 1, 1, repetition!());
+    let snapshot = [
+        log!([1, 1, 1], 1, 1, repetition!()),
+        log!([1, 1, 2], 2, 1, repetition!()),
+    ];
+
+    assert_eq!(reconcile(Some(&tail), &snapshot).unwrap(), []);
+}
+
+#[test]
+fn wrapped() {
+    let tail = log!([1, 1, 1], 1, 1, repetition!());
+    let snapshot = [
+        log!([1, 1, 3], 2, 1, repetition!([1, 1, 2], 2)),
+        log!([1, 1, 4], 3, 1, repetition!()),
+    ];
+
+    let mut expected = vec![Op::Gap {
+        kind: GapKind::Wrapped,
+        after: tail.datetime,
+        before: Some(snapshot[0].earliest_datetime()),
+    }];
+    expected.extend(inserts(&snapshot));
+
+    assert_eq!(reconcile(Some(&tail), &snapshot).unwrap(), expected);
+}
+
+#[test]
+fn cleared() {
+    let tail = log!([1, 1, 3], 3, 1, repetition!());
+
+    assert_eq!(
+        reconcile(Some(&tail), &[]).unwrap(),
+        [Op::Gap {
+            kind: GapKind::Cleared,
+            after: tail.datetime,
+            before: None,
+        }]
+    );
+}
+
+#[test]
+fn some_new() {
+    let tail = log!([1, 1, 2], 2, 1, repetition!());
+    let snapshot = [
+        log!([1, 1, 1], 1, 1, repetition!()),
+        log!([1, 1, 2], 2, 1, repetition!()),
+        log!([1, 1, 3], 3, 1, repetition!()),
+        log!([1, 1, 4], 4, 1, repetition!()),
+    ];
+
+    assert_eq!(
+        reconcile(Some(&tail), &snapshot).unwrap(),
+        inserts(&snapshot[2..])
+    );
+}
+
+#[test]
+fn nothing_new() {
+    let tail = log!([1, 1, 2], 2, 1, repetition!([1, 1, 1], 2));
+    let snapshot = [
+        log!([1, 1, 1], 1, 1, repetition!()),
+        log!([1, 1, 2], 2, 1, repetition!([1, 1, 1], 2)),
+    ];
+
+    assert_eq!(reconcile(Some(&tail), &snapshot).unwrap(), []);
+}
+
+#[test]
+fn repetition_added() {
+    let tail = log!([1, 1, 1], 1, 1, repetition!());
+    let snapshot = [
+        log!([1, 1, 3], 1, 1, repetition!([1, 1, 1], 2)),
+        log!([1, 1, 4], 2, 2, repetition!()),
+    ];
+
+    assert_eq!(
+        reconcile(Some(&tail), &snapshot).unwrap(),
+        [
+            Op::UpdateRepetition {
+                old: tail,
+                new: snapshot[0].clone(),
+            },
+            Op::Insert(snapshot[1].clone()),
+        ]
+    );
+}
+
+#[test]
+fn repetition_updated() {
+    let tail = log!([1, 1, 3], 1, 1, repetition!([1, 1, 1], 5));
+    let snapshot = [log!([1, 1, 9], 1, 1, repetition!([1, 1, 1], 6))];
+
+    assert_eq!(
+        reconcile(Some(&tail), &snapshot).unwrap(),
+        [Op::UpdateRepetition {
+            old: tail,
+            new: snapshot[0].clone(),
+        }]
+    );
+}
+
+/// The FRITZ!Box sometimes updates the repetition of a log after a newer log
+/// has been logged, so the snapshot isn't strictly sorted by `datetime`.
+#[test]
+fn repetition_updated_out_of_order() {
+    let tail = log!([1, 1, 2], 2, 1, repetition!());
+    let snapshot = [
+        log!([1, 1, 5], 1, 1, repetition!([1, 1, 1], 3)),
+        log!([1, 1, 2], 2, 1, repetition!()),
+        log!([1, 1, 6], 3, 1, repetition!()),
+    ];
+
+    assert_eq!(
+        reconcile(Some(&tail), &snapshot).unwrap(),
+        inserts(&snapshot[2..])
+    );
+}
+
+#[test]
+fn replaced() {
+    let tail = log!([1, 1, 3], 3, 1, repetition!());
+    let snapshot = [
+        log!([1, 1, 1], 1, 1, repetition!()),
+        log!([1, 1, 4], 4, 1, repetition!([1, 1, 2], 2)),
+        log!([1, 1, 5], 5, 1, repetition!()),
+    ];
+
+    assert_eq!(
+        reconcile(Some(&tail), &snapshot).unwrap(),
+        [
+            Op::Gap {
+                kind: GapKind::Replaced,
+                after: tail.datetime,
+                before: Some(snapshot[1].earliest_datetime()),
+            },
+            Op::Insert(snapshot[1].clone()),
+            Op::Insert(snapshot[2].clone()),
+        ]
+    );
+}
+
+#[test]
+fn replaced_nothing_new() {
+    let tail = log!([1, 1, 3], 3, 1, repetition!());
+    let snapshot = [
+        log!([1, 1, 1], 1, 1, repetition!()),
+        log!([1, 1, 3], 4, 1, repetition!()),
+    ];
+
+    assert_eq!(
+        reconcile(Some(&tail), &snapshot).unwrap(),
+        [Op::Gap {
+            kind: GapKind::Replaced,
+            after: tail.datetime,
+            before: None,
+        }]
+    );
+}
+
+#[test]
+fn unsorted() {
+    let snapshot = [
+        log!([1, 1, 2], 2, 1, repetition!()),
+        log!([1, 1, 1], 1, 1, repetition!()),
+    ];
+
+    assert!(reconcile(None, &snapshot).is_err());
+}
+
+/// Mimics the event log of the FRITZ!Box.
+///
+/// Consecutive events with the same message are folded into a single entry
+/// and only the newest `capacity` entries are kept.
+struct LogBuffer {
+    /// All entries ever logged, from old to new
+    entries: Vec<Log>,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    fn new(capacity: usize) -> LogBuffer {
+        LogBuffer {
+            entries: Vec::new(),
+            capacity,
+        }
+    }
+
+    fn push(&mut self, second: u32, message_id: i64) {
+        let log = log!(
+            [second / 3600, second / 60 % 60, second % 60],
+            message_id,
+            1,
+            repetition!()
+        );
+
+        match self.entries.last_mut() {
+            Some(last) if last.message_id == message_id => {
+                last.repetition = Some(Repetition {
+                    datetime: last.earliest_datetime(),
+                    count: last.repetition.as_ref().map_or(1, |rep| rep.count) + 1,
+                    datetime_resolution: None,
+                });
+                last.datetime = log.datetime;
+            }
+            _ => self.entries.push(log),
+        }
+    }
+
+    fn snapshot(&self) -> &[Log] {
+        &self.entries[self.entries.len().saturating_sub(self.capacity)..]
+    }
+}
+
+/// Apply `ops` the way the database would.
+fn apply(db: &mut Vec<Log>, ops: &[Op]) {
+    for op in ops {
+        match op {
+            Op::Insert(log) => db.push(log.clone()),
+            Op::UpdateRepetition { old, new } => {
+                let tail = db.last_mut().unwrap();
+                assert_eq!(tail, old);
+                *tail = new.clone();
+            }
+            Op::Gap { .. } => {}
+        }
+    }
+}
+
+/// Message id, seconds since the previous event and whether to poll afterwards.
+fn events() -> impl Strategy<Value = Vec<(i64, u32, bool)>> {
+    prop::collection::vec((1i64..4, 1u32..4, prop::bool::weighted(0.3)), 1..100)
+}
+
+proptest! {
+    /// Without losing entries, the database mirrors the FRITZ!Box.
+    #[test]
+    fn mirrors_unbounded_buffer(events in events()) {
+        let mut buffer = LogBuffer::new(usize::MAX);
+        let mut db = Vec::new();
+        let mut second = 0;
+
+        for (i, (message_id, delay, poll)) in events.iter().copied().enumerate() {
+            second += delay;
+            buffer.push(second, message_id);
+            if !poll && i + 1 != events.len() {
+                continue;
+            }
+
+            let ops = reconcile(db.last(), buffer.snapshot()).unwrap();
+            let has_gap = ops.iter().any(|op| matches!(op, Op::Gap { .. }));
+            prop_assert!(!has_gap);
+            apply(&mut db, &ops);
+            prop_assert_eq!(&db, &buffer.entries);
+        }
+    }
+
+    /// Entries are never inserted twice and a gap is reported if the most
+    /// recent log in the database got pushed out of the FRITZ!Box.
+    #[test]
+    fn bounded_buffer(events in events(), capacity in 1usize..8) {
+        let mut buffer = LogBuffer::new(capacity);
+        let mut db: Vec<Log> = Vec::new();
+        let mut second = 0;
+        let mut wrapped = false;
+
+        for (i, (message_id, delay, poll)) in events.iter().copied().enumerate() {
+            second += delay;
+            buffer.push(second, message_id);
+            if !poll && i + 1 != events.len() {
+                continue;
+            }
+
+            let snapshot = buffer.snapshot();
+            let tail = db.last().cloned();
+            let ops = reconcile(tail.as_ref(), snapshot).unwrap();
+
+            let tail_in_snapshot = tail.as_ref().is_none_or(|tail| {
+                snapshot.iter().any(|log| {
+                    log.earliest_datetime() == tail.earliest_datetime()
+                        && log.message_id == tail.message_id
+                })
+            });
+            let gaps = ops
+                .iter()
+                .filter_map(|op| match op {
+                    Op::Gap { kind, .. } => Some(*kind),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            if tail_in_snapshot {
+                prop_assert_eq!(gaps, []);
+            } else {
+                prop_assert_eq!(gaps, [GapKind::Wrapped]);
+                wrapped = true;
+            }
+
+            for op in ops.iter() {
+                if let (Op::Insert(log), Some(tail)) = (op, tail.as_ref()) {
+                    prop_assert!(log.datetime > tail.datetime);
+                }
+            }
+
+            apply(&mut db, &ops);
+
+            // applying the same snapshot again doesn't change anything
+            prop_assert_eq!(reconcile(db.last(), snapshot).unwrap(), []);
+
+            // every entry is stored at most once
+            for (i, lhs) in db.iter().enumerate() {
+                prop_assert!(!db[i + 1..]
+                    .iter()
+                    .any(|rhs| lhs.earliest_datetime() == rhs.earliest_datetime()));
+            }
+
+            // until the first entry is lost, the database mirrors the FRITZ!Box
+            if !wrapped {
+                let start = buffer.entries.len() - db.len();
+                prop_assert_eq!(&db, &buffer.entries[start..]);
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::fritz::{resync, Resync};
+
+#[test]
+fn empty_database() {
+    let snapshot = [log!([1, 1, 1], 1, 1, repetition!())];
+
+    assert!(resync(None, &[], &snapshot).is_empty());
+}
+
+#[test]
+fn in_sync() {
+    let db = [
+        log!([1, 1, 1], 1, 1, repetition!()),
+        log!([1, 1, 3], 2, 1, repetition!([1, 1, 2], 2)),
+    ];
+
+    assert!(resync(db.last(), &db, &db).is_empty());
+}
+
+#[test]
+fn missing() {
+    let snapshot = [
+        log!([1, 1, 1], 1, 1, repetition!()),
+        log!([1, 1, 2], 2, 1, repetition!()),
+        log!([1, 1, 3], 3, 1, repetition!()),
+    ];
+    let db = [snapshot[0].clone(), snapshot[2].clone()];
+
+    assert_eq!(
+        resync(db.last(), &db, &snapshot),
+        Resync {
+            missing: vec![snapshot[1].clone()],
+            ..Resync::default()
+        }
+    );
+}
+
+#[test]
+fn diverged() {
+    let snapshot = [
+        log!([1, 1, 5], 1, 1, repetition!([1, 1, 1], 3)),
+        log!([1, 1, 6], 2, 1, repetition!()),
+    ];
+    let db = [
+        log!([1, 1, 2], 1, 1, repetition!([1, 1, 1], 2)),
+        snapshot[1].clone(),
+    ];
+
+    assert_eq!(
+        resync(db.last(), &db, &snapshot),
+        Resync {
+            diverged: vec![(db[0].clone(), snapshot[0].clone())],
+            ..Resync::default()
+        }
+    );
+}
+
+#[test]
+fn orphaned() {
+    let snapshot = [
+        log!([1, 1, 1], 1, 1, repetition!()),
+        log!([1, 1, 3], 3, 1, repetition!()),
+    ];
+    let db = [
+        snapshot[0].clone(),
+        log!([1, 1, 2], 2, 1, repetition!()),
+        snapshot[1].clone(),
+    ];
+
+    assert_eq!(
+        resync(db.last(), &db, &snapshot),
+        Resync {
+            orphaned: vec![db[1].clone()],
+            ..Resync::default()
+        }
+    );
+}
+
+/// Logs that have been pushed out of the FRITZ!Box aren't orphaned, even if
+/// their repetition overlaps with the oldest log on the FRITZ!Box.
+#[test]
+fn pushed_out() {
+    let snapshot = [
+        log!([1, 1, 2], 2, 1, repetition!()),
+        log!([1, 1, 4], 3, 1, repetition!()),
+    ];
+    let db = [
+        log!([1, 1, 2], 1, 1, repetition!([1, 1, 1], 2)),
+        snapshot[0].clone(),
+        snapshot[1].clone(),
+    ];
+
+    assert!(resync(db.last(), &db, &snapshot).is_empty());
+}
+
+/// Logs newer than the most recent log in the database are left to `reconcile`.
+#[test]
+fn ignores_new() {
+    let snapshot = [
+        log!([1, 1, 1], 1, 1, repetition!()),
+        log!([1, 1, 2], 2, 1, repetition!()),
+        log!([1, 1, 3], 3, 1, repetition!()),
+    ];
+    let db = [snapshot[0].clone()];
+
+    assert!(resync(db.last(), &db, &snapshot).is_empty());
+
+    // the most recent log in the database is gone, e.g. after a factory reset
+    let db = [log!([1, 1, 2], 4, 1, repetition!())];
+    assert_eq!(
+        resync(db.last(), &db, &snapshot),
+        Resync {
+            missing: snapshot[..2].to_vec(),
+            orphaned: db.to_vec(),
+            ..Resync::default()
+        }
+    );
+}
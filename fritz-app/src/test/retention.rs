@@ -0,0 +1,29 @@
+use crate::db::Table;
+use crate::retention::{parse_policies, RetentionPolicy};
+
+#[test]
+fn parse() {
+    assert_eq!(
+        parse_policies("ping=30, requests=90,").unwrap(),
+        [
+            RetentionPolicy {
+                table: Table::Ping,
+                max_age: chrono::Duration::days(30),
+            },
+            RetentionPolicy {
+                table: Table::Requests,
+                max_age: chrono::Duration::days(90),
+            },
+        ]
+    );
+    assert!(parse_policies("").unwrap().is_empty());
+}
+
+#[test]
+fn parse_invalid() {
+    assert!(parse_policies("ping").is_err());
+    assert!(parse_policies("pings=30").is_err());
+    assert!(parse_policies("ping=-1").is_err());
+    assert!(parse_policies("ping=0").is_err());
+    assert!(parse_policies("ping=30,ping=90").is_err());
+}
@@ -0,0 +1,101 @@
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::db::{self, Resolution};
+use crate::ping::rollup::{aggregate, summarize};
+
+fn ping(datetime: DateTime<Utc>, target: &str, duration_ms: Option<i64>) -> db::Ping {
+    db::Ping {
+        id: None,
+        datetime,
+        target: target.to_string(),
+        duration_ms,
+        ttl: duration_ms.map(|_| 64),
+        bytes: duration_ms.map(|_| 56),
+    }
+}
+
+#[test]
+fn bucket() {
+    let datetime = Utc.with_ymd_and_hms(2024, 6, 28, 11, 48, 32).unwrap()
+        + chrono::Duration::milliseconds(250);
+    assert_eq!(
+        Resolution::Minute.bucket(datetime),
+        Utc.with_ymd_and_hms(2024, 6, 28, 11, 48, 0).unwrap()
+    );
+    assert_eq!(
+        Resolution::Hour.bucket(datetime),
+        Utc.with_ymd_and_hms(2024, 6, 28, 11, 0, 0).unwrap()
+    );
+}
+
+#[test]
+fn summarize_latencies() {
+    let start = Utc.with_ymd_and_hms(2024, 6, 28, 11, 48, 0).unwrap();
+    let pings = [Some(10), None, Some(30), Some(20), Some(40), None]
+        .into_iter()
+        .enumerate()
+        .map(|(i, duration_ms)| {
+            ping(
+                start + chrono::Duration::seconds(i as i64),
+                "1.1.1.1",
+                duration_ms,
+            )
+        })
+        .collect::<Vec<_>>();
+    let rollup = summarize("1.1.1.1", start, &pings.iter().collect::<Vec<_>>());
+
+    assert_eq!(rollup.count, 6);
+    assert_eq!(rollup.loss_count, 2);
+    assert!((rollup.loss_ratio - 2.0 / 6.0).abs() < 1e-9);
+    assert_eq!(rollup.min_ms, Some(10));
+    assert_eq!(rollup.avg_ms, Some(25.0));
+    assert_eq!(rollup.max_ms, Some(40));
+    assert_eq!(rollup.p50_ms, Some(25.0));
+    assert!((rollup.p95_ms.unwrap() - 38.5).abs() < 1e-9);
+    assert!((rollup.p99_ms.unwrap() - 39.7).abs() < 1e-9);
+    // |30 - 10| + |20 - 30| + |40 - 20| over 3 pairs
+    assert!((rollup.jitter_ms.unwrap() - 50.0 / 3.0).abs() < 1e-9);
+}
+
+#[test]
+fn summarize_all_lost() {
+    let start = Utc.with_ymd_and_hms(2024, 6, 28, 11, 48, 0).unwrap();
+    let pings = [ping(start, "1.1.1.1", None), ping(start, "1.1.1.1", None)];
+    let rollup = summarize("1.1.1.1", start, &pings.iter().collect::<Vec<_>>());
+
+    assert_eq!((rollup.count, rollup.loss_count), (2, 2));
+    assert_eq!(rollup.loss_ratio, 1.0);
+    assert_eq!(rollup.min_ms, None);
+    assert_eq!(rollup.avg_ms, None);
+    assert_eq!(rollup.p99_ms, None);
+    assert_eq!(rollup.jitter_ms, None);
+}
+
+#[test]
+fn aggregate_by_target_and_bucket() {
+    let start = Utc.with_ymd_and_hms(2024, 6, 28, 11, 48, 0).unwrap();
+    let pings = [
+        ping(start, "1.1.1.1", Some(10)),
+        ping(start, "8.8.8.8", Some(20)),
+        ping(start + chrono::Duration::seconds(59), "1.1.1.1", Some(30)),
+        ping(start + chrono::Duration::seconds(60), "1.1.1.1", Some(40)),
+    ];
+
+    let rollups = aggregate(Resolution::Minute, &pings);
+    let buckets = rollups
+        .iter()
+        .map(|rollup| (rollup.datetime, rollup.target.as_str(), rollup.count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        buckets,
+        [
+            (start, "1.1.1.1", 2),
+            (start, "8.8.8.8", 1),
+            (start + chrono::Duration::seconds(60), "1.1.1.1", 1),
+        ]
+    );
+
+    let rollups = aggregate(Resolution::Hour, &pings);
+    assert_eq!(rollups.len(), 2);
+    assert_eq!(rollups[0].count, 3);
+}
@@ -0,0 +1,179 @@
+use std::net::SocketAddr;
+
+use chrono::{FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+use crate::api::{Client, LoginFailed};
+use crate::fritz::{BoxTimezone, TimezoneSetting};
+use crate::simulator::{Config, Server, Simulator};
+
+fn datetime(hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2023, 1, 1)
+        .unwrap()
+        .and_hms_opt(hour, minute, second)
+        .unwrap()
+}
+
+fn config(capacity: usize) -> Config {
+    Config {
+        rounds_1: 1000,
+        rounds_2: 100,
+        capacity,
+        log_logins: false,
+        ..Config::default()
+    }
+}
+
+async fn start(config: Config) -> (Simulator, Server) {
+    let simulator = Simulator::new(config).unwrap();
+    let server = simulator
+        .serve(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    (simulator, server)
+}
+
+fn new_client(simulator: &Simulator, server: &Server, password: &str) -> Client {
+    Client::new(
+        Some(&server.domain()),
+        Some("fritz3713"),
+        Some(password),
+        Some(simulator.cert_pem().as_bytes()),
+        None,
+    )
+    .unwrap()
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn session() {
+    let (simulator, server) = start(config(400)).await;
+    simulator.push_log(datetime(1, 1, 1), "first", 1, 1);
+    simulator.push_log(datetime(1, 1, 2), "second", 2, 1);
+    simulator.push_log(datetime(1, 1, 3), "second", 2, 1);
+    simulator.push_log(datetime(1, 1, 4), "second", 2, 1);
+
+    let client = new_client(&simulator, &server, "password");
+
+    let logs = client.logs(None).await.unwrap();
+    assert_eq!(logs.len(), 2);
+    assert_eq!(logs[0].message, "second");
+    assert_eq!(logs[0].repetition.as_ref().map(|r| r.count), Some(3));
+    assert_eq!(logs[0].earliest_datetime().naive_local(), datetime(1, 1, 2));
+    assert_eq!(logs[1].message_id, 1);
+
+    let certificate = client.certificate().await.unwrap();
+    assert_eq!(certificate, simulator.cert_pem());
+
+    let cleared = client.clear_logs().await.unwrap();
+    assert_eq!(cleared["data"]["log"], serde_json::json!([]));
+    assert!(client.logs(None).await.unwrap().is_empty());
+
+    client.logout().await.unwrap();
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn expired_session() {
+    let (simulator, server) = start(config(400)).await;
+    let client = new_client(&simulator, &server, "password");
+
+    let session_id = client.login().await.unwrap();
+    simulator.expire_sessions();
+
+    // logs in again
+    assert_ne!(
+        client.check_or_renew_session_id().await.unwrap(),
+        session_id
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn blocked_after_failed_login() {
+    let (simulator, server) = start(config(400)).await;
+
+    let err = new_client(&simulator, &server, "wrong")
+        .logs(None)
+        .await
+        .unwrap_err();
+    assert!(err.downcast_ref::<LoginFailed>().is_some());
+    assert!(simulator.block_time() > 0);
+
+    // even the correct password is rejected while logins are blocked
+    let client = new_client(&simulator, &server, "password");
+    assert!(client.login().await.is_err());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn append_wrapped_buffer() {
+    let (simulator, server) = start(config(3)).await;
+    let client = new_client(&simulator, &server, "password");
+    let db = crate::db::Database::open_in_memory();
+
+    simulator.push_log(datetime(1, 1, 1), "first", 1, 1);
+    simulator.push_log(datetime(1, 1, 2), "second", 2, 1);
+
+    let mut logs = client.logs(None).await.unwrap();
+    logs.reverse();
+    let appended = db.append(&logs).await.unwrap();
+    assert_eq!(appended.inserted, 2);
+    assert!(!appended.gap);
+
+    // the ring buffer rolls over, dropping everything that's in the database
+    for second in 3..7 {
+        simulator.push_log(datetime(1, 1, second), "message", i64::from(second), 1);
+    }
+
+    let mut logs = client.logs(None).await.unwrap();
+    assert_eq!(logs.len(), 3);
+    logs.reverse();
+    let appended = db.append(&logs).await.unwrap();
+    assert_eq!(appended.inserted, 3);
+    assert!(appended.gap);
+
+    assert_eq!(db.count_logs().await.unwrap(), 5);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn named_timezone() {
+    let (simulator, server) = start(Config {
+        timezone: chrono_tz::America::New_York,
+        ..config(400)
+    })
+    .await;
+    simulator.push_log(datetime(1, 1, 1), "first", 1, 1);
+
+    let timezone = BoxTimezone::Named(chrono_tz::America::New_York);
+    let client =
+        new_client(&simulator, &server, "password").with_timezone(TimezoneSetting::Fixed(timezone));
+
+    let logs = client.logs(None).await.unwrap();
+    assert_eq!(
+        logs[0].datetime,
+        Utc.with_ymd_and_hms(2023, 1, 1, 6, 1, 1).unwrap()
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn detect_timezone() {
+    let (simulator, server) = start(Config {
+        timezone: chrono_tz::Asia::Kolkata,
+        log_logins: true,
+        ..config(400)
+    })
+    .await;
+
+    let client = new_client(&simulator, &server, "password").with_timezone(TimezoneSetting::Detect);
+    assert_eq!(client.timezone(), BoxTimezone::Host);
+
+    // detected from the log of the login
+    let logs = client.logs(None).await.unwrap();
+    let offset = FixedOffset::east_opt(5 * 3600 + 30 * 60).unwrap();
+    assert_eq!(
+        client.timezone(),
+        BoxTimezone::Offset(offset).prefer(BoxTimezone::Host, Utc::now())
+    );
+    assert!(
+        (Utc::now() - logs[0].datetime.with_timezone(&Utc))
+            .num_seconds()
+            .abs()
+            < 5
+    );
+}
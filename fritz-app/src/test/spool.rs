@@ -0,0 +1,200 @@
+use std::path::PathBuf;
+
+use chrono::{TimeZone, Utc};
+
+use crate::db::{self, Database, Resolution, Spool};
+use crate::ping::rollup::rollup;
+
+/// A fresh directory for a test, removed by the test once it's done.
+pub(super) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fritz-app-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub(super) fn ping(second: u32) -> db::Ping {
+    db::Ping {
+        id: None,
+        datetime: Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, second).unwrap(),
+        target: "192.168.178.1".to_string(),
+        duration_ms: Some(3),
+        ttl: Some(64),
+        bytes: Some(56),
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn spool_and_replay() -> anyhow::Result<()> {
+    let dir = test_dir("spool-and-replay");
+    let url = format!("sqlite://{}", dir.join("logs.db3").display());
+    let logs = [
+        log!([1, 1, 1], 1, 1, repetition!()),
+        log!([1, 1, 2], 2, 1, repetition!()),
+    ];
+
+    // a closed pool fails like an unreachable database
+    let db = Database::open(&url)
+        .await?
+        .with_spool(Spool::open(dir.join("spool"))?);
+    db.clone().close().await;
+    db.insert_ping(&ping(0)).await?;
+    assert!(db.append_new_logs(&logs).await?.is_empty());
+
+    // writes are spooled until the spool has been replayed, even after a restart
+    let db = Database::open(&url)
+        .await?
+        .with_spool(Spool::open(dir.join("spool"))?);
+    db.insert_ping(&ping(1)).await?;
+    assert!(db
+        .select_pings(ping(0).datetime, ping(2).datetime)
+        .await?
+        .is_empty());
+
+    assert_eq!(db.replay_spool().await?, 3);
+    assert_eq!(db.replay_spool().await?, 0);
+    assert_eq!(
+        db.select_latest_logs(0, 10).await?,
+        [logs[1].clone(), logs[0].clone()]
+    );
+    let pings = db.select_pings(ping(0).datetime, ping(2).datetime).await?;
+    assert_eq!(
+        pings.iter().map(|ping| ping.datetime).collect::<Vec<_>>(),
+        [ping(0).datetime, ping(1).datetime]
+    );
+
+    // and written directly afterwards
+    db.insert_ping(&ping(2)).await?;
+    assert_eq!(std::fs::metadata(dir.join("spool/spool.jsonl"))?.len(), 0);
+
+    db.close().await;
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn torn_entry() -> anyhow::Result<()> {
+    let dir = test_dir("torn-entry");
+    let entry = r#"{"kind":"ping","id":null,"datetime":"2023-01-01T00:00:00Z","target":"1.1.1.1","duration_ms":null,"ttl":null,"bytes":null}"#;
+    std::fs::write(
+        dir.join("spool.jsonl"),
+        format!("{}\n{{\"kind\":\"pi", entry),
+    )?;
+
+    // an entry that has only been written partially is dropped
+    let db = Database::open_in_memory().with_spool(Spool::open(&dir)?);
+    assert_eq!(db.replay_spool().await?, 1);
+    let pings = db.select_pings(ping(0).datetime, ping(1).datetime).await?;
+    assert_eq!(pings.len(), 1);
+    assert_eq!(pings[0].target, "1.1.1.1");
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn request_of_older_version() -> anyhow::Result<()> {
+    let dir = test_dir("request-of-older-version");
+    let entry = r#"{"kind":"request","id":null,"datetime":"2023-01-01T00:00:00Z","name":"logs","url":"https://192.168.178.1/data.lua","method":"POST","duration_ms":120,"response_code":200,"session_id":null}"#;
+    std::fs::write(dir.join("spool.jsonl"), format!("{}\n", entry))?;
+
+    // requests spooled before the error columns existed are still replayed
+    let db = Database::open_in_memory().with_spool(Spool::open(&dir)?);
+    assert_eq!(db.replay_spool().await?, 1);
+    let requests = db.query_requests(&db::RequestQuery::new()).await?;
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        (requests[0].attempt, requests[0].error_kind.as_ref()),
+        (1, None)
+    );
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn replay_onto_newer_tail() -> anyhow::Result<()> {
+    use futures_util::{FutureExt, StreamExt};
+
+    let dir = test_dir("replay-onto-newer-tail");
+    let url = format!("sqlite://{}", dir.join("logs.db3").display());
+    let logs = [
+        log!([1, 1, 1], 1, 1, repetition!()),
+        log!([1, 1, 2], 2, 1, repetition!()),
+        log!([1, 1, 3], 3, 1, repetition!()),
+    ];
+
+    // this instance lost its connection and spooled a snapshot
+    let db = Database::open(&url)
+        .await?
+        .with_spool(Spool::open(dir.join("spool"))?);
+    db.append_new_logs(&logs[..1]).await?;
+    db.clone().close().await;
+    assert!(db.append_new_logs(&logs[..2]).await?.is_empty());
+
+    // meanwhile another instance took over and archived newer logs
+    let leader = Database::open(&url).await?;
+    leader.append_new_logs(&logs).await?;
+    leader.close().await;
+
+    // the outdated snapshot neither duplicates logs nor records a gap
+    let db = Database::open(&url)
+        .await?
+        .with_spool(Spool::open(dir.join("spool"))?);
+    let mut events = db.subscribe().await?;
+    assert_eq!(db.replay_spool().await?, 1);
+    assert!(events.next().now_or_never().is_none());
+    assert_eq!(
+        db.select_latest_logs(0, 10).await?,
+        [logs[2].clone(), logs[1].clone(), logs[0].clone()]
+    );
+
+    db.close().await;
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn roll_up_late_pings() -> anyhow::Result<()> {
+    let dir = test_dir("roll-up-late-pings");
+    let url = format!("sqlite://{}", dir.join("logs.db3").display());
+    let at_minute = |minute: u32| db::Ping {
+        datetime: Utc.with_ymd_and_hms(2023, 1, 1, 0, minute, 30).unwrap(),
+        ..ping(0)
+    };
+    let now = Utc.with_ymd_and_hms(2023, 1, 1, 0, 10, 0).unwrap();
+    let counts = |db: Database| async move {
+        let rollups = db.select_ping_rollups(Resolution::Minute, None, ..).await?;
+        anyhow::Ok(
+            rollups
+                .iter()
+                .map(|rollup| rollup.count)
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    let db = Database::open(&url)
+        .await?
+        .with_spool(Spool::open(dir.join("spool"))?);
+    db.insert_ping(&ping(0)).await?;
+    db.insert_ping(&at_minute(2)).await?;
+    assert_eq!(rollup(&db, Resolution::Minute, now).await?, 2);
+
+    // a ping of a bucket that has already been rolled up is spooled
+    db.clone().close().await;
+    db.insert_ping(&ping(1)).await?;
+
+    // and rolled up again once it has been replayed
+    let db = Database::open(&url)
+        .await?
+        .with_spool(Spool::open(dir.join("spool"))?);
+    assert_eq!(counts(db.clone()).await?, [1, 1]);
+    assert_eq!(db.replay_spool().await?, 1);
+    assert_eq!(rollup(&db, Resolution::Minute, now).await?, 2);
+    assert_eq!(counts(db.clone()).await?, [2, 1]);
+    assert_eq!(rollup(&db, Resolution::Minute, now).await?, 0);
+
+    db.close().await;
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use chrono::{FixedOffset, TimeZone, Utc};
+
+use crate::api;
+use crate::fritz::{BoxTimezone, Log, TimeResolution};
+
+fn log(date: &str, time: &str) -> api::Log {
+    message_log(date, time, "Anmeldung")
+}
+
+fn message_log(date: &str, time: &str, message: &str) -> api::Log {
+    api::Log([
+        date.to_string(),
+        time.to_string(),
+        message.to_string(),
+        "344".to_string(),
+        "1".to_string(),
+        String::new(),
+    ])
+}
+
+fn offset(minutes: i32) -> BoxTimezone {
+    BoxTimezone::Offset(FixedOffset::east_opt(minutes * 60).unwrap())
+}
+
+#[test]
+fn detect() {
+    let requested = Utc.with_ymd_and_hms(2023, 7, 1, 10, 0, 0).unwrap();
+
+    let detect = |date, time| BoxTimezone::detect(&log(date, time), requested, "").unwrap();
+    assert_eq!(detect("01.07.23", "12:00:05"), Some(offset(120)));
+    assert_eq!(detect("01.07.23", "15:31:00"), Some(offset(330)));
+    assert_eq!(detect("01.07.23", "04:59:59"), Some(offset(-300)));
+    assert_eq!(detect("30.06.23", "22:01:00"), Some(offset(-720)));
+    assert_eq!(detect("01.07.23", "10:00:00"), Some(offset(0)));
+}
+
+#[test]
+fn detect_unrelated_log() {
+    let requested = Utc.with_ymd_and_hms(2023, 7, 1, 10, 0, 0).unwrap();
+
+    // logged long before the request
+    let detected = BoxTimezone::detect(&log("01.07.23", "12:07:00"), requested, "").unwrap();
+    assert_eq!(detected, None);
+}
+
+#[test]
+fn detect_login_only() {
+    let requested = Utc.with_ymd_and_hms(2023, 7, 1, 10, 0, 0).unwrap();
+    let other = |message: &str| {
+        let mut log = message_log("01.07.23", "12:00:05", message);
+        log.0[3] = "502".to_string();
+        log
+    };
+
+    // another log at the same time isn't the login
+    let detected = BoxTimezone::detect(&other("WLAN-Gerät angemeldet"), requested, "fritz3713");
+    assert_eq!(detected.unwrap(), None);
+    let detected = BoxTimezone::detect(&other("WLAN-Gerät angemeldet"), requested, "");
+    assert_eq!(detected.unwrap(), None);
+
+    // a login is also recognized by the username
+    let detected = BoxTimezone::detect(
+        &other("Anmeldung des Benutzers fritz3713 an der FRITZ!Box-Benutzeroberfläche."),
+        requested,
+        "fritz3713",
+    );
+    assert_eq!(detected.unwrap(), Some(offset(120)));
+}
+
+#[test]
+fn prefer() {
+    let berlin = BoxTimezone::Named(chrono_tz::Europe::Berlin);
+    let summer = Utc.with_ymd_and_hms(2023, 7, 1, 10, 0, 0).unwrap();
+    let winter = Utc.with_ymd_and_hms(2023, 12, 1, 10, 0, 0).unwrap();
+
+    assert_eq!(offset(120).prefer(berlin, summer), berlin);
+    assert_eq!(offset(60).prefer(berlin, winter), berlin);
+    assert_eq!(offset(60).prefer(berlin, summer), offset(60));
+    assert_eq!(offset(330).prefer(berlin, winter), offset(330));
+}
+
+#[test]
+fn detected_across_daylight_saving_time() {
+    let berlin = BoxTimezone::Named(chrono_tz::Europe::Berlin);
+    // detected in summer, logged after the clock has been turned back
+    let login = Utc.with_ymd_and_hms(2023, 10, 28, 10, 0, 0).unwrap();
+    let timezone = BoxTimezone::detect(&log("28.10.23", "12:00:00"), login, "")
+        .unwrap()
+        .unwrap()
+        .prefer(berlin, login);
+    let datetime = chrono::NaiveDate::from_ymd_opt(2023, 10, 30)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+
+    assert_eq!(
+        timezone.from_local_datetime(&datetime).single().unwrap(),
+        Utc.with_ymd_and_hms(2023, 10, 30, 11, 0, 0).unwrap()
+    );
+}
+
+#[test]
+fn parse() {
+    assert_eq!(
+        "Europe/Berlin".parse::<BoxTimezone>().unwrap(),
+        BoxTimezone::Named(chrono_tz::Europe::Berlin)
+    );
+    assert!("Europe/Nowhere".parse::<BoxTimezone>().is_err());
+}
+
+#[test]
+fn named() {
+    let timezone = BoxTimezone::Named(chrono_tz::Europe::Berlin);
+    let datetime = chrono::NaiveDate::from_ymd_opt(2023, 7, 1)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+
+    assert_eq!(
+        timezone.from_local_datetime(&datetime).single().unwrap(),
+        Utc.with_ymd_and_hms(2023, 7, 1, 10, 0, 0).unwrap()
+    );
+}
+
+/// Resolve a snapshot of the FRITZ!Box in `Europe/Berlin`, newest log first
+fn resolve(logs: &[(&str, &str, &str)]) -> Vec<Log> {
+    resolve_after(logs, None)
+}
+
+/// Resolve a snapshot after the newest archived log `tail`
+fn resolve_after(logs: &[(&str, &str, &str)], tail: Option<&Log>) -> Vec<Log> {
+    let logs = logs
+        .iter()
+        .map(|(date, time, message)| message_log(date, time, message))
+        .collect();
+    Log::from_api(logs, &BoxTimezone::Named(chrono_tz::Europe::Berlin), tail).unwrap()
+}
+
+#[test]
+fn ambiguous() {
+    // the clock is turned back from 03:00 to 02:00 at 01:00 UTC
+    let logs = resolve(&[
+        ("29.10.23", "02:10:00", "Anmeldung"),
+        (
+            "29.10.23",
+            "02:05:00",
+            "Anmeldung [2 Meldungen seit 29.10.23 02:40:00]",
+        ),
+        ("29.10.23", "02:30:00", "Anmeldung"),
+        ("29.10.23", "01:59:00", "Anmeldung"),
+    ]);
+
+    let resolved = logs
+        .iter()
+        .map(|log| (log.datetime.with_timezone(&Utc), log.datetime_resolution))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        resolved,
+        [
+            (
+                Utc.with_ymd_and_hms(2023, 10, 29, 1, 10, 0).unwrap(),
+                Some(TimeResolution::Second)
+            ),
+            (
+                Utc.with_ymd_and_hms(2023, 10, 29, 1, 5, 0).unwrap(),
+                Some(TimeResolution::Second)
+            ),
+            (
+                Utc.with_ymd_and_hms(2023, 10, 29, 0, 30, 0).unwrap(),
+                Some(TimeResolution::First)
+            ),
+            (Utc.with_ymd_and_hms(2023, 10, 28, 23, 59, 0).unwrap(), None),
+        ]
+    );
+
+    let repetition = logs[1].repetition.as_ref().unwrap();
+    assert_eq!(
+        repetition.datetime,
+        Utc.with_ymd_and_hms(2023, 10, 29, 0, 40, 0).unwrap()
+    );
+    assert_eq!(repetition.datetime_resolution, Some(TimeResolution::First));
+}
+
+#[test]
+fn skipped() {
+    // the clock is turned forward from 02:00 to 03:00 at 01:00 UTC
+    let logs = resolve(&[
+        ("26.03.23", "03:10:00", "Anmeldung"),
+        ("26.03.23", "02:30:00", "Anmeldung"),
+    ]);
+
+    assert_eq!(
+        logs[1].datetime,
+        Utc.with_ymd_and_hms(2023, 3, 26, 1, 30, 0).unwrap()
+    );
+    assert_eq!(logs[1].datetime_resolution, Some(TimeResolution::Shifted));
+    assert_eq!(logs[0].datetime_resolution, None);
+}
+
+#[test]
+fn wrapped_after_clock_turned_back() {
+    // archived before the clock was turned back at 01:00 UTC
+    let tail = resolve(&[("29.10.23", "02:50:00", "Anmeldung")]).remove(0);
+    assert_eq!(tail.datetime_resolution, Some(TimeResolution::First));
+
+    // the buffer has wrapped, only logs after the clock was turned back are left
+    let logs = resolve_after(
+        &[
+            ("29.10.23", "02:20:00", "Anmeldung"),
+            ("29.10.23", "02:10:00", "Anmeldung"),
+        ],
+        Some(&tail),
+    );
+    let resolved = logs
+        .iter()
+        .map(|log| (log.datetime.with_timezone(&Utc), log.datetime_resolution))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        resolved,
+        [
+            (
+                Utc.with_ymd_and_hms(2023, 10, 29, 1, 20, 0).unwrap(),
+                Some(TimeResolution::Second)
+            ),
+            (
+                Utc.with_ymd_and_hms(2023, 10, 29, 1, 10, 0).unwrap(),
+                Some(TimeResolution::Second)
+            ),
+        ]
+    );
+}
+
+#[test]
+fn overlapping_tail() {
+    let tail = resolve(&[
+        ("29.10.23", "02:50:00", "Anmeldung"),
+        ("29.10.23", "02:40:00", "Anmeldung"),
+    ])
+    .remove(0);
+
+    // logs up to the tail are resolved as before, the ones after it follow it
+    let logs = resolve_after(
+        &[
+            ("29.10.23", "02:10:00", "Anmeldung"),
+            ("29.10.23", "02:50:00", "Anmeldung"),
+            ("29.10.23", "02:40:00", "Anmeldung"),
+        ],
+        Some(&tail),
+    );
+    let resolutions = logs
+        .iter()
+        .map(|log| log.datetime_resolution)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        resolutions,
+        [
+            Some(TimeResolution::Second),
+            Some(TimeResolution::First),
+            Some(TimeResolution::First),
+        ]
+    );
+    assert!(logs[1].is_same_entry(&tail));
+}
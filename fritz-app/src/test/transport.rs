@@ -0,0 +1,68 @@
+use crate::api::transport::{RecordTransport, ReplayTransport};
+use crate::api::Client;
+
+const CASSETTE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/src/test/cassettes/session.jsonl"
+);
+
+fn new_client() -> Client {
+    Client::new(
+        Some("fritz.box"),
+        Some("fritz3713"),
+        Some("password"),
+        None,
+        None,
+    )
+    .unwrap()
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn replay_session() {
+    let client = new_client().with_transport(ReplayTransport::open(CASSETTE).unwrap());
+
+    // logs in before fetching the logs
+    let logs = client.logs(None).await.unwrap();
+    assert_eq!(logs.len(), 3);
+    assert_eq!(logs[0].message_id, 344);
+    assert_eq!(logs[1].repetition.as_ref().map(|r| r.count), Some(3));
+
+    let cleared = client.clear_logs().await.unwrap();
+    assert_eq!(cleared["data"]["log"], serde_json::json!([]));
+
+    let certificate = client.certificate().await.unwrap();
+    assert!(certificate.starts_with("-----BEGIN CERTIFICATE-----"));
+
+    // the cassette is exhausted
+    let err = client.logout().await.unwrap_err();
+    assert!(format!("{:#}", err).contains("no interaction left"));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn replay_unexpected_request() {
+    let client = new_client().with_transport(ReplayTransport::open(CASSETTE).unwrap());
+
+    // the cassette expects the logs to be fetched first
+    let err = client.clear_logs().await.unwrap_err();
+    assert!(format!("{:#}", err).contains("unexpected clear-logs"));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn record_replayed_session() {
+    let path =
+        std::env::temp_dir().join(format!("fritz-app-cassette-{}.jsonl", std::process::id()));
+
+    let replay = ReplayTransport::open(CASSETTE).unwrap();
+    let client = new_client().with_transport(RecordTransport::create(replay, &path).unwrap());
+    client.logs(None).await.unwrap();
+    client.clear_logs().await.unwrap();
+    client.certificate().await.unwrap();
+
+    // the recorded cassette can be replayed itself
+    let client = new_client().with_transport(ReplayTransport::open(&path).unwrap());
+    client.logs(None).await.unwrap();
+    client.clear_logs().await.unwrap();
+    client.certificate().await.unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+}